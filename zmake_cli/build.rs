@@ -100,12 +100,33 @@ async fn download_deno() -> eyre::Result<()> {
     Ok(())
 }
 
+/// The runtime is evaluated once here, every engine starts from the snapshot.
+///
+/// The snapshot is created by the V8 of the host, it is left empty when cross compiling.
+fn create_snapshot() -> eyre::Result<()> {
+    let path = std::path::PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR not set"))
+        .join("zmake.snapshot");
+
+    if env::var("HOST")? != env::var("TARGET")? {
+        std::fs::write(path, [])?;
+        return Ok(());
+    }
+
+    let snapshot = zmake_lib::snapshot::create_snapshot()?;
+
+    std::fs::write(path, snapshot)?;
+
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() {
     color_eyre::install().unwrap_or_else(|_| println!("failed to install color-eyre"));
 
     download_deno().await.unwrap();
 
+    create_snapshot().unwrap();
+
     ShadowBuilder::builder()
         .build_pattern(BuildPattern::RealTime)
         .build()
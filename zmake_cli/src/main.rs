@@ -19,8 +19,11 @@ use tracing::{Level, info, trace_span};
 use tracing_subscriber::Registry;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_tree::HierarchicalLayer;
-use zmake_lib::engine::{Engine, EngineMode, EngineOptions};
-use zmake_lib::project_resolver::ProjectResolver;
+use zmake_lib::audit::AuditPolicy;
+use zmake_lib::determinism::DeterminismOptions;
+use zmake_lib::engine::{EngineMode, EngineOptions};
+use zmake_lib::engine_pool::{EnginePool, EnginePoolOptions};
+use zmake_lib::project_resolver::{ProjectResolver, ProjectResolverOptions};
 use zmake_lib::sandbox::Sandbox;
 
 const STYLES: styling::Styles = styling::Styles::styled()
@@ -126,6 +129,8 @@ struct SafeDenoArgs {}
 
 static DENO_BINARY: &[u8] = include_bytes!(concat!(std::env!("OUT_DIR"), "/deno"));
 static DENO_CHECKSUM: &'static str = include_str!(concat!(std::env!("OUT_DIR"), "/deno.sha256sum"));
+/// It is empty when cross compiled, the engines then evaluate the runtime by themselves.
+static SNAPSHOT: &[u8] = include_bytes!(concat!(std::env!("OUT_DIR"), "/zmake.snapshot"));
 
 fn run_deno(args: &[std::ffi::OsString]) -> eyre::Result<()> {
     let _span = trace_span!("run deno", args = format!("{:?}", args)).entered();
@@ -171,11 +176,38 @@ fn run_deno(args: &[std::ffi::OsString]) -> eyre::Result<()> {
 struct ExportBuiltinArgs {
     #[arg(long, value_hint = clap::ValueHint::FilePath)]
     output_file: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Export the declaration of builtin modules available in the engine mode instead"
+    )]
+    builtin_declaration: Option<Mode>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Mode {
+    Project,
+    Rule,
+    Script,
+}
+
+impl From<Mode> for EngineMode {
+    fn from(value: Mode) -> Self {
+        match value {
+            Mode::Project => EngineMode::Project,
+            Mode::Rule => EngineMode::Rule,
+            Mode::Script => EngineMode::Script,
+        }
+    }
 }
 
 impl ExportBuiltinArgs {
     pub fn invoke(self) -> eyre::Result<()> {
-        let builtins = zmake_lib::builtin::id::construct_builtins_typescript_export();
+        let builtins = match self.builtin_declaration {
+            Some(mode) => zmake_lib::builtin::typescript_declaration(mode.into()),
+            None => zmake_lib::builtin::id::construct_builtins_typescript_export(),
+        };
 
         if let Some(output_file) = self.output_file {
             let mut output_file = File::create(output_file)?;
@@ -196,6 +228,78 @@ struct MakeArgs {
 
     #[arg(long, help = "Set the cpu counts that zmake use")]
     concurrency: Option<usize>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = UndeclaredRead::Warn,
+        help = "What to do when a build script reads a file that was not declared as input"
+    )]
+    undeclared_read: UndeclaredRead,
+
+    #[arg(
+        long,
+        value_hint = clap::ValueHint::DirPath,
+        help = "Where to cache the transpiled and compiled build scripts, defaults to `.zmake/cache` in the project directory"
+    )]
+    cache_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Do not cache the transpiled and compiled build scripts across runs"
+    )]
+    no_script_cache: bool,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "The seed of `Math.random` in build scripts"
+    )]
+    random_seed: u64,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "The time that `Date.now()` returns in build scripts, in milliseconds since the unix epoch"
+    )]
+    clock: u64,
+
+    #[arg(
+        long,
+        help = "Fail when a build script uses a non-deterministic API, like `Math.random`, instead of warning"
+    )]
+    deny_non_deterministic: bool,
+
+    #[arg(
+        long,
+        value_name = "MIB",
+        help = "The max heap size of a build script engine in MiB, defaults to the limit of V8"
+    )]
+    max_heap_size: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "Terminate a build script that runs longer than the time, defaults to no limit"
+    )]
+    script_timeout: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum UndeclaredRead {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl From<UndeclaredRead> for AuditPolicy {
+    fn from(value: UndeclaredRead) -> Self {
+        match value {
+            UndeclaredRead::Allow => AuditPolicy::Allow,
+            UndeclaredRead::Warn => AuditPolicy::Warn,
+            UndeclaredRead::Deny => AuditPolicy::Deny,
+        }
+    }
 }
 
 impl MakeArgs {
@@ -215,17 +319,52 @@ impl MakeArgs {
 
         info!("use concurrency {}", concurrency);
 
+        let cache_directory = if self.no_script_cache {
+            None
+        } else {
+            Some(
+                self.cache_dir
+                    .unwrap_or_else(|| project_dir.join(".zmake").join("cache")),
+            )
+        };
+
+        let max_heap_size = self
+            .max_heap_size
+            .map(|x| {
+                x.checked_mul(1024 * 1024)
+                    .ok_or(eyre::eyre!("the max heap size {} MiB is too large", x))
+            })
+            .transpose()?;
+
         let sandbox = std::sync::Arc::from(Sandbox::new(project_dir)?);
 
-        let engine = Engine::new(
+        let pool = EnginePool::new(
             sandbox,
-            EngineOptions {
-                tokio_handle: runtime.handle().clone(),
-                mode: EngineMode::Project,
+            EnginePoolOptions {
+                size: concurrency,
+                engine: EngineOptions {
+                    tokio_handle: runtime.handle().clone(),
+                    mode: EngineMode::Project,
+                    cache_directory,
+                    snapshot: (!SNAPSHOT.is_empty()).then_some(SNAPSHOT),
+                    determinism: Some(DeterminismOptions {
+                        random_seed: self.random_seed,
+                        clock: self.clock as f64,
+                    }),
+                    max_heap_size,
+                    execution_timeout: self.script_timeout.map(std::time::Duration::from_secs),
+                },
             },
         )?;
 
-        let resolver = ProjectResolver::new(engine);
+        let resolver = ProjectResolver::new(
+            pool,
+            ProjectResolverOptions {
+                audit_policy: self.undeclared_read.into(),
+                deny_non_deterministic: self.deny_non_deterministic,
+                ..Default::default()
+            },
+        );
 
         resolver.resolve_project(project_file.to_string_lossy().to_string())?;
 
@@ -479,5 +618,13 @@ fn inner_main() -> eyre::Result<()> {
 }
 
 pub fn main() {
-    ::std::process::exit(inner_main().map(|_x| exit_code::SUCCESS).unwrap());
+    let code = match inner_main() {
+        Ok(()) => exit_code::SUCCESS,
+        Err(report) => {
+            eprintln!("{:?}", report);
+            exit_code::FAILURE
+        }
+    };
+
+    ::std::process::exit(code);
 }
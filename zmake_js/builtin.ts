@@ -0,0 +1,191 @@
+/**
+ * The builtin ids of zmake.
+ *
+ * Generated by `zmake export-builtin`, do not edit.
+ */
+import type * as core from "zmake:core";
+
+/**
+ * The builtin ids with type `target_type`.
+ */
+export const targetType = {
+    /** `moe.kawayi:zmake@1.0.0#target_type::build` */
+    build: "moe.kawayi:zmake@1.0.0#target_type::build",
+    /** `moe.kawayi:zmake@1.0.0#target_type::clean` */
+    clean: "moe.kawayi:zmake@1.0.0#target_type::clean",
+    /** `moe.kawayi:zmake@1.0.0#target_type::deploy` */
+    deploy: "moe.kawayi:zmake@1.0.0#target_type::deploy",
+    /** `moe.kawayi:zmake@1.0.0#target_type::initialize` */
+    initialize: "moe.kawayi:zmake@1.0.0#target_type::initialize",
+    /** `moe.kawayi:zmake@1.0.0#target_type::install` */
+    install: "moe.kawayi:zmake@1.0.0#target_type::install",
+    /** `moe.kawayi:zmake@1.0.0#target_type::package` */
+    package: "moe.kawayi:zmake@1.0.0#target_type::package",
+    /** `moe.kawayi:zmake@1.0.0#target_type::test` */
+    test: "moe.kawayi:zmake@1.0.0#target_type::test",
+} as const satisfies Record<string, core.TargetType>;
+
+/**
+ * The builtin ids with type `target_type`.
+ */
+export type TargetType = (typeof targetType)[keyof typeof targetType];
+
+/**
+ * The builtin ids with type `architecture`.
+ */
+export const architecture = {
+    /** `moe.kawayi:zmake@1.0.0#architecture::arm64` */
+    arm64: "moe.kawayi:zmake@1.0.0#architecture::arm64",
+    /** `moe.kawayi:zmake@1.0.0#architecture::x64` */
+    x64: "moe.kawayi:zmake@1.0.0#architecture::x64",
+} as const satisfies Record<string, core.Architecture>;
+
+/**
+ * The builtin ids with type `architecture`.
+ */
+export type Architecture = (typeof architecture)[keyof typeof architecture];
+
+/**
+ * The builtin ids with type `os`.
+ */
+export const os = {
+    /** `moe.kawayi:zmake@1.0.0#os::linux` */
+    linux: "moe.kawayi:zmake@1.0.0#os::linux",
+    /** `moe.kawayi:zmake@1.0.0#os::macos` */
+    macos: "moe.kawayi:zmake@1.0.0#os::macos",
+    /** `moe.kawayi:zmake@1.0.0#os::windows` */
+    windows: "moe.kawayi:zmake@1.0.0#os::windows",
+} as const satisfies Record<string, core.Os>;
+
+/**
+ * The builtin ids with type `os`.
+ */
+export type Os = (typeof os)[keyof typeof os];
+
+/**
+ * The builtin ids with type `tool`.
+ */
+export const tool = {
+    /** `moe.kawayi:zmake@1.0.0#tool::bad_tar` */
+    bsdTar: "moe.kawayi:zmake@1.0.0#tool::bad_tar",
+    /** `moe.kawayi:zmake@1.0.0#tool::curl` */
+    curl: "moe.kawayi:zmake@1.0.0#tool::curl",
+    /** `moe.kawayi:zmake@1.0.0#tool::git` */
+    git: "moe.kawayi:zmake@1.0.0#tool::git",
+    /** `moe.kawayi:zmake@1.0.0#tool::ld` */
+    ld: "moe.kawayi:zmake@1.0.0#tool::ld",
+    /** `moe.kawayi:zmake@1.0.0#tool::link_exe` */
+    linkExe: "moe.kawayi:zmake@1.0.0#tool::link_exe",
+} as const satisfies Record<string, core.Tool>;
+
+/**
+ * The builtin ids with type `tool`.
+ */
+export type Tool = (typeof tool)[keyof typeof tool];
+
+/**
+ * The builtin ids with type `tool_type`.
+ */
+export const toolType = {
+    /** `moe.kawayi:zmake@1.0.0#tool_type::archiver` */
+    archiver: "moe.kawayi:zmake@1.0.0#tool_type::archiver",
+    /** `moe.kawayi:zmake@1.0.0#tool_type::downloader` */
+    downloader: "moe.kawayi:zmake@1.0.0#tool_type::downloader",
+    /** `moe.kawayi:zmake@1.0.0#tool_type::linker` */
+    linker: "moe.kawayi:zmake@1.0.0#tool_type::linker",
+} as const satisfies Record<string, core.ToolType>;
+
+/**
+ * The builtin ids with type `tool_type`.
+ */
+export type ToolType = (typeof toolType)[keyof typeof toolType];
+
+/**
+ * The builtin ids of `c`.
+ */
+export const c = {
+    /**
+     * The builtin ids with type `tool`.
+     */
+    tool: {
+        /** `moe.kawayi:zmake@1.0.0#tool::c/clang` */
+        clang: "moe.kawayi:zmake@1.0.0#tool::c/clang",
+        /** `moe.kawayi:zmake@1.0.0#tool::c/gcc` */
+        gcc: "moe.kawayi:zmake@1.0.0#tool::c/gcc",
+        /** `moe.kawayi:zmake@1.0.0#tool::c/msvc` */
+        msvc: "moe.kawayi:zmake@1.0.0#tool::c/msvc",
+    },
+    /**
+     * The builtin ids with type `tool_type`.
+     */
+    toolType: {
+        /** `moe.kawayi:zmake@1.0.0#tool_type::c/compiler` */
+        compiler: "moe.kawayi:zmake@1.0.0#tool_type::c/compiler",
+        /** `moe.kawayi:zmake@1.0.0#tool_type::c/preprocessor` */
+        preprocessor: "moe.kawayi:zmake@1.0.0#tool_type::c/preprocessor",
+    },
+} as const satisfies {
+    tool: Record<string, core.Tool>;
+    toolType: Record<string, core.ToolType>;
+};
+
+export namespace c {
+    /**
+     * The builtin ids of `c` with type `tool`.
+     */
+    export type Tool = (typeof c.tool)[keyof typeof c.tool];
+    /**
+     * The builtin ids of `c` with type `tool_type`.
+     */
+    export type ToolType = (typeof c.toolType)[keyof typeof c.toolType];
+}
+
+/**
+ * The builtin ids of `cpp`.
+ */
+export const cpp = {
+    /**
+     * The builtin ids with type `tool`.
+     */
+    tool: {
+        /** `moe.kawayi:zmake@1.0.0#tool::cpp/clang` */
+        clang: "moe.kawayi:zmake@1.0.0#tool::cpp/clang",
+        /** `moe.kawayi:zmake@1.0.0#tool::cpp/gcc` */
+        gcc: "moe.kawayi:zmake@1.0.0#tool::cpp/gcc",
+        /** `moe.kawayi:zmake@1.0.0#tool::cpp/msvc` */
+        msvc: "moe.kawayi:zmake@1.0.0#tool::cpp/msvc",
+    },
+    /**
+     * The builtin ids with type `tool_type`.
+     */
+    toolType: {
+        /** `moe.kawayi:zmake@1.0.0#tool_type::cpp/compiler` */
+        compiler: "moe.kawayi:zmake@1.0.0#tool_type::cpp/compiler",
+        /** `moe.kawayi:zmake@1.0.0#tool_type::cpp/preprocessor` */
+        preprocessor: "moe.kawayi:zmake@1.0.0#tool_type::cpp/preprocessor",
+    },
+} as const satisfies {
+    tool: Record<string, core.Tool>;
+    toolType: Record<string, core.ToolType>;
+};
+
+export namespace cpp {
+    /**
+     * The builtin ids of `cpp` with type `tool`.
+     */
+    export type Tool = (typeof cpp.tool)[keyof typeof cpp.tool];
+    /**
+     * The builtin ids of `cpp` with type `tool_type`.
+     */
+    export type ToolType = (typeof cpp.toolType)[keyof typeof cpp.toolType];
+}
+
+export default {
+    ...targetType,
+    ...architecture,
+    ...os,
+    ...tool,
+    ...toolType,
+    c: { ...c.tool, ...c.toolType },
+    cpp: { ...cpp.tool, ...cpp.toolType },
+};
@@ -0,0 +1,46 @@
+declare module "zmake:syscall" {
+    export function digest(path: string): Promise<string>;
+    export function log(level: "trace" | "debug" | "info" | "warn" | "error", message: string): void;
+    export const version: string;
+}
+
+declare module "zmake:fs" {
+    export function readText(path: string): Promise<string>;
+    export function readBytes(path: string): Promise<Uint8Array>;
+    export function stat(path: string): Promise<{ kind: "file" | "directory" | "symlink" | "other"; size: number; readonly: boolean }>;
+    export function readDir(path: string): Promise<({ name: string; kind: "file" | "directory" | "symlink" | "other" })[]>;
+    export function glob(pattern: string): Promise<string[]>;
+}
+
+declare module "zmake:console" {
+    export function trace(...data: any[]): void;
+    export function debug(...data: any[]): void;
+    export function info(...data: any[]): void;
+    export function log(...data: any[]): void;
+    export function warn(...data: any[]): void;
+    export function error(...data: any[]): void;
+    export function dir(item?: any): void;
+    export function assert(condition?: boolean, ...data: any[]): void;
+    export function count(label?: string): void;
+    export function countReset(label?: string): void;
+    export function group(...label: any[]): void;
+    export function groupCollapsed(...label: any[]): void;
+    export function groupEnd(): void;
+    export function time(label?: string): void;
+    export function timeLog(label?: string, ...data: any[]): void;
+    export function timeEnd(label?: string): void;
+    const console: Console;
+    export default console;
+}
+
+declare module "zmake:semver" {
+    export function parse(version: string): SemVer | null;
+    export function parseLenient(version: string): SemVer | null;
+    export function compare(a: string, b: string): -1 | 0 | 1;
+    export function satisfies(version: string, range: string): boolean;
+    export function maxSatisfying(versions: string[], range: string): string | null;
+}
+
+declare module "zmake:core" {
+    export function requireZMakeVersion(range: string, packageName?: string | null): void;
+}
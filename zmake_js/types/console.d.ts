@@ -1,19 +1,32 @@
-declare module "node:console" {
+declare module "zmake:console" {
+    /**
+     * The console of scripts, the messages are written to the log of zmake.
+     *
+     * Arguments are formatted like `util.inspect` of Node, a leading string may contain
+     * `printf`-like substitutions(`%s %d %i %f %j %o %O %c %%`).
+     */
     export interface Console {
-        trace(...args: any): void;
-        debug(...args: any): void;
-        log(...args: any): void;
-        warn(...args: any): void;
-        error(...args: any): void;
+        trace(...data: any[]): void;
+        debug(...data: any[]): void;
+        info(...data: any[]): void;
+        log(...data: any[]): void;
+        warn(...data: any[]): void;
+        error(...data: any[]): void;
+        dir(item?: any): void;
+        assert(condition?: boolean, ...data: any[]): void;
+        count(label?: string): void;
+        countReset(label?: string): void;
+        group(...label: any[]): void;
+        groupCollapsed(...label: any[]): void;
+        groupEnd(): void;
+        /** The elapsed time is only written to the log. */
+        time(label?: string): void;
+        timeLog(label?: string, ...data: any[]): void;
+        timeEnd(label?: string): void;
     }
 }
 
-declare module "zmake:console" {
-    export interface Console {
-        trace(message: string): void;
-        debug(message: string): void;
-        log(message: string): void;
-        warn(message: string): void;
-        error(message: string): void;
-    }
+declare module "node:console" {
+    export * from "zmake:console";
+    export { default } from "zmake:console";
 }
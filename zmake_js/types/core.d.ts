@@ -49,11 +49,19 @@ declare module "zmake:core" {
     export type ToolType = Id<"tool_type">;
 
     /**
-     * zmake Id with type `tool_name`
+     * zmake Id with type `tool`
      */
-    export type ToolName = Id<"tool_name">;
+    export type Tool = Id<"tool">;
 
-    export function requireZMakeVersion(version: Version): void;
+    /**
+     * zmake Id with type `tool_provider`
+     */
+    export type ToolProvider = Id<"tool_provider">;
+
+    /**
+     * zmake Id with type `property`
+     */
+    export type Property = Id<"property">;
 
     export type visibility = "public" | "private" | string[];
 
@@ -71,6 +79,12 @@ declare module "zmake:core" {
         group: GroupId;
         artifact: string;
         version: Version;
+        /**
+         * The range of zmake version required by the project, like `^0.1`.
+         *
+         * It is checked when resolving the project, see `requireZMakeVersion`.
+         */
+        zmake?: string;
         description?: string;
         license?: string;
         authors?: Author[];
@@ -1,4 +1,18 @@
-
-declare module "zmake:semver"{
-
+declare module "zmake:semver" {
+    /**
+     * A semantic version, see https://semver.org.
+     *
+     * Ranges use the syntax of Cargo, like `^1.2`, `~1.2.3` or `>=1.0, <2.0`.
+     */
+    export interface SemVer {
+        major: number;
+        minor: number;
+        patch: number;
+        /** The pre-release identifiers without `-`, empty if there are none. */
+        prerelease: string;
+        /** The build metadata without `+`, empty if there is none. */
+        build: string;
+        /** The normalized version string. */
+        version: string;
+    }
 }
@@ -0,0 +1,962 @@
+//! Run actions inside linux user, mount, PID and network namespaces.
+//!
+//! The layout of the sandbox:
+//!
+//! - `/zmake/exec`: the exec root, the materialized inputs, read-only
+//! - `/zmake/exec/<output_directory>`: the only writable directory
+//! - `/proc`, `/tmp`(private tmpfs), `/dev/null` and `/dev/zero`
+//! - the declared readonly host paths
+//!
+//! Undeclared files do not exist in the sandbox and the network namespace has no interface,
+//! so undeclared reads and network access fail inside the action.
+//! Undeclared writes to the sandbox root are detected and reported after the action exits.
+//!
+//! The inputs opened by the action are recorded by inotify, which watches inodes,
+//! so opening through the bind mount in the sandbox is reported too. A read of anything
+//! in the exec root that is not a declared input is an error.
+
+use crate::action::{Action, ActionError, ActionResult, ActionRunner, materialize};
+use crate::audit::{AccessKind, FileAccess};
+use crate::cas::Cas;
+use crate::path::NeutralPath;
+use ahash::AHashMap;
+use async_trait::async_trait;
+use std::collections::BTreeSet;
+use std::ffi::{CString, c_char};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{trace, warn};
+
+static EXEC_ROOT: &'static str = "zmake/exec";
+
+static OLD_ROOT: &'static str = ".old_root";
+
+static DEVICES: &[&'static str] = &["/dev/null", "/dev/zero"];
+
+/// The steps of setup in the child process, the index is reported to the parent on failure.
+static SETUP_STEPS: &[&'static str] = &[
+    "clone",
+    "write setgroups",
+    "write uid_map",
+    "write gid_map",
+    "make mounts private",
+    "bind new root",
+    "bind mount",
+    "remount read-only",
+    "mount proc",
+    "mount tmpfs",
+    "pivot root",
+    "detach old root",
+    "change directory",
+    "redirect stdio",
+    "execve",
+];
+
+#[derive(Debug)]
+pub struct LinuxNamespaceRunner {
+    cas: Arc<dyn Cas>,
+    /// Each action gets a fresh directory here.
+    work_root: PathBuf,
+}
+
+struct BindMount {
+    source: CString,
+    target: CString,
+    readonly: bool,
+}
+
+/// Everything the child needs, prepared before `clone`.
+///
+/// The child of a multithreaded process must not allocate, so no allocation after `clone`.
+struct ChildPlan {
+    new_root: CString,
+    old_root: CString,
+    old_root_in_sandbox: CString,
+    proc_directory: CString,
+    tmp_directory: CString,
+    mounts: Vec<BindMount>,
+    uid_map: Vec<u8>,
+    gid_map: Vec<u8>,
+    working_directory: CString,
+    program: CString,
+    _arguments: Vec<CString>,
+    argument_pointers: Vec<*const c_char>,
+    _environment: Vec<CString>,
+    environment_pointers: Vec<*const c_char>,
+    stdin: RawFd,
+    stdout: RawFd,
+    stderr: RawFd,
+}
+
+fn c_path(path: &Path) -> Result<CString, ActionError> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+fn pointers(strings: &[CString]) -> Vec<*const c_char> {
+    strings
+        .iter()
+        .map(|string| string.as_ptr())
+        .chain(std::iter::once(std::ptr::null()))
+        .collect()
+}
+
+/// Report the failed step and errno to the parent then exit.
+fn child_fail(error_fd: RawFd, step: usize) -> ! {
+    let errno = std::io::Error::last_os_error().raw_os_error().unwrap_or(0);
+    let mut message = [0u8; 8];
+    message[..4].copy_from_slice(&(step as u32).to_ne_bytes());
+    message[4..].copy_from_slice(&errno.to_ne_bytes());
+
+    unsafe {
+        libc::write(error_fd, message.as_ptr().cast(), message.len());
+        libc::_exit(127);
+    }
+}
+
+unsafe fn child_write_file(path: &[u8], content: &[u8]) -> bool {
+    unsafe {
+        let fd = libc::open(path.as_ptr().cast(), libc::O_WRONLY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return false;
+        }
+        let written = libc::write(fd, content.as_ptr().cast(), content.len());
+        libc::close(fd);
+        written == content.len() as isize
+    }
+}
+
+/// The flags that are locked in a user namespace must be kept when remounting.
+unsafe fn child_locked_flags(target: &CString) -> libc::c_ulong {
+    unsafe {
+        let mut stat: libc::statvfs = std::mem::zeroed();
+        if libc::statvfs(target.as_ptr(), &mut stat) != 0 {
+            return 0;
+        }
+
+        let mut flags = 0;
+        for (st, ms) in [
+            (libc::ST_NOSUID, libc::MS_NOSUID),
+            (libc::ST_NODEV, libc::MS_NODEV),
+            (libc::ST_NOEXEC, libc::MS_NOEXEC),
+            (libc::ST_NOATIME, libc::MS_NOATIME),
+            (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+            (libc::ST_RELATIME, libc::MS_RELATIME),
+        ] {
+            if stat.f_flag & st != 0 {
+                flags |= ms;
+            }
+        }
+        flags
+    }
+}
+
+/// Make the bind mount and every submount of it read-only.
+///
+/// Kernels before 5.12 have no `mount_setattr`, the mount is bound again without submounts,
+/// so nothing writable is left under it.
+unsafe fn child_make_readonly(mount: &BindMount) -> bool {
+    unsafe {
+        let null = std::ptr::null::<c_char>();
+
+        let mut attr: libc::mount_attr = std::mem::zeroed();
+        attr.attr_set = libc::MOUNT_ATTR_RDONLY;
+
+        if libc::syscall(
+            libc::SYS_mount_setattr,
+            libc::AT_FDCWD,
+            mount.target.as_ptr(),
+            libc::AT_RECURSIVE as libc::c_uint,
+            &attr as *const libc::mount_attr,
+            std::mem::size_of::<libc::mount_attr>(),
+        ) == 0
+        {
+            return true;
+        }
+
+        if std::io::Error::last_os_error().raw_os_error() != Some(libc::ENOSYS) {
+            return false;
+        }
+
+        libc::umount2(mount.target.as_ptr(), libc::MNT_DETACH) == 0
+            && libc::mount(
+                mount.source.as_ptr(),
+                mount.target.as_ptr(),
+                null,
+                libc::MS_BIND,
+                std::ptr::null(),
+            ) == 0
+            && libc::mount(
+                null,
+                mount.target.as_ptr(),
+                null,
+                libc::MS_BIND
+                    | libc::MS_REMOUNT
+                    | libc::MS_RDONLY
+                    | child_locked_flags(&mount.target),
+                std::ptr::null(),
+            ) == 0
+    }
+}
+
+/// Runs in the child after `clone`, only async-signal-safe functions are allowed.
+unsafe fn child_main(plan: &ChildPlan, error_fd: RawFd) -> ! {
+    unsafe {
+        let null = std::ptr::null::<c_char>();
+
+        // old kernels do not have setgroups
+        if !child_write_file(b"/proc/self/setgroups\0", b"deny")
+            && std::io::Error::last_os_error().raw_os_error() != Some(libc::ENOENT)
+        {
+            child_fail(error_fd, 1);
+        }
+        if !child_write_file(b"/proc/self/uid_map\0", &plan.uid_map) {
+            child_fail(error_fd, 2);
+        }
+        if !child_write_file(b"/proc/self/gid_map\0", &plan.gid_map) {
+            child_fail(error_fd, 3);
+        }
+
+        if libc::mount(
+            null,
+            c"/".as_ptr(),
+            null,
+            libc::MS_REC | libc::MS_PRIVATE,
+            std::ptr::null(),
+        ) != 0
+        {
+            child_fail(error_fd, 4);
+        }
+
+        if libc::mount(
+            plan.new_root.as_ptr(),
+            plan.new_root.as_ptr(),
+            null,
+            libc::MS_BIND | libc::MS_REC,
+            std::ptr::null(),
+        ) != 0
+        {
+            child_fail(error_fd, 5);
+        }
+
+        for mount in plan.mounts.iter() {
+            if libc::mount(
+                mount.source.as_ptr(),
+                mount.target.as_ptr(),
+                null,
+                libc::MS_BIND | libc::MS_REC,
+                std::ptr::null(),
+            ) != 0
+            {
+                child_fail(error_fd, 6);
+            }
+
+            if mount.readonly && !child_make_readonly(mount) {
+                child_fail(error_fd, 7);
+            }
+        }
+
+        if libc::mount(
+            c"proc".as_ptr(),
+            plan.proc_directory.as_ptr(),
+            c"proc".as_ptr(),
+            libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+            std::ptr::null(),
+        ) != 0
+        {
+            child_fail(error_fd, 8);
+        }
+
+        if libc::mount(
+            c"tmpfs".as_ptr(),
+            plan.tmp_directory.as_ptr(),
+            c"tmpfs".as_ptr(),
+            libc::MS_NOSUID | libc::MS_NODEV,
+            std::ptr::null(),
+        ) != 0
+        {
+            child_fail(error_fd, 9);
+        }
+
+        if libc::syscall(
+            libc::SYS_pivot_root,
+            plan.new_root.as_ptr(),
+            plan.old_root.as_ptr(),
+        ) != 0
+            || libc::chdir(c"/".as_ptr()) != 0
+        {
+            child_fail(error_fd, 10);
+        }
+
+        if libc::umount2(plan.old_root_in_sandbox.as_ptr(), libc::MNT_DETACH) != 0 {
+            child_fail(error_fd, 11);
+        }
+
+        if libc::chdir(plan.working_directory.as_ptr()) != 0 {
+            child_fail(error_fd, 12);
+        }
+
+        if libc::dup2(plan.stdin, 0) < 0
+            || libc::dup2(plan.stdout, 1) < 0
+            || libc::dup2(plan.stderr, 2) < 0
+        {
+            child_fail(error_fd, 13);
+        }
+
+        libc::execve(
+            plan.program.as_ptr(),
+            plan.argument_pointers.as_ptr(),
+            plan.environment_pointers.as_ptr(),
+        );
+
+        child_fail(error_fd, 14);
+    }
+}
+
+/// Clone into new namespaces and wait for the action.
+///
+/// Returns the wait status.
+fn spawn_and_wait(plan: &ChildPlan) -> Result<libc::c_int, ActionError> {
+    let mut error_pipe = [0 as RawFd; 2];
+
+    if unsafe { libc::pipe2(error_pipe.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    let [error_read, error_write] = error_pipe;
+
+    // stack is NULL, so it behaves like fork(2)
+    let pid = unsafe {
+        libc::syscall(
+            libc::SYS_clone,
+            libc::SIGCHLD as libc::c_ulong
+                | (libc::CLONE_NEWUSER
+                    | libc::CLONE_NEWNS
+                    | libc::CLONE_NEWPID
+                    | libc::CLONE_NEWNET
+                    | libc::CLONE_NEWIPC
+                    | libc::CLONE_NEWUTS) as libc::c_ulong,
+            0usize,
+            0usize,
+            0usize,
+            0usize,
+        )
+    };
+
+    if pid == 0 {
+        unsafe {
+            libc::close(error_read);
+            child_main(plan, error_write);
+        }
+    }
+
+    let clone_error = std::io::Error::last_os_error();
+
+    unsafe {
+        libc::close(error_write);
+    }
+
+    if pid < 0 {
+        unsafe {
+            libc::close(error_read);
+        }
+        return Err(ActionError::SetupError {
+            step: SETUP_STEPS[0],
+            source: clone_error,
+        });
+    }
+
+    let mut message = [0u8; 8];
+    let mut received = 0usize;
+
+    while received < message.len() {
+        let count = unsafe {
+            libc::read(
+                error_read,
+                message[received..].as_mut_ptr().cast(),
+                message.len() - received,
+            )
+        };
+
+        if count > 0 {
+            received += count as usize;
+        } else if count == 0
+            || std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted
+        {
+            break;
+        }
+    }
+
+    unsafe {
+        libc::close(error_read);
+    }
+
+    let mut status: libc::c_int = 0;
+
+    loop {
+        let waited = unsafe { libc::waitpid(pid as libc::pid_t, &mut status, 0) };
+        if waited >= 0 {
+            break;
+        }
+        let error = std::io::Error::last_os_error();
+        if error.kind() != std::io::ErrorKind::Interrupted {
+            return Err(error.into());
+        }
+    }
+
+    if received == message.len() {
+        let mut step = [0u8; 4];
+        let mut errno = [0u8; 4];
+        step.copy_from_slice(&message[..4]);
+        errno.copy_from_slice(&message[4..]);
+
+        let step = u32::from_ne_bytes(step) as usize;
+
+        return Err(ActionError::SetupError {
+            step: SETUP_STEPS.get(step).copied().unwrap_or("unknown"),
+            source: std::io::Error::from_raw_os_error(i32::from_ne_bytes(errno)),
+        });
+    }
+
+    Ok(status)
+}
+
+/// Watch `IN_OPEN` of every input directory to know which inputs were read.
+struct InputWatcher {
+    fd: OwnedFd,
+    directories: AHashMap<libc::c_int, NeutralPath>,
+    incomplete: bool,
+}
+
+impl InputWatcher {
+    fn new(exec_root: &Path, output_directory: &NeutralPath) -> Result<Self, ActionError> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        let mut watcher = Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            directories: AHashMap::new(),
+            incomplete: false,
+        };
+
+        // list the directories before watching, or the listing is reported as an access
+        let mut directories = Vec::new();
+        let mut pending = vec![NeutralPath::current_dir()];
+
+        while let Some(relative) = pending.pop() {
+            // outputs are listed after the action exits
+            if relative == *output_directory {
+                continue;
+            }
+
+            for entry in std::fs::read_dir(exec_root.join(&relative))? {
+                let entry = entry?;
+
+                if entry.file_type()?.is_dir() {
+                    pending.push(relative.join(entry.file_name().to_string_lossy())?);
+                }
+            }
+
+            directories.push(relative);
+        }
+
+        for relative in directories {
+            let path = exec_root.join(&relative);
+
+            let wd = unsafe {
+                libc::inotify_add_watch(
+                    watcher.fd.as_raw_fd(),
+                    c_path(&path)?.as_ptr(),
+                    libc::IN_OPEN,
+                )
+            };
+
+            if wd < 0 {
+                warn!(
+                    "failed to watch input directory {}: {}",
+                    path.display(),
+                    std::io::Error::last_os_error()
+                );
+                watcher.incomplete = true;
+                continue;
+            }
+
+            watcher.directories.insert(wd, relative);
+        }
+
+        Ok(watcher)
+    }
+
+    fn collect(self) -> (BTreeSet<FileAccess>, bool) {
+        let mut accesses = BTreeSet::new();
+        let mut incomplete = self.incomplete;
+
+        let header = std::mem::size_of::<libc::inotify_event>();
+        let mut buffer = vec![0u8; 64 * 1024];
+
+        loop {
+            let count = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    buffer.as_mut_ptr().cast(),
+                    buffer.len(),
+                )
+            };
+
+            // EAGAIN, all events are read
+            if count <= 0 {
+                break;
+            }
+
+            let count = count as usize;
+            let mut offset = 0;
+
+            while offset + header <= count {
+                let event: libc::inotify_event =
+                    unsafe { std::ptr::read_unaligned(buffer[offset..].as_ptr().cast()) };
+
+                let name_start = offset + header;
+                let name_end = (name_start + event.len as usize).min(count);
+                offset = name_start + event.len as usize;
+
+                if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                    incomplete = true;
+                    continue;
+                }
+
+                let Some(directory) = self.directories.get(&event.wd) else {
+                    continue;
+                };
+
+                let name = &buffer[name_start..name_end];
+                let name = &name[..name.iter().position(|x| *x == 0).unwrap_or(name.len())];
+
+                let path = if name.is_empty() {
+                    directory.clone()
+                } else {
+                    match directory.join(String::from_utf8_lossy(name)) {
+                        Ok(path) => path,
+                        Err(_) => continue,
+                    }
+                };
+
+                let kind = if event.mask & libc::IN_ISDIR != 0 {
+                    AccessKind::List
+                } else {
+                    AccessKind::Read
+                };
+
+                accesses.insert(FileAccess { path, kind });
+            }
+        }
+
+        (accesses, incomplete)
+    }
+}
+
+impl LinuxNamespaceRunner {
+    pub fn new(cas: Arc<dyn Cas>, work_root: PathBuf) -> Self {
+        Self { cas, work_root }
+    }
+
+    async fn create_mount_point_directory(
+        new_root: &Path,
+        created: &mut BTreeSet<PathBuf>,
+        path: PathBuf,
+    ) -> Result<(), ActionError> {
+        tokio::fs::create_dir_all(&path).await?;
+
+        let mut current = path.as_path();
+        while current != new_root {
+            created.insert(current.to_path_buf());
+            match current.parent() {
+                Some(parent) => current = parent,
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Create the mount points in the new root, returns all paths created.
+    async fn prepare_root(
+        new_root: &Path,
+        readonly_host_paths: &[PathBuf],
+    ) -> Result<BTreeSet<PathBuf>, ActionError> {
+        let mut created = BTreeSet::new();
+
+        for directory in [EXEC_ROOT, OLD_ROOT, "proc", "tmp", "dev"] {
+            Self::create_mount_point_directory(new_root, &mut created, new_root.join(directory))
+                .await?;
+        }
+
+        for host_path in readonly_host_paths
+            .iter()
+            .map(PathBuf::as_path)
+            .chain(DEVICES.iter().map(Path::new))
+        {
+            let relative = host_path
+                .strip_prefix("/")
+                .map_err(|_| ActionError::HostPathNotAbsolute(host_path.to_path_buf()))?;
+            let target = new_root.join(relative);
+
+            if tokio::fs::metadata(host_path).await?.is_dir() {
+                Self::create_mount_point_directory(new_root, &mut created, target).await?;
+            } else {
+                if let Some(parent) = target.parent() {
+                    Self::create_mount_point_directory(
+                        new_root,
+                        &mut created,
+                        parent.to_path_buf(),
+                    )
+                    .await?;
+                }
+                tokio::fs::File::create(&target).await?;
+                created.insert(target);
+            }
+        }
+
+        Ok(created)
+    }
+
+    /// Record every file in the output directory as written.
+    fn list_outputs(
+        output_root: &Path,
+        output_directory: &NeutralPath,
+        accesses: &mut BTreeSet<FileAccess>,
+    ) -> Result<(), ActionError> {
+        let mut pending = vec![(output_root.to_path_buf(), output_directory.clone())];
+
+        while let Some((directory, relative)) = pending.pop() {
+            for entry in std::fs::read_dir(&directory)? {
+                let entry = entry?;
+                let path = relative.join(entry.file_name().to_string_lossy())?;
+
+                if entry.file_type()?.is_dir() {
+                    pending.push((entry.path(), path));
+                } else {
+                    accesses.insert(FileAccess {
+                        path,
+                        kind: AccessKind::Write,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Anything in the new root which is not a mount point was written by the action.
+    fn find_undeclared_write(
+        new_root: &Path,
+        allowed: &BTreeSet<PathBuf>,
+    ) -> Result<Option<PathBuf>, ActionError> {
+        let mut pending = vec![new_root.to_path_buf()];
+
+        while let Some(directory) = pending.pop() {
+            for entry in std::fs::read_dir(&directory)? {
+                let path = entry?.path();
+
+                if !allowed.contains(&path) {
+                    return Ok(Some(
+                        Path::new("/").join(path.strip_prefix(new_root).unwrap_or(&path)),
+                    ));
+                }
+
+                if path.is_dir() && !path.is_symlink() {
+                    pending.push(path);
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn plan(
+        action: &Action,
+        new_root: &Path,
+        exec_root: &Path,
+        output_root: &Path,
+        stdio: (RawFd, RawFd, RawFd),
+    ) -> Result<ChildPlan, ActionError> {
+        let program = CString::new(
+            action
+                .arguments
+                .first()
+                .ok_or(ActionError::EmptyArguments)?
+                .as_str(),
+        )?;
+
+        let arguments = action
+            .arguments
+            .iter()
+            .map(|argument| CString::new(argument.as_str()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let environment = action
+            .environment
+            .iter()
+            .map(|(key, value)| CString::new(format!("{}={}", key, value)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let exec_in_sandbox = new_root.join(EXEC_ROOT);
+
+        let mut mounts = vec![
+            BindMount {
+                source: c_path(exec_root)?,
+                target: c_path(&exec_in_sandbox)?,
+                readonly: true,
+            },
+            BindMount {
+                source: c_path(output_root)?,
+                target: c_path(&exec_in_sandbox.join(&action.output_directory))?,
+                readonly: false,
+            },
+        ];
+
+        for host_path in action
+            .readonly_host_paths
+            .iter()
+            .map(PathBuf::as_path)
+            .chain(DEVICES.iter().map(Path::new))
+        {
+            let relative = host_path
+                .strip_prefix("/")
+                .map_err(|_| ActionError::HostPathNotAbsolute(host_path.to_path_buf()))?;
+
+            mounts.push(BindMount {
+                source: c_path(host_path)?,
+                target: c_path(&new_root.join(relative))?,
+                readonly: true,
+            });
+        }
+
+        // map the current user to itself, so the action has no capability after execve
+        let uid = unsafe { libc::geteuid() };
+        let gid = unsafe { libc::getegid() };
+
+        let argument_pointers = pointers(&arguments);
+        let environment_pointers = pointers(&environment);
+
+        Ok(ChildPlan {
+            new_root: c_path(new_root)?,
+            old_root: c_path(&new_root.join(OLD_ROOT))?,
+            old_root_in_sandbox: c_path(&Path::new("/").join(OLD_ROOT))?,
+            proc_directory: c_path(&new_root.join("proc"))?,
+            tmp_directory: c_path(&new_root.join("tmp"))?,
+            mounts,
+            uid_map: format!("{} {} 1", uid, uid).into_bytes(),
+            gid_map: format!("{} {} 1", gid, gid).into_bytes(),
+            working_directory: c_path(
+                &Path::new("/")
+                    .join(EXEC_ROOT)
+                    .join(&action.working_directory),
+            )?,
+            program,
+            _arguments: arguments,
+            argument_pointers,
+            _environment: environment,
+            environment_pointers,
+            stdin: stdio.0,
+            stdout: stdio.1,
+            stderr: stdio.2,
+        })
+    }
+
+    /// Run the action in the work directory, the caller cleans the work directory.
+    async fn run_in(
+        &self,
+        action: &Action,
+        work_directory: &Path,
+    ) -> Result<ActionResult, ActionError> {
+        let new_root = work_directory.join("root");
+        let exec_root = work_directory.join("exec");
+        let output_root = work_directory.join("output");
+
+        trace!("prepare sandbox for action at {}", work_directory.display());
+
+        materialize(&action.inputs, self.cas.as_ref(), &exec_root).await?;
+        tokio::fs::create_dir_all(exec_root.join(&action.output_directory)).await?;
+        tokio::fs::create_dir_all(&output_root).await?;
+
+        let allowed = Self::prepare_root(&new_root, &action.readonly_host_paths).await?;
+
+        let watcher = InputWatcher::new(&exec_root, &action.output_directory)?;
+
+        let output_directory = action.output_directory.clone();
+        let inputs = action.inputs.clone();
+        let action = action.clone();
+        let stdout_path = work_directory.join("stdout");
+        let stderr_path = work_directory.join("stderr");
+
+        let status = {
+            let new_root = new_root.clone();
+            let stdout_path = stdout_path.clone();
+            let stderr_path = stderr_path.clone();
+
+            tokio::task::spawn_blocking(move || -> Result<libc::c_int, ActionError> {
+                let stdin = std::fs::File::open("/dev/null")?;
+                let stdout = std::fs::File::create(&stdout_path)?;
+                let stderr = std::fs::File::create(&stderr_path)?;
+
+                let plan = Self::plan(
+                    &action,
+                    &new_root,
+                    &exec_root,
+                    &output_root,
+                    (stdin.as_raw_fd(), stdout.as_raw_fd(), stderr.as_raw_fd()),
+                )?;
+
+                spawn_and_wait(&plan)
+            })
+            .await
+            .map_err(std::io::Error::other)??
+        };
+
+        let undeclared = Self::find_undeclared_write(&new_root, &allowed)?;
+
+        let (mut accesses, accesses_incomplete) = watcher.collect();
+        Self::list_outputs(
+            &work_directory.join("output"),
+            &output_directory,
+            &mut accesses,
+        )?;
+
+        let result = ActionResult {
+            exit_code: libc::WIFEXITED(status).then(|| libc::WEXITSTATUS(status)),
+            signal: libc::WIFSIGNALED(status).then(|| libc::WTERMSIG(status)),
+            stdout: tokio::fs::read(&stdout_path).await?,
+            stderr: tokio::fs::read(&stderr_path).await?,
+            output_directory: work_directory.join("output"),
+            accesses: accesses.into_iter().collect(),
+            accesses_incomplete,
+        };
+
+        if let Some(path) = undeclared {
+            return Err(ActionError::UndeclaredWrite(path));
+        }
+
+        if let Some(access) = result
+            .accesses
+            .iter()
+            .find(|access| access.kind == AccessKind::Read && inputs.get(&access.path).is_none())
+        {
+            return Err(ActionError::UndeclaredRead(access.path.clone()));
+        }
+
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl ActionRunner for LinuxNamespaceRunner {
+    async fn run(&self, action: &Action) -> Result<ActionResult, ActionError> {
+        action.validate()?;
+
+        let work_directory = self.work_root.join(uuid::Uuid::new_v4().to_string());
+
+        let result = self.run_in(action, &work_directory).await;
+
+        // the output directory is kept for the caller, unless the action failed
+        let garbage = if result.is_ok() {
+            vec![work_directory.join("root"), work_directory.join("exec")]
+        } else {
+            vec![work_directory]
+        };
+
+        for directory in garbage {
+            match tokio::fs::remove_dir_all(&directory).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => warn!(
+                    "failed to clean action sandbox {}: {}",
+                    directory.display(),
+                    err
+                ),
+                _ => {}
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunking::digest_of;
+    use crate::fs::{FsItem, VirtualFsItem, VirtualTree};
+    use crate::local_cas::LocalCas;
+    use crate::test_util::TempDir;
+    use std::collections::BTreeMap;
+
+    /// The host paths that `/bin/sh` needs.
+    static SHELL_PATHS: &[&str] = &["/bin", "/lib", "/lib64", "/usr"];
+
+    async fn run_shell(root: &TempDir, script: &str) -> eyre::Result<ActionResult> {
+        let cas = Arc::new(LocalCas::new(root.path().join("cas")));
+
+        let content = b"declared".to_vec();
+        let digest = digest_of(&content);
+        cas.store(&digest, Box::new(std::io::Cursor::new(content)))
+            .await?;
+
+        let inputs = VirtualTree::new([VirtualFsItem::new(
+            NeutralPath::new("input.txt")?,
+            FsItem::File(digest),
+            false,
+            true,
+        )?])?;
+
+        let action = Action {
+            arguments: vec!["/bin/sh".to_string(), "-c".to_string(), script.to_string()],
+            environment: BTreeMap::new(),
+            inputs,
+            working_directory: NeutralPath::current_dir(),
+            output_directory: NeutralPath::new("out")?,
+            readonly_host_paths: SHELL_PATHS
+                .iter()
+                .map(|path| PathBuf::from(*path))
+                .filter(|path| path.exists())
+                .collect(),
+        };
+
+        Ok(LinuxNamespaceRunner::new(cas, root.path().join("work"))
+            .run(&action)
+            .await?)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs unprivileged user namespaces"]
+    async fn declared_input_is_read_and_output_is_written() -> eyre::Result<()> {
+        let root = TempDir::new()?;
+
+        let result = run_shell(&root, "cat input.txt > out/copy.txt").await?;
+
+        assert!(result.success());
+        assert!(result.accesses.contains(&FileAccess {
+            path: NeutralPath::new("input.txt")?,
+            kind: AccessKind::Read,
+        }));
+        assert_eq!(
+            std::fs::read(result.output_directory.join("copy.txt"))?,
+            b"declared"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs unprivileged user namespaces"]
+    async fn inputs_and_host_paths_are_read_only() -> eyre::Result<()> {
+        let root = TempDir::new()?;
+
+        for script in ["echo x > input.txt", "touch /usr/zmake-test"] {
+            let result = run_shell(&root, script).await?;
+
+            assert!(!result.success(), "`{}` succeeded", script);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+//! Hermetic execution of actions.
+//!
+//! An action can only see its declared inputs, write its declared output directory
+//! and read the declared environment variables.
+
+#[cfg(target_os = "linux")]
+pub mod linux;
+
+use crate::audit::FileAccess;
+use crate::cas::{Cas, CasError};
+use crate::fs::{FsItem, VirtualTree};
+use crate::path::{NeutralPath, PathError};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::ffi::NulError;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    /// The first argument is the program.
+    ///
+    /// There is no `PATH` lookup, so the program must be a path inside the sandbox.
+    pub arguments: Vec<String>,
+    /// The only environment variables the action can see.
+    pub environment: BTreeMap<String, String>,
+    /// The declared inputs, they are read-only for the action.
+    pub inputs: VirtualTree,
+    /// Relative to the exec root.
+    pub working_directory: NeutralPath,
+    /// Relative to the exec root, the only place the action can write to.
+    pub output_directory: NeutralPath,
+    /// Host paths(like toolchains) that can be read by the action.
+    ///
+    /// They are mounted read-only at the same path in the sandbox.
+    pub readonly_host_paths: Vec<PathBuf>,
+}
+
+impl Action {
+    /// Check the action before anything is created for it.
+    pub fn validate(&self) -> Result<(), ActionError> {
+        if self.arguments.is_empty() {
+            return Err(ActionError::EmptyArguments);
+        }
+
+        if self.output_directory.is_escaping() {
+            return Err(ActionError::OutputDirectoryEscape(
+                self.output_directory.clone(),
+            ));
+        }
+
+        // the output directory is mounted over the exec root and hides every input
+        if self.output_directory.components().next().is_none() {
+            return Err(ActionError::OutputDirectoryIsExecRoot(
+                self.output_directory.clone(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ActionResult {
+    /// None if the action was killed by signal.
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// The host path of the declared output directory.
+    pub output_directory: PathBuf,
+    /// The files read and written by the action, relative to the exec root.
+    pub accesses: Vec<FileAccess>,
+    /// Some accesses may be lost, so `accesses` can not prove the action is hermetic.
+    pub accesses_incomplete: bool,
+}
+
+impl ActionResult {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ActionError {
+    #[error("the action has no program to execute")]
+    EmptyArguments,
+    #[error("the output directory `{0}` is not in the exec root")]
+    OutputDirectoryEscape(NeutralPath),
+    #[error("the output directory `{0}` is the exec root, it must be a directory in it")]
+    OutputDirectoryIsExecRoot(NeutralPath),
+    #[error("the readonly host path `{0}` is not absolute")]
+    HostPathNotAbsolute(PathBuf),
+    #[error("failed to materialize input `{path}`: {source}")]
+    MaterializeError { path: NeutralPath, source: CasError },
+    #[error("failed to setup sandbox at step `{step}`: {source}")]
+    SetupError {
+        step: &'static str,
+        source: std::io::Error,
+    },
+    #[error("the action wrote to undeclared path `{0}`")]
+    UndeclaredWrite(PathBuf),
+    #[error("the action read undeclared input `{0}`")]
+    UndeclaredRead(NeutralPath),
+    #[error("the argument or environment variable contains NUL: {0}")]
+    InvalidCString(#[from] NulError),
+    #[error("get an path error: {0}")]
+    PathError(#[from] PathError),
+    #[error("get an io error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+#[async_trait]
+pub trait ActionRunner: Send + Sync + std::fmt::Debug {
+    async fn run(&self, action: &Action) -> Result<ActionResult, ActionError>;
+}
+
+/// Write the tree into the directory, file contents are fetched from the CAS.
+pub async fn materialize(
+    tree: &VirtualTree,
+    cas: &dyn Cas,
+    directory: &Path,
+) -> Result<(), ActionError> {
+    for item in tree.iter() {
+        let relative_path = item.get_relative_path();
+        let path = directory.join(relative_path);
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        match item.get_digest() {
+            FsItem::File(digest) => {
+                if let Some(local) = cas.get_local_path(digest).await {
+                    tokio::fs::copy(local, &path).await?;
+                } else {
+                    let mut data = cas.fetch(digest, 0).await.map_err(|err| {
+                        ActionError::MaterializeError {
+                            path: relative_path.clone(),
+                            source: err,
+                        }
+                    })?;
+
+                    let mut file = tokio::fs::File::create(&path).await?;
+                    tokio::io::copy(&mut data, &mut file).await?;
+                    file.flush().await?;
+                }
+
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+
+                    let mode = match (item.is_executable(), item.is_readonly()) {
+                        (true, true) => 0o555,
+                        (true, false) => 0o755,
+                        (false, true) => 0o444,
+                        (false, false) => 0o644,
+                    };
+
+                    tokio::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))
+                        .await?;
+                }
+            }
+            FsItem::Symlink(target) => {
+                #[cfg(unix)]
+                tokio::fs::symlink(target, &path).await?;
+                #[cfg(windows)]
+                tokio::fs::symlink_file(target, &path).await?;
+            }
+            FsItem::EmptyDirectory => {
+                tokio::fs::create_dir_all(&path).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
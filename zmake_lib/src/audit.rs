@@ -0,0 +1,137 @@
+//! Record the files accessed by build scripts and actions.
+//!
+//! Every access that was not declared as input makes the result non-hermetic.
+
+use crate::path::NeutralPath;
+use crate::pattern::Pattern;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use thiserror::Error;
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessKind {
+    Read,
+    Write,
+    List,
+}
+
+impl Display for AccessKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccessKind::Read => write!(f, "read"),
+            AccessKind::Write => write!(f, "write"),
+            AccessKind::List => write!(f, "list"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct FileAccess {
+    /// Relative to the sandbox root(or the exec root for actions).
+    pub path: NeutralPath,
+    pub kind: AccessKind,
+}
+
+/// What to do when a read was not declared as input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum AuditPolicy {
+    Allow,
+    #[default]
+    Warn,
+    Deny,
+}
+
+#[derive(Error, Debug)]
+pub enum AuditError {
+    #[error("undeclared {kind} of `{path}`, declare it as input to keep the build hermetic")]
+    UndeclaredAccess { path: NeutralPath, kind: AccessKind },
+    #[error("invalid input pattern: {0}")]
+    InvalidPattern(#[from] glob::PatternError),
+}
+
+/// Thread-safe log of file accesses, duplicated accesses are recorded once.
+#[derive(Debug, Default)]
+pub struct AccessAudit {
+    accesses: Mutex<BTreeSet<FileAccess>>,
+    /// Some accesses may be lost(e.g. the event queue overflowed).
+    incomplete: AtomicBool,
+}
+
+impl AccessAudit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, path: NeutralPath, kind: AccessKind) {
+        self.accesses
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(FileAccess { path, kind });
+    }
+
+    pub fn mark_incomplete(&self) {
+        self.incomplete.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_incomplete(&self) -> bool {
+        self.incomplete.load(Ordering::SeqCst)
+    }
+
+    pub fn snapshot(&self) -> Vec<FileAccess> {
+        self.accesses
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Take all recorded accesses and clear the log.
+    pub fn take(&self) -> Vec<FileAccess> {
+        std::mem::take(
+            &mut *self
+                .accesses
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        )
+        .into_iter()
+        .collect()
+    }
+}
+
+/// Check that every read and list was declared.
+///
+/// Writes are not checked here, they are checked against the declared outputs.
+pub fn check_accesses(
+    accesses: &[FileAccess],
+    declared: &Pattern,
+    policy: AuditPolicy,
+) -> Result<(), AuditError> {
+    if policy == AuditPolicy::Allow {
+        return Ok(());
+    }
+
+    for access in accesses.iter().filter(|x| x.kind != AccessKind::Write) {
+        if declared.matches(&access.path)? {
+            continue;
+        }
+
+        let error = AuditError::UndeclaredAccess {
+            path: access.path.clone(),
+            kind: access.kind,
+        };
+
+        match policy {
+            AuditPolicy::Deny => return Err(error),
+            AuditPolicy::Warn => warn!("{}", error),
+            AuditPolicy::Allow => {}
+        }
+    }
+
+    Ok(())
+}
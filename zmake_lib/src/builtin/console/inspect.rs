@@ -0,0 +1,571 @@
+//! Format values like `util.inspect` of Node.
+//!
+//! Exceptions thrown by getters or proxies are expected to be caught by the caller, the value is
+//! then formatted as `[Thrown]`.
+
+use v8::{Local, PinScope, Value};
+
+/// Objects deeper than it are formatted as `[Object]`.
+const MAX_DEPTH: usize = 2;
+
+/// The entries of array, map and set are truncated after it.
+const MAX_ENTRIES: usize = 100;
+
+/// The entries are put in one line if it is not longer than it.
+const BREAK_LENGTH: usize = 72;
+
+/// Format the arguments of console, strings are not quoted.
+///
+/// A leading string may contain `printf`-like substitutions: `%s %d %i %f %j %o %O %c %%`.
+pub fn format_arguments<'s, 'i>(scope: &mut PinScope<'s, 'i>, args: &[Local<'s, Value>]) -> String {
+    let mut parts = Vec::with_capacity(args.len());
+    let mut rest = args;
+
+    if let Some((first, others)) = args.split_first()
+        && first.is_string()
+    {
+        let format = first.to_rust_string_lossy(scope);
+        let (formatted, used) = substitute(scope, &format, others);
+
+        parts.push(formatted);
+        rest = &others[used..];
+    }
+
+    for value in rest {
+        if value.is_string() {
+            parts.push(value.to_rust_string_lossy(scope));
+        } else {
+            parts.push(inspect(scope, *value));
+        }
+    }
+
+    parts.join(" ")
+}
+
+/// Format the value, strings are quoted.
+pub fn inspect<'s, 'i>(scope: &mut PinScope<'s, 'i>, value: Local<'s, Value>) -> String {
+    inspect_value(scope, value, 0, &mut Vec::new())
+}
+
+/// Return the formatted string and the count of used arguments.
+fn substitute<'s, 'i>(
+    scope: &mut PinScope<'s, 'i>,
+    format: &str,
+    args: &[Local<'s, Value>],
+) -> (String, usize) {
+    let mut output = String::with_capacity(format.len());
+    let mut used = 0;
+    let mut chars = format.chars().peekable();
+
+    while let Some(char) = chars.next() {
+        if char != '%' {
+            output.push(char);
+            continue;
+        }
+
+        let Some(&specifier) = chars.peek() else {
+            output.push('%');
+            break;
+        };
+
+        if specifier == '%' {
+            chars.next();
+            output.push('%');
+            continue;
+        }
+
+        // unknown specifiers and missing arguments are kept as they are
+        let Some(value) = args.get(used).copied() else {
+            output.push('%');
+            continue;
+        };
+
+        if !matches!(specifier, 's' | 'd' | 'i' | 'f' | 'j' | 'o' | 'O' | 'c') {
+            output.push('%');
+            continue;
+        }
+
+        chars.next();
+        used += 1;
+
+        match specifier {
+            's' if value.is_string() => output.push_str(&value.to_rust_string_lossy(scope)),
+            'd' | 'i' | 'f' if value.is_big_int() => {
+                output.push_str(&format!("{}n", value.to_rust_string_lossy(scope)))
+            }
+            'd' | 'i' | 'f' => {
+                let number = if value.is_symbol() || (specifier != 'f' && value.is_object()) {
+                    f64::NAN
+                } else {
+                    value.number_value(scope).unwrap_or(f64::NAN)
+                };
+
+                let number = if specifier == 'i' {
+                    number.trunc()
+                } else {
+                    number
+                };
+
+                output.push_str(&format_number(scope, number));
+            }
+            'j' => match v8::json::stringify(scope, value) {
+                Some(json) => output.push_str(&json.to_rust_string_lossy(scope)),
+                None => output.push_str("[Circular]"),
+            },
+            // css is meaningless for logs
+            'c' => {}
+            _ => output.push_str(&inspect(scope, value)),
+        }
+    }
+
+    (output, used)
+}
+
+fn format_number<'s, 'i>(scope: &mut PinScope<'s, 'i>, number: f64) -> String {
+    if number == 0.0 && number.is_sign_negative() {
+        return "-0".to_string();
+    }
+
+    v8::Number::new(scope, number).to_rust_string_lossy(scope)
+}
+
+fn quote(string: &str) -> String {
+    let quote = if string.contains('\'') && !string.contains('"') {
+        '"'
+    } else {
+        '\''
+    };
+
+    let mut quoted = String::with_capacity(string.len() + 2);
+    quoted.push(quote);
+
+    for char in string.chars() {
+        match char {
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            _ if char == quote => {
+                quoted.push('\\');
+                quoted.push(char);
+            }
+            _ if char.is_control() => quoted.push_str(&format!("\\x{:02X}", char as u32)),
+            _ => quoted.push(char),
+        }
+    }
+
+    quoted.push(quote);
+    quoted
+}
+
+fn format_key(key: &str) -> String {
+    let mut chars = key.chars();
+
+    let is_identifier = chars
+        .next()
+        .is_some_and(|char| char.is_alphabetic() || char == '_' || char == '$')
+        && chars.all(|char| char.is_alphanumeric() || char == '_' || char == '$');
+
+    if is_identifier {
+        key.to_string()
+    } else {
+        quote(key)
+    }
+}
+
+/// Format the time of `Date` as ISO 8601 in UTC.
+fn format_date(time: f64) -> String {
+    if !time.is_finite() {
+        return "Invalid Date".to_string();
+    }
+
+    let time = time as i64;
+    let days = time.div_euclid(86_400_000);
+    let millisecond = time.rem_euclid(86_400_000);
+
+    // the civil date of days since the unix epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        millisecond / 3_600_000,
+        millisecond / 60_000 % 60,
+        millisecond / 1000 % 60,
+        millisecond % 1000
+    )
+}
+
+fn inspect_function<'s, 'i>(
+    scope: &mut PinScope<'s, 'i>,
+    function: Local<'s, v8::Function>,
+) -> String {
+    let name = function.get_name(scope).to_rust_string_lossy(scope);
+    let source = function.to_rust_string_lossy(scope);
+
+    match (source.starts_with("class"), name.is_empty()) {
+        (true, true) => "[class (anonymous)]".to_string(),
+        (true, false) => format!("[class {}]", name),
+        (false, true) => "[Function (anonymous)]".to_string(),
+        (false, false) => format!("[Function: {}]", name),
+    }
+}
+
+fn inspect_error<'s, 'i>(scope: &mut PinScope<'s, 'i>, error: Local<'s, v8::Object>) -> String {
+    let stack = v8::String::new(scope, "stack")
+        .and_then(|key| error.get(scope, key.into()))
+        .filter(|stack| stack.is_string());
+
+    match stack {
+        Some(stack) => stack.to_rust_string_lossy(scope),
+        None => format!("[{}]", error.to_rust_string_lossy(scope)),
+    }
+}
+
+/// Return the formatted elements and the count of truncated elements.
+fn inspect_elements<'s, 'i>(
+    scope: &mut PinScope<'s, 'i>,
+    array: Local<'s, v8::Object>,
+    length: usize,
+    depth: usize,
+    seen: &mut Vec<Local<'s, v8::Object>>,
+) -> (Vec<String>, usize) {
+    let mut elements = Vec::with_capacity(length.min(MAX_ENTRIES));
+
+    for index in 0..length.min(MAX_ENTRIES) {
+        elements.push(match array.get_index(scope, index as u32) {
+            Some(element) => inspect_value(scope, element, depth + 1, seen),
+            None => "[Thrown]".to_string(),
+        });
+    }
+
+    (elements, length.saturating_sub(MAX_ENTRIES))
+}
+
+fn inspect_value<'s, 'i>(
+    scope: &mut PinScope<'s, 'i>,
+    value: Local<'s, Value>,
+    depth: usize,
+    seen: &mut Vec<Local<'s, v8::Object>>,
+) -> String {
+    if value.is_undefined() {
+        return "undefined".to_string();
+    }
+    if value.is_null() {
+        return "null".to_string();
+    }
+    if value.is_string() {
+        return quote(&value.to_rust_string_lossy(scope));
+    }
+    if value.is_big_int() {
+        return format!("{}n", value.to_rust_string_lossy(scope));
+    }
+    if value.is_number() {
+        let number = value.number_value(scope).unwrap_or(f64::NAN);
+        return format_number(scope, number);
+    }
+    if let Ok(symbol) = value.try_cast::<v8::Symbol>() {
+        let description = symbol.description(scope);
+
+        return if description.is_undefined() {
+            "Symbol()".to_string()
+        } else {
+            format!("Symbol({})", description.to_rust_string_lossy(scope))
+        };
+    }
+    if let Ok(function) = value.try_cast::<v8::Function>() {
+        return inspect_function(scope, function);
+    }
+
+    let Ok(object) = value.try_cast::<v8::Object>() else {
+        return value.to_rust_string_lossy(scope);
+    };
+
+    if seen.iter().any(|x| x.strict_equals(value)) {
+        return "[Circular]".to_string();
+    }
+    if value.is_native_error() {
+        return inspect_error(scope, object);
+    }
+    if let Ok(date) = value.try_cast::<v8::Date>() {
+        return format_date(date.value_of());
+    }
+    if value.is_reg_exp() {
+        return value.to_rust_string_lossy(scope);
+    }
+
+    let constructor = object.get_constructor_name().to_rust_string_lossy(scope);
+
+    if depth > MAX_DEPTH {
+        return if value.is_array() {
+            "[Array]".to_string()
+        } else {
+            format!("[{}]", constructor)
+        };
+    }
+
+    seen.push(object);
+
+    let mut entries = Vec::new();
+    let mut remaining = 0;
+
+    let (prefix, open, close) = if let Ok(array) = value.try_cast::<v8::Array>() {
+        (entries, remaining) =
+            inspect_elements(scope, object, array.length() as usize, depth, seen);
+
+        let prefix = if constructor == "Array" {
+            String::new()
+        } else {
+            format!("{}({})", constructor, array.length())
+        };
+
+        (prefix, "[", "]")
+    } else if let Ok(typed_array) = value.try_cast::<v8::TypedArray>() {
+        (entries, remaining) = inspect_elements(scope, object, typed_array.length(), depth, seen);
+
+        (
+            format!("{}({})", constructor, typed_array.length()),
+            "[",
+            "]",
+        )
+    } else if let Ok(map) = value.try_cast::<v8::Map>() {
+        let pairs = map.as_array(scope);
+        let size = map.size();
+
+        for index in 0..size.min(MAX_ENTRIES) {
+            let key = pairs.get_index(scope, (index * 2) as u32);
+            let value = pairs.get_index(scope, (index * 2 + 1) as u32);
+
+            if let (Some(key), Some(value)) = (key, value) {
+                let key = inspect_value(scope, key, depth + 1, seen);
+                let value = inspect_value(scope, value, depth + 1, seen);
+                entries.push(format!("{} => {}", key, value));
+            }
+        }
+
+        remaining = size.saturating_sub(MAX_ENTRIES);
+        (format!("{}({})", constructor, size), "{", "}")
+    } else if let Ok(set) = value.try_cast::<v8::Set>() {
+        let size = set.size();
+        let values = set.as_array(scope);
+        (entries, remaining) = inspect_elements(scope, values.into(), size, depth, seen);
+
+        (format!("{}({})", constructor, size), "{", "}")
+    } else if let Ok(promise) = value.try_cast::<v8::Promise>() {
+        entries.push(match promise.state() {
+            v8::PromiseState::Pending => "<pending>".to_string(),
+            v8::PromiseState::Fulfilled => {
+                let result = promise.result(scope);
+                inspect_value(scope, result, depth + 1, seen)
+            }
+            v8::PromiseState::Rejected => {
+                let result = promise.result(scope);
+                format!(
+                    "<rejected> {}",
+                    inspect_value(scope, result, depth + 1, seen)
+                )
+            }
+        });
+
+        ("Promise".to_string(), "{", "}")
+    } else {
+        let prefix = if constructor == "Object" {
+            String::new()
+        } else {
+            constructor
+        };
+
+        (prefix, "{", "}")
+    };
+
+    // the properties of plain objects, arrays and maps only show their elements
+    if open == "{" && !value.is_map() && !value.is_set() && !value.is_promise() {
+        let names = object.get_own_property_names(
+            scope,
+            v8::GetPropertyNamesArgs {
+                mode: v8::KeyCollectionMode::OwnOnly,
+                property_filter: v8::PropertyFilter::ONLY_ENUMERABLE
+                    | v8::PropertyFilter::SKIP_SYMBOLS,
+                index_filter: v8::IndexFilter::IncludeIndices,
+                key_conversion: v8::KeyConversionMode::ConvertToString,
+            },
+        );
+
+        if let Some(names) = names {
+            for index in 0..names.length() {
+                let Some(key) = names.get_index(scope, index) else {
+                    continue;
+                };
+
+                let name = format_key(&key.to_rust_string_lossy(scope));
+
+                let value = match object.get(scope, key) {
+                    Some(value) => inspect_value(scope, value, depth + 1, seen),
+                    None => "[Thrown]".to_string(),
+                };
+
+                entries.push(format!("{}: {}", name, value));
+            }
+        }
+    }
+
+    seen.pop();
+
+    if remaining > 0 {
+        entries.push(format!(
+            "... {} more item{}",
+            remaining,
+            if remaining > 1 { "s" } else { "" }
+        ));
+    }
+
+    wrap(&prefix, open, close, &entries, depth)
+}
+
+fn wrap(prefix: &str, open: &str, close: &str, entries: &[String], depth: usize) -> String {
+    let head = if prefix.is_empty() {
+        open.to_string()
+    } else {
+        format!("{} {}", prefix, open)
+    };
+
+    if entries.is_empty() {
+        return format!("{}{}", head, close);
+    }
+
+    let line = entries.join(", ");
+
+    if !line.contains('\n') && head.len() + line.len() + depth * 2 <= BREAK_LENGTH {
+        return format!("{} {} {}", head, line, close);
+    }
+
+    let indentation = "  ".repeat(depth + 1);
+
+    format!(
+        "{}\n{}{}\n{}{}",
+        head,
+        indentation,
+        entries.join(&format!(",\n{}", indentation)),
+        "  ".repeat(depth),
+        close
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Evaluate the expressions in a fresh context and format them like `console.log`.
+    fn format(expressions: &[&str]) -> Option<String> {
+        let _ = crate::platform::get_initialized_or_default();
+
+        let mut isolate = v8::Isolate::new(Default::default());
+        let scope = std::pin::pin!(v8::HandleScope::new(&mut isolate));
+        let mut scope = scope.init();
+        let context = v8::Context::new(&scope, Default::default());
+        let scope = &mut v8::ContextScope::new(&mut scope, context);
+
+        let mut values = Vec::with_capacity(expressions.len());
+
+        for expression in expressions {
+            let code = v8::String::new(scope, expression)?;
+            values.push(v8::Script::compile(scope, code, None)?.run(scope)?);
+        }
+
+        Some(format_arguments(scope, &values))
+    }
+
+    #[test]
+    fn substitutions() {
+        for (expressions, expected) in [
+            (&["'%s is %d'", "'answer'", "42"][..], "answer is 42"),
+            (&["'%i'", "42.9"], "42"),
+            (&["'%f'", "-0"], "-0"),
+            (&["'%d'", "10n"], "10n"),
+            (&["'%d'", "({})"], "NaN"),
+            (&["'%j'", "({ a: [1] })"], "{\"a\":[1]}"),
+            (&["'%o'", "'text'"], "'text'"),
+            (&["'%c styled'", "'color: red'"], " styled"),
+            (&["'100%%'"], "100%"),
+        ] {
+            assert_eq!(format(expressions).as_deref(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn missing_and_unknown_substitutions_are_kept() {
+        assert_eq!(
+            format(&["'%s and %s'", "'one'"]).as_deref(),
+            Some("one and %s")
+        );
+        assert_eq!(format(&["'%x'", "1"]).as_deref(), Some("%x 1"));
+        assert_eq!(format(&["'trailing %'"]).as_deref(), Some("trailing %"));
+    }
+
+    #[test]
+    fn rest_arguments_are_appended() {
+        assert_eq!(
+            format(&["'%s'", "'used'", "'rest'", "1", "({ a: 'b' })"]).as_deref(),
+            Some("used rest 1 { a: 'b' }")
+        );
+        assert_eq!(format(&["1", "'%s'"]).as_deref(), Some("1 %s"));
+    }
+
+    #[test]
+    fn quote_prefers_single_quotes() {
+        assert_eq!(quote("text"), "'text'");
+        assert_eq!(quote("it's"), "\"it's\"");
+        assert_eq!(quote("it's \"quoted\""), "'it\\'s \"quoted\"'");
+        assert_eq!(quote("line\nbreak\t\\"), "'line\\nbreak\\t\\\\'");
+        assert_eq!(quote("\u{1}"), "'\\x01'");
+    }
+
+    #[test]
+    fn format_key_quotes_non_identifiers() {
+        assert_eq!(format_key("name"), "name");
+        assert_eq!(format_key("_private$"), "_private$");
+        assert_eq!(format_key("kebab-case"), "'kebab-case'");
+        assert_eq!(format_key("1st"), "'1st'");
+        assert_eq!(format_key(""), "''");
+    }
+
+    #[test]
+    fn format_date_is_iso_in_utc() {
+        assert_eq!(format_date(0.0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(format_date(-1.0), "1969-12-31T23:59:59.999Z");
+        assert_eq!(format_date(951_782_400_000.0), "2000-02-29T00:00:00.000Z");
+        assert_eq!(format_date(1_700_000_000_123.0), "2023-11-14T22:13:20.123Z");
+        assert_eq!(format_date(f64::NAN), "Invalid Date");
+    }
+
+    #[test]
+    fn wrap_breaks_long_entries() {
+        assert_eq!(wrap("", "[", "]", &[], 0), "[]");
+        assert_eq!(
+            wrap("Map(1)", "{", "}", &["'a' => 1".to_string()], 0),
+            "Map(1) { 'a' => 1 }"
+        );
+
+        let long = vec!["a".repeat(40), "b".repeat(40)];
+
+        assert_eq!(
+            wrap("", "[", "]", &long, 1),
+            format!("[\n    {},\n    {}\n  ]", long[0], long[1])
+        );
+    }
+}
@@ -0,0 +1,532 @@
+//! `zmake:console`(and `node:console`), the console of scripts.
+//!
+//! The messages are written to `tracing`, with the location of script as a field. The time
+//! measured by `console.time` is only written to the log, so scripts can not observe it.
+
+pub mod inspect;
+
+use crate::builtin::js::{script_location, script_stack};
+use crate::engine::State;
+use crate::make_builtin::{SyscallError, SyscallParameter, TypeScriptType};
+use crate::make_builtin_js;
+use crate::module_loader::ModuleLoadError;
+use crate::module_specifier::ModuleSpecifier;
+use ahash::AHashMap;
+use serde::Deserialize;
+use std::cell::{Cell, RefCell};
+use std::time::Instant;
+use v8::{Global, Local, PinScope};
+
+#[::static_init::dynamic(lazy)]
+pub static CONSOLE: ModuleSpecifier = ModuleSpecifier::Builtin("console".to_string());
+
+make_builtin_js!(
+    typed:
+    {
+        trace: [Project, Rule, Script] (data: Data) -> (),
+        debug: [Project, Rule, Script] (data: Data) -> (),
+        info: [Project, Rule, Script] (data: Data) -> (),
+        log: [Project, Rule, Script] (data: Data) -> (),
+        warn: [Project, Rule, Script] (data: Data) -> (),
+        error: [Project, Rule, Script] (data: Data) -> (),
+        dir: [Project, Rule, Script] (item: Item) -> (),
+        assert: [Project, Rule, Script] (condition: Condition, data: Data) -> (),
+        count: [Project, Rule, Script] (label: Label) -> (),
+        count_reset as "countReset": [Project, Rule, Script] (label: Label) -> (),
+        group: [Project, Rule, Script] (label: Data) -> (),
+        group_collapsed as "groupCollapsed": [Project, Rule, Script] (label: Data) -> (),
+        group_end as "groupEnd": [Project, Rule, Script] () -> (),
+        time: [Project, Rule, Script] (label: Label) -> (),
+        time_log as "timeLog": [Project, Rule, Script] (label: Label, data: Data) -> (),
+        time_end as "timeEnd": [Project, Rule, Script] (label: Label) -> ()
+    }
+    accessors:
+    {
+        console as "default" => "const console: Console;\n    export default console;"
+    }
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl TypeScriptType for Level {
+    fn typescript_type() -> String {
+        "\"trace\" | \"debug\" | \"info\" | \"warn\" | \"error\"".to_string()
+    }
+}
+
+/// Write a message of script to `tracing`.
+pub(crate) fn emit(level: Level, location: Option<&str>, message: &str) {
+    match level {
+        Level::Trace => tracing::trace!(location, "FROM SCRIPT {}", message),
+        Level::Debug => tracing::debug!(location, "FROM SCRIPT {}", message),
+        Level::Info => tracing::info!(location, "FROM SCRIPT {}", message),
+        Level::Warn => tracing::warn!(location, "FROM SCRIPT {}", message),
+        Level::Error => tracing::error!(location, "FROM SCRIPT {}", message),
+    }
+}
+
+/// The counters, timers and group of the console of an engine.
+#[derive(Debug, Default)]
+pub struct ConsoleState {
+    group_depth: Cell<usize>,
+    counters: RefCell<AHashMap<String, u64>>,
+    timers: RefCell<AHashMap<String, Instant>>,
+}
+
+impl ConsoleState {
+    /// Forget the counters, timers and groups, so an evaluation does not see earlier ones.
+    pub fn reset(&self) {
+        self.group_depth.set(0);
+        self.counters.borrow_mut().clear();
+        self.timers.borrow_mut().clear();
+    }
+}
+
+fn write<'s, 'i>(scope: &mut PinScope<'s, 'i>, level: Level, message: &str) {
+    let location = script_location(scope);
+
+    let depth = scope
+        .get_current_context()
+        .get_slot::<State>()
+        .map_or(0, |state| state.console.group_depth.get());
+
+    if depth == 0 {
+        return emit(level, location.as_deref(), message);
+    }
+
+    let indentation = "  ".repeat(depth);
+    let message = message
+        .lines()
+        .map(|line| format!("{}{}", indentation, line))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    emit(level, location.as_deref(), &message);
+}
+
+/// The rest arguments of console methods, they are formatted like `console.log` when written.
+pub struct Data(Vec<Global<v8::Value>>);
+
+impl SyscallParameter for Data {
+    fn take<'s, 'i>(
+        scope: &mut PinScope<'s, 'i>,
+        args: &::v8::FunctionCallbackArguments<'s>,
+        index: i32,
+        _name: &str,
+        _syscall: &str,
+    ) -> Option<Self> {
+        Some(Data(
+            (index..args.length())
+                .map(|index| Global::new(scope, args.get(index)))
+                .collect(),
+        ))
+    }
+
+    fn typescript_parameter(name: &str) -> String {
+        format!("...{}: any[]", name)
+    }
+}
+
+impl Data {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// An exception thrown by getters is ignored.
+    fn format<'s, 'i>(&self, scope: &mut PinScope<'s, 'i>) -> String {
+        let try_catch = std::pin::pin!(v8::TryCatch::new(scope));
+        let try_catch = &mut try_catch.init();
+
+        let values: Vec<Local<v8::Value>> = self
+            .0
+            .iter()
+            .map(|value| Local::new(try_catch, value))
+            .collect();
+
+        inspect::format_arguments(try_catch, &values)
+    }
+}
+
+/// Any value, it is formatted by `console.dir`.
+pub struct Item(Global<v8::Value>);
+
+impl SyscallParameter for Item {
+    fn take<'s, 'i>(
+        scope: &mut PinScope<'s, 'i>,
+        args: &::v8::FunctionCallbackArguments<'s>,
+        index: i32,
+        _name: &str,
+        _syscall: &str,
+    ) -> Option<Self> {
+        Some(Item(Global::new(scope, args.get(index))))
+    }
+
+    fn typescript_parameter(name: &str) -> String {
+        format!("{}?: any", name)
+    }
+}
+
+/// The condition of `console.assert`, it is converted like `Boolean(condition)`.
+pub struct Condition(bool);
+
+impl SyscallParameter for Condition {
+    fn take<'s, 'i>(
+        scope: &mut PinScope<'s, 'i>,
+        args: &::v8::FunctionCallbackArguments<'s>,
+        index: i32,
+        _name: &str,
+        _syscall: &str,
+    ) -> Option<Self> {
+        Some(Condition(args.get(index).boolean_value(scope)))
+    }
+
+    fn typescript_parameter(name: &str) -> String {
+        format!("{}?: boolean", name)
+    }
+}
+
+/// The label of counters and timers, `default` if it is not given.
+///
+/// A label of other type is inspected instead of rejected, like the console of browsers.
+pub struct Label(String);
+
+impl SyscallParameter for Label {
+    fn take<'s, 'i>(
+        scope: &mut PinScope<'s, 'i>,
+        args: &::v8::FunctionCallbackArguments<'s>,
+        index: i32,
+        _name: &str,
+        _syscall: &str,
+    ) -> Option<Self> {
+        let label = args.get(index);
+
+        let label = if label.is_undefined() {
+            "default".to_string()
+        } else if label.is_string() {
+            label.to_rust_string_lossy(scope)
+        } else {
+            let try_catch = std::pin::pin!(v8::TryCatch::new(scope));
+            let try_catch = &mut try_catch.init();
+
+            inspect::inspect(try_catch, label)
+        };
+
+        Some(Label(label))
+    }
+
+    fn typescript_parameter(name: &str) -> String {
+        format!("{}?: string", name)
+    }
+}
+
+fn print<'s, 'i>(
+    scope: &mut PinScope<'s, 'i>,
+    data: &Data,
+    level: Level,
+) -> Result<(), SyscallError> {
+    let message = data.format(scope);
+    write(scope, level, &message);
+
+    Ok(())
+}
+
+/// The frames of `console.trace`, like the default `Error.stackTraceLimit`.
+const TRACE_FRAMES: usize = 10;
+
+/// Like Node, the message is followed by the stack of the script.
+pub fn trace<'s, 'i>(scope: &mut PinScope<'s, 'i>, data: Data) -> Result<(), SyscallError> {
+    let mut message = if data.is_empty() {
+        "Trace".to_string()
+    } else {
+        format!("Trace: {}", data.format(scope))
+    };
+
+    for frame in script_stack(scope, TRACE_FRAMES) {
+        match frame.function {
+            Some(function) => {
+                message.push_str(&format!("\n    at {} ({})", function, frame.location))
+            }
+            None => message.push_str(&format!("\n    at {}", frame.location)),
+        }
+    }
+
+    write(scope, Level::Trace, &message);
+
+    Ok(())
+}
+
+pub fn debug<'s, 'i>(scope: &mut PinScope<'s, 'i>, data: Data) -> Result<(), SyscallError> {
+    print(scope, &data, Level::Debug)
+}
+
+pub fn info<'s, 'i>(scope: &mut PinScope<'s, 'i>, data: Data) -> Result<(), SyscallError> {
+    print(scope, &data, Level::Info)
+}
+
+pub fn log<'s, 'i>(scope: &mut PinScope<'s, 'i>, data: Data) -> Result<(), SyscallError> {
+    print(scope, &data, Level::Info)
+}
+
+pub fn warn<'s, 'i>(scope: &mut PinScope<'s, 'i>, data: Data) -> Result<(), SyscallError> {
+    print(scope, &data, Level::Warn)
+}
+
+pub fn error<'s, 'i>(scope: &mut PinScope<'s, 'i>, data: Data) -> Result<(), SyscallError> {
+    print(scope, &data, Level::Error)
+}
+
+/// Unlike `log`, a string is quoted.
+pub fn dir<'s, 'i>(scope: &mut PinScope<'s, 'i>, item: Item) -> Result<(), SyscallError> {
+    let message = {
+        let try_catch = std::pin::pin!(v8::TryCatch::new(scope));
+        let try_catch = &mut try_catch.init();
+
+        let item = Local::new(try_catch, &item.0);
+        inspect::inspect(try_catch, item)
+    };
+
+    write(scope, Level::Info, &message);
+
+    Ok(())
+}
+
+pub fn assert<'s, 'i>(
+    scope: &mut PinScope<'s, 'i>,
+    condition: Condition,
+    data: Data,
+) -> Result<(), SyscallError> {
+    if condition.0 {
+        return Ok(());
+    }
+
+    let message = if data.is_empty() {
+        "Assertion failed".to_string()
+    } else {
+        format!("Assertion failed: {}", data.format(scope))
+    };
+
+    write(scope, Level::Error, &message);
+
+    Ok(())
+}
+
+pub fn count<'s, 'i>(scope: &mut PinScope<'s, 'i>, label: Label) -> Result<(), SyscallError> {
+    let Label(label) = label;
+
+    let Some(state) = scope.get_current_context().get_slot::<State>() else {
+        return Err(SyscallError::Error(
+            "failed to get the engine state".to_string(),
+        ));
+    };
+
+    let count = {
+        let mut counters = state.console.counters.borrow_mut();
+        let count = counters.entry(label.clone()).or_default();
+        *count += 1;
+        *count
+    };
+
+    write(scope, Level::Info, &format!("{}: {}", label, count));
+
+    Ok(())
+}
+
+pub fn count_reset<'s, 'i>(scope: &mut PinScope<'s, 'i>, label: Label) -> Result<(), SyscallError> {
+    let Label(label) = label;
+
+    let Some(state) = scope.get_current_context().get_slot::<State>() else {
+        return Err(SyscallError::Error(
+            "failed to get the engine state".to_string(),
+        ));
+    };
+
+    if state.console.counters.borrow_mut().remove(&label).is_none() {
+        write(
+            scope,
+            Level::Warn,
+            &format!("Count for '{}' does not exist", label),
+        );
+    }
+
+    Ok(())
+}
+
+pub fn group<'s, 'i>(scope: &mut PinScope<'s, 'i>, label: Data) -> Result<(), SyscallError> {
+    if !label.is_empty() {
+        print(scope, &label, Level::Info)?;
+    }
+
+    let Some(state) = scope.get_current_context().get_slot::<State>() else {
+        return Err(SyscallError::Error(
+            "failed to get the engine state".to_string(),
+        ));
+    };
+
+    state
+        .console
+        .group_depth
+        .set(state.console.group_depth.get() + 1);
+
+    Ok(())
+}
+
+/// Logs can not be collapsed, it is the same as `group`.
+pub fn group_collapsed<'s, 'i>(
+    scope: &mut PinScope<'s, 'i>,
+    label: Data,
+) -> Result<(), SyscallError> {
+    group(scope, label)
+}
+
+pub fn group_end<'s, 'i>(scope: &mut PinScope<'s, 'i>) -> Result<(), SyscallError> {
+    let Some(state) = scope.get_current_context().get_slot::<State>() else {
+        return Err(SyscallError::Error(
+            "failed to get the engine state".to_string(),
+        ));
+    };
+
+    state
+        .console
+        .group_depth
+        .set(state.console.group_depth.get().saturating_sub(1));
+
+    Ok(())
+}
+
+pub fn time<'s, 'i>(scope: &mut PinScope<'s, 'i>, label: Label) -> Result<(), SyscallError> {
+    let Label(label) = label;
+
+    let Some(state) = scope.get_current_context().get_slot::<State>() else {
+        return Err(SyscallError::Error(
+            "failed to get the engine state".to_string(),
+        ));
+    };
+
+    let exists = state.console.timers.borrow().contains_key(&label);
+
+    if exists {
+        write(
+            scope,
+            Level::Warn,
+            &format!("Timer '{}' already exists", label),
+        );
+    } else {
+        state
+            .console
+            .timers
+            .borrow_mut()
+            .insert(label, Instant::now());
+    }
+
+    Ok(())
+}
+
+/// Write the elapsed time of timer with the data, and stop it if `end`.
+fn write_elapsed<'s, 'i>(
+    scope: &mut PinScope<'s, 'i>,
+    label: Label,
+    data: Option<Data>,
+    end: bool,
+) -> Result<(), SyscallError> {
+    let Label(label) = label;
+
+    let Some(state) = scope.get_current_context().get_slot::<State>() else {
+        return Err(SyscallError::Error(
+            "failed to get the engine state".to_string(),
+        ));
+    };
+
+    let start = if end {
+        state.console.timers.borrow_mut().remove(&label)
+    } else {
+        state.console.timers.borrow().get(&label).copied()
+    };
+
+    let Some(start) = start else {
+        write(
+            scope,
+            Level::Warn,
+            &format!("Timer '{}' does not exist", label),
+        );
+        return Ok(());
+    };
+
+    let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+
+    let message = match data {
+        Some(data) if !data.is_empty() => {
+            format!("{}: {:.3}ms {}", label, elapsed, data.format(scope))
+        }
+        _ => format!("{}: {:.3}ms", label, elapsed),
+    };
+
+    write(scope, Level::Info, &message);
+
+    Ok(())
+}
+
+pub fn time_log<'s, 'i>(
+    scope: &mut PinScope<'s, 'i>,
+    label: Label,
+    data: Data,
+) -> Result<(), SyscallError> {
+    write_elapsed(scope, label, Some(data), false)
+}
+
+pub fn time_end<'s, 'i>(scope: &mut PinScope<'s, 'i>, label: Label) -> Result<(), SyscallError> {
+    write_elapsed(scope, label, None, true)
+}
+
+fn set_method<'s, 'i>(
+    scope: &mut PinScope<'s, 'i>,
+    object: Local<'s, v8::Object>,
+    name: &'static str,
+    callback: impl v8::MapFnTo<v8::FunctionCallback>,
+) -> Result<(), ModuleLoadError> {
+    let key = v8::String::new(scope, name).ok_or(ModuleLoadError::V8ObjectAllocationError(
+        "failed to create method name",
+    ))?;
+
+    let function = v8::Function::new(scope, callback).ok_or(
+        ModuleLoadError::V8ObjectAllocationError("failed to create function"),
+    )?;
+
+    match object.set(scope, key.into(), function.into()) {
+        Some(true) => Ok(()),
+        _ => Err(ModuleLoadError::V8SyntheticModuleBuildingError(name)),
+    }
+}
+
+/// The default export, an object with every function of console.
+pub fn console<'s, 'i>(
+    scope: &mut PinScope<'s, 'i>,
+) -> Result<Local<'s, v8::Value>, ModuleLoadError> {
+    let object = v8::Object::new(scope);
+
+    set_method(scope, object, "trace", guarded::trace)?;
+    set_method(scope, object, "debug", guarded::debug)?;
+    set_method(scope, object, "info", guarded::info)?;
+    set_method(scope, object, "log", guarded::log)?;
+    set_method(scope, object, "warn", guarded::warn)?;
+    set_method(scope, object, "error", guarded::error)?;
+    set_method(scope, object, "dir", guarded::dir)?;
+    set_method(scope, object, "assert", guarded::assert)?;
+    set_method(scope, object, "count", guarded::count)?;
+    set_method(scope, object, "countReset", guarded::count_reset)?;
+    set_method(scope, object, "group", guarded::group)?;
+    set_method(scope, object, "groupCollapsed", guarded::group_collapsed)?;
+    set_method(scope, object, "groupEnd", guarded::group_end)?;
+    set_method(scope, object, "time", guarded::time)?;
+    set_method(scope, object, "timeLog", guarded::time_log)?;
+    set_method(scope, object, "timeEnd", guarded::time_end)?;
+
+    Ok(object.into())
+}
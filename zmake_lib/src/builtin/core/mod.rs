@@ -0,0 +1,39 @@
+//! `zmake:core`, the functions shared by every kind of script.
+
+use crate::builtin::js::script_location;
+use crate::make_builtin::SyscallError;
+use crate::make_builtin_js;
+use crate::module_specifier::ModuleSpecifier;
+use crate::version_requirement::{VersionRequirementError, check_zmake_version};
+use v8::PinScope;
+
+#[::static_init::dynamic(lazy)]
+pub static CORE: ModuleSpecifier = ModuleSpecifier::Builtin("core".to_string());
+
+make_builtin_js!(
+    typed:
+    {
+        require_zmake_version as "requireZMakeVersion": [Project, Rule, Script] (range: String, package_name: Option<String>) -> ()
+    }
+    accessors:
+    {
+    }
+);
+
+/// Throw an `Error` if the running zmake does not satisfy the range.
+///
+/// The package is the calling script if it is not given.
+pub fn require_zmake_version<'s, 'i>(
+    scope: &mut PinScope<'s, 'i>,
+    range: String,
+    package_name: Option<String>,
+) -> Result<(), SyscallError> {
+    let package = package_name
+        .or_else(|| script_location(scope))
+        .unwrap_or_else(|| "unknown script".to_string());
+
+    check_zmake_version(&package, &range).map_err(|err| match err {
+        VersionRequirementError::InvalidRange { .. } => SyscallError::TypeError(err.to_string()),
+        VersionRequirementError::Unsatisfied { .. } => SyscallError::Error(err.to_string()),
+    })
+}
@@ -0,0 +1,381 @@
+//! `zmake:fs`, read-only access to the files of the project.
+//!
+//! Every path is relative to the project root and goes through the sandbox, so the access is
+//! recorded as an input of the script. The files are read on the tokio runtime.
+
+use crate::engine::State;
+use crate::make_builtin::{Json, Promise, SyscallError, TypeScriptType, Uint8Array};
+use crate::make_builtin_js;
+use crate::module_specifier::ModuleSpecifier;
+use crate::path::NeutralPath;
+use crate::sandbox::{EntryKind, Sandbox, SandboxError};
+use serde::Serialize;
+use std::io::{ErrorKind, Read};
+use std::sync::Arc;
+use tracing::warn;
+
+#[::static_init::dynamic(lazy)]
+pub static FS: ModuleSpecifier = ModuleSpecifier::Builtin("fs".to_string());
+
+make_builtin_js!(
+    typed:
+    {
+        read_text as "readText": [Rule, Script] (path: String) -> Promise<String>,
+        read_bytes as "readBytes": [Rule, Script] (path: String) -> Promise<Uint8Array>,
+        stat: [Rule, Script] (path: String) -> Promise<Json<Stat>>,
+        read_dir as "readDir": [Rule, Script] (path: String) -> Promise<Json<Vec<Entry>>>,
+        glob: [Rule, Script] (pattern: String) -> Promise<Json<Vec<String>>>
+    }
+    accessors:
+    {
+    }
+);
+
+/// The kind of file, symlinks are not followed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+impl From<EntryKind> for Kind {
+    fn from(kind: EntryKind) -> Self {
+        match kind {
+            EntryKind::File => Kind::File,
+            EntryKind::Directory => Kind::Directory,
+            EntryKind::Symlink => Kind::Symlink,
+            EntryKind::Other => Kind::Other,
+        }
+    }
+}
+
+impl TypeScriptType for Kind {
+    fn typescript_type() -> String {
+        "\"file\" | \"directory\" | \"symlink\" | \"other\"".to_string()
+    }
+}
+
+/// The result of `stat`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Stat {
+    kind: Kind,
+    size: u64,
+    readonly: bool,
+}
+
+impl TypeScriptType for Stat {
+    fn typescript_type() -> String {
+        format!(
+            "{{ kind: {}; size: number; readonly: boolean }}",
+            Kind::typescript_type()
+        )
+    }
+}
+
+/// An entry of `readDir`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    name: String,
+    kind: Kind,
+}
+
+impl TypeScriptType for Entry {
+    fn typescript_type() -> String {
+        format!("{{ name: string; kind: {} }}", Kind::typescript_type())
+    }
+}
+
+/// Run the work with the sandbox on the tokio runtime.
+fn spawn_with_sandbox<'s, 'i, T, F>(
+    scope: &mut ::v8::PinScope<'s, 'i>,
+    work: F,
+) -> Result<Promise<T>, SyscallError>
+where
+    F: FnOnce(&Sandbox) -> Result<T, String> + Send + 'static,
+{
+    let Some(state) = scope.get_current_context().get_slot::<State>() else {
+        return Err(SyscallError::Error(
+            "failed to get the engine state".to_string(),
+        ));
+    };
+
+    let sandbox: Arc<Sandbox> = state.module_loader.get_sandbox();
+
+    Ok(Promise::blocking(move || work(&sandbox)))
+}
+
+fn read(sandbox: &Sandbox, path: &str) -> Result<Vec<u8>, String> {
+    let path = NeutralPath::new(path).map_err(|err| err.to_string())?;
+
+    let mut data = Vec::new();
+
+    sandbox
+        .open_file(&path)
+        .map_err(|err| format!("failed to open `{}`: {}", path, err))?
+        .read_to_end(&mut data)
+        .map_err(|err| format!("failed to read `{}`: {}", path, err))?;
+
+    Ok(data)
+}
+
+pub fn read_text<'s, 'i>(
+    scope: &mut ::v8::PinScope<'s, 'i>,
+    path: String,
+) -> Result<Promise<String>, SyscallError> {
+    spawn_with_sandbox(scope, move |sandbox| {
+        String::from_utf8(read(sandbox, &path)?)
+            .map_err(|_| format!("`{}` is not valid utf-8", path))
+    })
+}
+
+pub fn read_bytes<'s, 'i>(
+    scope: &mut ::v8::PinScope<'s, 'i>,
+    path: String,
+) -> Result<Promise<Uint8Array>, SyscallError> {
+    spawn_with_sandbox(scope, move |sandbox| Ok(Uint8Array(read(sandbox, &path)?)))
+}
+
+/// The modification time is not reported, it is not deterministic. Symlinks are not followed.
+pub fn stat<'s, 'i>(
+    scope: &mut ::v8::PinScope<'s, 'i>,
+    path: String,
+) -> Result<Promise<Json<Stat>>, SyscallError> {
+    spawn_with_sandbox(scope, move |sandbox| {
+        let path = NeutralPath::new(&path).map_err(|err| err.to_string())?;
+
+        let stat = sandbox
+            .stat(&path)
+            .map_err(|err| format!("failed to stat `{}`: {}", path, err))?;
+
+        Ok(Json(Stat {
+            kind: stat.kind.into(),
+            size: stat.size,
+            readonly: stat.readonly,
+        }))
+    })
+}
+
+pub fn read_dir<'s, 'i>(
+    scope: &mut ::v8::PinScope<'s, 'i>,
+    path: String,
+) -> Result<Promise<Json<Vec<Entry>>>, SyscallError> {
+    spawn_with_sandbox(scope, move |sandbox| {
+        let path = NeutralPath::new(&path).map_err(|err| err.to_string())?;
+
+        let entries = sandbox
+            .read_directory(&path)
+            .map_err(|err| format!("failed to read directory `{}`: {}", path, err))?;
+
+        Ok(Json(
+            entries
+                .into_iter()
+                .map(|entry| Entry {
+                    name: entry.name,
+                    kind: entry.kind.into(),
+                })
+                .collect(),
+        ))
+    })
+}
+
+/// The directories that `**` does not walk into, they are not sources of the project.
+const SKIPPED_DIRECTORIES: &[&str] = &[".git", "node_modules"];
+
+/// The default cache directory, it is skipped like [SKIPPED_DIRECTORIES].
+const CACHE_DIRECTORY: &str = ".zmake/cache";
+
+/// `depth` is the count of components that the pattern matches, `None` for `**`.
+fn walk(
+    sandbox: &Sandbox,
+    directory: &NeutralPath,
+    pattern: &::glob::Pattern,
+    depth: Option<usize>,
+    matches: &mut Vec<String>,
+) -> Result<(), SandboxError> {
+    let options = ::glob::MatchOptions {
+        case_sensitive: true,
+        require_literal_separator: true,
+        require_literal_leading_dot: false,
+    };
+
+    for entry in sandbox.read_directory(directory)? {
+        // the name is not portable, it can not be a NeutralPath
+        let Ok(path) = directory.join(&entry.name) else {
+            continue;
+        };
+
+        if pattern.matches_with(path.as_ref(), options) {
+            matches.push(path.to_string());
+        }
+
+        if entry.kind != EntryKind::Directory {
+            continue;
+        }
+
+        // do not list the directories that can not match, they would be recorded as inputs
+        let descend = match depth {
+            Some(depth) => path.components().count() < depth,
+            None => {
+                !SKIPPED_DIRECTORIES.contains(&entry.name.as_str())
+                    && path.to_string() != CACHE_DIRECTORY
+            }
+        };
+
+        // an unreadable directory matches nothing, like the glob of shells
+        if descend && let Err(err) = walk(sandbox, &path, pattern, depth, matches) {
+            warn!("skip the directory `{}` in glob: {}", path, err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Match the pattern against the files of the project, symlinks are not followed.
+fn glob_files(sandbox: &Sandbox, pattern: &str) -> Result<Vec<String>, String> {
+    let pattern = pattern.strip_prefix("./").unwrap_or(pattern);
+
+    let matcher = ::glob::Pattern::new(pattern)
+        .map_err(|err| format!("invalid glob pattern `{}`: {}", pattern, err))?;
+
+    // only walk the directory before the first component with wildcards
+    let mut base = NeutralPath::current_dir();
+
+    if let Some((directories, _)) = pattern.rsplit_once('/') {
+        for component in directories.split('/') {
+            if component.contains(['*', '?', '[']) {
+                break;
+            }
+
+            base = base.join(component).map_err(|err| err.to_string())?;
+        }
+    }
+
+    let depth = if pattern.contains("**") {
+        None
+    } else {
+        Some(pattern.split('/').count())
+    };
+
+    let mut matches = Vec::new();
+
+    match walk(sandbox, &base, &matcher, depth, &mut matches) {
+        Ok(()) => Ok(matches),
+        // a missing base matches nothing, the listing is still recorded as an input
+        Err(SandboxError::IoError(err))
+            if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) =>
+        {
+            Ok(Vec::new())
+        }
+        Err(err) => Err(format!("failed to read directory `{}`: {}", base, err)),
+    }
+}
+
+/// Match the pattern against the files of the project, symlinks are not followed.
+///
+/// `**` does not walk into `.git`, `node_modules` and the cache directory.
+pub fn glob<'s, 'i>(
+    scope: &mut ::v8::PinScope<'s, 'i>,
+    pattern: String,
+) -> Result<Promise<Json<Vec<String>>>, SyscallError> {
+    spawn_with_sandbox(scope, move |sandbox| {
+        glob_files(sandbox, &pattern).map(Json)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn sandbox(files: &[&str]) -> eyre::Result<(TempDir, Sandbox)> {
+        let root = TempDir::new()?;
+
+        for name in files {
+            let path = root.path().join(name);
+
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            std::fs::write(path, "")?;
+        }
+
+        let sandbox = Sandbox::new(root.path())?;
+
+        Ok((root, sandbox))
+    }
+
+    #[test]
+    fn glob_matches_the_pattern() -> eyre::Result<()> {
+        let (_root, sandbox) = sandbox(&["a.ts", "src/b.ts", "src/c.js", "src/d/e.ts"])?;
+
+        let glob = |pattern| glob_files(&sandbox, pattern).map_err(|err| eyre::eyre!(err));
+
+        assert_eq!(glob("*.ts")?, ["a.ts"]);
+        assert_eq!(glob("./src/*.js")?, ["src/c.js"]);
+        assert_eq!(glob("src/*.ts")?, ["src/b.ts"]);
+        assert_eq!(glob("src/**/*.ts")?, ["src/b.ts", "src/d/e.ts"]);
+        assert_eq!(glob("*/d")?, ["src/d"]);
+        assert!(glob_files(&sandbox, "[").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn glob_skips_directories_that_are_not_sources() -> eyre::Result<()> {
+        let (_root, sandbox) = sandbox(&[
+            "src/a.ts",
+            ".git/hooks/b.ts",
+            "node_modules/pkg/c.ts",
+            "src/node_modules/d.ts",
+            ".zmake/cache/e.ts",
+            ".zmake/f.ts",
+        ])?;
+
+        let glob = |pattern| glob_files(&sandbox, pattern).map_err(|err| eyre::eyre!(err));
+
+        assert_eq!(glob("**/*.ts")?, [".zmake/f.ts", "src/a.ts"]);
+        // they are still matched when named literally
+        assert_eq!(glob("node_modules/*/*.ts")?, ["node_modules/pkg/c.ts"]);
+        assert_eq!(glob("node_modules/**/*.ts")?, ["node_modules/pkg/c.ts"]);
+        Ok(())
+    }
+
+    #[test]
+    fn glob_of_missing_base_is_empty() -> eyre::Result<()> {
+        let (_root, sandbox) = sandbox(&["file"])?;
+
+        let glob = |pattern| glob_files(&sandbox, pattern).map_err(|err| eyre::eyre!(err));
+
+        assert!(glob("missing/**/*.ts")?.is_empty());
+        assert!(glob("file/*.ts")?.is_empty());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn glob_skips_unreadable_directories() -> eyre::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (root, sandbox) = sandbox(&["src/a.ts", "src/locked/b.ts"])?;
+
+        let locked = root.path().join("src/locked");
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000))?;
+
+        let matches = glob_files(&sandbox, "**/*.ts").map_err(|err| eyre::eyre!(err));
+
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755))?;
+
+        // root reads the directory anyway
+        let expected: &[&str] = if unsafe { libc::geteuid() } == 0 {
+            &["src/a.ts", "src/locked/b.ts"]
+        } else {
+            &["src/a.ts"]
+        };
+
+        assert_eq!(matches?, expected);
+        Ok(())
+    }
+}
@@ -1,4 +1,8 @@
+use crate::id::{Id, IdType};
 use crate::make_builtin_id;
+use convert_case::{Case, Casing};
+use std::collections::BTreeMap;
+use strum::IntoEnumIterator;
 
 make_builtin_id! {
     pub mod c;
@@ -41,9 +45,174 @@ make_builtin_id! {
     }
 }
 
+/// The ids grouped by type, in the declaration order of `IdType`.
+fn group_by_type(builtin: &BTreeMap<String, Id>) -> Vec<(IdType, Vec<(&String, &Id)>)> {
+    IdType::iter()
+        .map(|id_type| {
+            let ids = builtin
+                .iter()
+                .filter(|(_, id)| id.get_type() == id_type)
+                .collect::<Vec<_>>();
+
+            (id_type, ids)
+        })
+        .filter(|(_, ids)| !ids.is_empty())
+        .collect()
+}
+
+/// `TargetType` for `IdType::TargetType`, the same as the templates in `core.d.ts`.
+fn type_name(id_type: IdType) -> String {
+    format!("{:?}", id_type)
+}
+
+fn type_mark(id_type: IdType) -> &'static str {
+    id_type.into()
+}
+
+/// The object literal of the ids, grouped by type.
+fn push_ids(typescript: &mut String, ids: &[(&String, &Id)], indentation: &str) {
+    for (key, id) in ids {
+        typescript.push_str(&format!("{}/** `{}` */\n", indentation, id));
+        typescript.push_str(&format!("{}{}: \"{}\",\n", indentation, key, id));
+    }
+}
+
+fn push_artifact(typescript: &mut String, name: &str, builtin: &BTreeMap<String, Id>) {
+    let groups = group_by_type(builtin);
+
+    typescript.push_str(&format!("/**\n * The builtin ids of `{}`.\n */\n", name));
+    typescript.push_str(&format!("export const {} = {{\n", name));
+
+    for (id_type, ids) in &groups {
+        typescript.push_str(&format!(
+            "    /**\n     * The builtin ids with type `{}`.\n     */\n",
+            type_mark(*id_type)
+        ));
+        typescript.push_str(&format!(
+            "    {}: {{\n",
+            type_name(*id_type).to_case(Case::Camel)
+        ));
+        push_ids(typescript, ids, "        ");
+        typescript.push_str("    },\n");
+    }
+
+    typescript.push_str("} as const satisfies {\n");
+
+    for (id_type, _) in &groups {
+        typescript.push_str(&format!(
+            "    {}: Record<string, core.{}>;\n",
+            type_name(*id_type).to_case(Case::Camel),
+            type_name(*id_type)
+        ));
+    }
+
+    typescript.push_str("};\n\n");
+
+    // a namespace with only types is erasable
+    typescript.push_str(&format!("export namespace {} {{\n", name));
+
+    for (id_type, _) in &groups {
+        let member = format!("{}.{}", name, type_name(*id_type).to_case(Case::Camel));
+
+        typescript.push_str(&format!(
+            "    /**\n     * The builtin ids of `{}` with type `{}`.\n     */\n",
+            name,
+            type_mark(*id_type)
+        ));
+        typescript.push_str(&format!(
+            "    export type {} = (typeof {})[keyof typeof {}];\n",
+            type_name(*id_type),
+            member,
+            member
+        ));
+    }
+
+    typescript.push_str("}\n\n");
+}
+
+/// The typescript package of builtin ids.
+///
+/// Every type of id has an object and a string literal type, like `os` and `Os`, which are
+/// checked against the templates in `core.d.ts`. The ids of submodules are in the namespace of
+/// submodule, like `c.tool` and `c.Tool`. The default export is the same as before, the ids
+/// by their names.
 pub fn construct_builtins_typescript_export() -> String {
-    let mut result = String::from("export default {\n");
-    result.push_str(&TYPESCRIPT_EXPORT);
-    result.push_str("}\n");
-    result
+    let mut typescript = String::from(
+        "/**\n * The builtin ids of zmake.\n *\n * Generated by `zmake export-builtin`, do not edit.\n */\n",
+    );
+    typescript.push_str("import type * as core from \"zmake:core\";\n\n");
+
+    let groups = group_by_type(&BUILTIN);
+
+    for (id_type, ids) in &groups {
+        let object = type_name(*id_type).to_case(Case::Camel);
+
+        typescript.push_str(&format!(
+            "/**\n * The builtin ids with type `{}`.\n */\n",
+            type_mark(*id_type)
+        ));
+        typescript.push_str(&format!("export const {} = {{\n", object));
+        push_ids(&mut typescript, ids, "    ");
+        typescript.push_str(&format!(
+            "}} as const satisfies Record<string, core.{}>;\n\n",
+            type_name(*id_type)
+        ));
+
+        typescript.push_str(&format!(
+            "/**\n * The builtin ids with type `{}`.\n */\n",
+            type_mark(*id_type)
+        ));
+        typescript.push_str(&format!(
+            "export type {} = (typeof {})[keyof typeof {}];\n\n",
+            type_name(*id_type),
+            object,
+            object
+        ));
+    }
+
+    let submodules = submodules();
+
+    for (name, builtin) in &submodules {
+        push_artifact(&mut typescript, name, builtin);
+    }
+
+    typescript.push_str("export default {\n");
+
+    for (id_type, _) in &groups {
+        typescript.push_str(&format!(
+            "    ...{},\n",
+            type_name(*id_type).to_case(Case::Camel)
+        ));
+    }
+
+    for (name, builtin) in &submodules {
+        let members = group_by_type(builtin)
+            .iter()
+            .map(|(id_type, _)| format!("...{}.{}", name, type_name(*id_type).to_case(Case::Camel)))
+            .collect::<Vec<_>>();
+
+        typescript.push_str(&format!("    {}: {{ {} }},\n", name, members.join(", ")));
+    }
+
+    typescript.push_str("};\n");
+    typescript
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The package in `zmake_js/builtin.ts` is generated, it must be exported again after
+    /// changing the builtin ids.
+    #[test]
+    fn typescript_export_is_up_to_date() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../zmake_js/builtin.ts");
+
+        assert_eq!(
+            std::fs::read_to_string(path).ok(),
+            Some(construct_builtins_typescript_export()),
+            "{} is outdated",
+            path
+        );
+    }
 }
@@ -1,8 +1,11 @@
-use tokio::sync::watch::error;
-use tracing::{debug, error, info, trace, warn};
-use tracing_subscriber::field::debug;
-
+use crate::builtin::console::{Level, emit};
+use crate::chunking::digest_of;
+use crate::engine::State;
+use crate::make_builtin::{Promise, SyscallError};
+use crate::module_specifier::BUILTIN_MODULE_PREFIX;
+use crate::path::NeutralPath;
 use crate::{make_builtin_js, module_loader::ModuleLoadError, module_specifier::ModuleSpecifier};
+use std::io::Read;
 
 pub static RT_CODE: &'static str =
     include_str!(concat!(std::env!("CARGO_MANIFEST_DIR"), "/../dist/rt.js"));
@@ -17,63 +20,132 @@ pub static SYSCALL: ModuleSpecifier = ModuleSpecifier::Builtin("syscall".to_stri
  *  To modify the name of method,remeber to modify it in js file too.
  */
 make_builtin_js!(
-    syscalls:{
-        log
+    typed:
+    {
+        digest: [Rule, Script] (path: String) -> Promise<String>,
+        log: [Project, Rule, Script] (level: Level, message: String) -> ()
     }
     accessors:
     {
-        version
+        version => "export const version: string;"
     }
 );
 
-pub fn log<'s, 'i>(
+/// Get `file:line:column` of the script calling the builtin, in the original source.
+pub(crate) fn script_location<'s, 'i>(scope: &mut ::v8::PinScope<'s, 'i>) -> Option<String> {
+    script_stack(scope, 16)
+        .into_iter()
+        .next()
+        .map(|frame| frame.location)
+}
+
+/// A frame of the script stack, its location is in the original source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ScriptFrame {
+    /// `None` for the top level of modules and anonymous functions.
+    pub function: Option<String>,
+    /// `file:line:column`.
+    pub location: String,
+}
+
+/// Get the stack of the script calling the builtin, the innermost frame first.
+///
+/// The frames of the runtime, like `console.log`, are skipped.
+pub(crate) fn script_stack<'s, 'i>(
     scope: &mut ::v8::PinScope<'s, 'i>,
-    args: ::v8::FunctionCallbackArguments<'s>,
-    mut return_value: ::v8::ReturnValue<'s, v8::Value>,
-) {
-    let level = args
-        .get(0)
-        .to_string(scope)
-        .unwrap()
-        .to_rust_string_lossy(scope);
-    let message = args
-        .get(1)
-        .to_string(scope)
-        .unwrap()
-        .to_rust_string_lossy(scope);
-
-    match level.as_ref() {
-        "trace" => {
-            trace!("FROM SCRIPT {}", message);
-        }
-        "debug" => {
-            debug!("FROM SCRIPT {}", message);
-        }
-        "info" => {
-            info!("FROM SCRIPT {}", message);
-        }
-        "warn" => {
-            warn!("FROM SCRIPT {}", message);
-        }
-        "error" => {
-            error!("FROM SCRIPT {}", message);
-        }
-        _ => {
-            error!("UNKNOWN LOG LEVEL FROM SCRIPT {}", message);
+    limit: usize,
+) -> Vec<ScriptFrame> {
+    let Some(stack_trace) = v8::StackTrace::current_stack_trace(scope, limit) else {
+        return Vec::new();
+    };
+
+    let mut frames = Vec::new();
+
+    for index in 0..stack_trace.get_frame_count() {
+        let Some(frame) = stack_trace.get_frame(scope, index) else {
+            continue;
+        };
+        let Some(file) = frame.get_script_name(scope) else {
+            continue;
+        };
+        let file = file.to_rust_string_lossy(scope);
+
+        if file.starts_with(BUILTIN_MODULE_PREFIX) {
+            continue;
         }
+
+        let (line, column) = match scope.get_current_context().get_slot::<State>() {
+            Some(state) => state.module_loader.remap_position(
+                &file,
+                frame.get_line_number(),
+                frame.get_column(),
+            ),
+            None => (frame.get_line_number(), frame.get_column()),
+        };
+
+        frames.push(ScriptFrame {
+            function: frame
+                .get_function_name(scope)
+                .map(|name| name.to_rust_string_lossy(scope))
+                .filter(|name| !name.is_empty()),
+            location: format!("{}:{}:{}", file, line, column),
+        });
     }
 
-    return_value.set_undefined();
+    frames
+}
+
+pub fn log<'s, 'i>(
+    scope: &mut ::v8::PinScope<'s, 'i>,
+    level: Level,
+    message: String,
+) -> Result<(), SyscallError> {
+    let location = script_location(scope);
+
+    emit(level, location.as_deref(), &message);
+
+    Ok(())
 }
 
 pub fn version<'s, 'i>(
     scope: &mut ::v8::PinScope<'s, 'i>,
 ) -> Result<v8::Local<'s, v8::Value>, ModuleLoadError> {
-    Ok(v8::String::new(scope, env!("CARGO_PKG_VERSION"))
-        .ok_or_else(|| {
-            crate::module_loader::ModuleLoadError::V8ObjectAllocationError(
-                "failed to create string",
-            )
-        })?
-        .into())
+    Ok(v8::String::new(
+        scope,
+        &crate::version_requirement::ZMAKE_VERSION.to_string(),
+    )
+    .ok_or_else(|| {
+        crate::module_loader::ModuleLoadError::V8ObjectAllocationError("failed to create string")
+    })?
+    .into())
+}
+
+/// Get the digest of a file in the project, the file is read on the tokio runtime.
+///
+/// Return a promise of the hex xxhash3-128 digest.
+pub fn digest<'s, 'i>(
+    scope: &mut ::v8::PinScope<'s, 'i>,
+    path: String,
+) -> Result<Promise<String>, SyscallError> {
+    let Some(state) = scope.get_current_context().get_slot::<State>() else {
+        return Err(SyscallError::Error(
+            "failed to get the engine state".to_string(),
+        ));
+    };
+
+    let sandbox = state.module_loader.get_sandbox();
+
+    Ok(Promise::blocking(move || {
+        let path = NeutralPath::new(&path).map_err(|err| err.to_string())?;
+
+        let mut data = Vec::new();
+
+        sandbox
+            .open_file(&path)
+            .map_err(|err| err.to_string())?
+            .read_to_end(&mut data)
+            .map_err(|err| format!("failed to read `{}`: {}", path, err))?;
+
+        Ok(digest_of(&data).hex_fast_xxhash3_128())
+    }))
 }
@@ -1,2 +1,101 @@
+use crate::engine::EngineMode;
+use crate::module_loader::ModuleLoadError;
+use crate::module_specifier::ModuleSpecifier;
+use v8::{Local, PinScope};
+
+pub mod console;
+pub mod core;
+pub mod fs;
 pub mod id;
 pub mod js;
+pub mod semver;
+
+/// A builtin module made by `make_builtin_js!`.
+pub struct SyntheticModule {
+    pub specifier: &'static ModuleSpecifier,
+    /// `v8::Module::create_synthetic_module` only takes the evaluation steps as a function
+    /// item, so every module creates itself.
+    pub create: for<'s, 'i> fn(
+        &PinScope<'s, 'i>,
+        &ModuleSpecifier,
+    ) -> Result<Local<'s, v8::Module>, ModuleLoadError>,
+    pub external_references: fn() -> Vec<v8::ExternalReference>,
+    pub typescript_declaration: fn(&str, EngineMode) -> String,
+}
+
+/// The order is the order of the startup snapshot and the typescript declaration.
+#[::static_init::dynamic(lazy)]
+pub static SYNTHETIC_MODULES: [SyntheticModule; 5] = [
+    SyntheticModule {
+        specifier: &js::SYSCALL,
+        create: js::create_module,
+        external_references: js::external_references,
+        typescript_declaration: js::typescript_declaration,
+    },
+    SyntheticModule {
+        specifier: &fs::FS,
+        create: fs::create_module,
+        external_references: fs::external_references,
+        typescript_declaration: fs::typescript_declaration,
+    },
+    SyntheticModule {
+        specifier: &console::CONSOLE,
+        create: console::create_module,
+        external_references: console::external_references,
+        typescript_declaration: console::typescript_declaration,
+    },
+    SyntheticModule {
+        specifier: &semver::SEMVER,
+        create: semver::create_module,
+        external_references: semver::external_references,
+        typescript_declaration: semver::typescript_declaration,
+    },
+    SyntheticModule {
+        specifier: &core::CORE,
+        create: core::create_module,
+        external_references: core::external_references,
+        typescript_declaration: core::typescript_declaration,
+    },
+];
+
+pub fn find_synthetic_module(specifier: &ModuleSpecifier) -> Option<&'static SyntheticModule> {
+    SYNTHETIC_MODULES
+        .iter()
+        .find(|module| module.specifier == specifier)
+}
+
+/// The typescript declaration of the builtin modules, with the syscalls available in the mode.
+pub fn typescript_declaration(mode: EngineMode) -> String {
+    SYNTHETIC_MODULES
+        .iter()
+        .map(|module| (module.typescript_declaration)(&module.specifier.to_string(), mode))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The declarations in `zmake_js/types` are generated, they must be exported again after
+    /// changing a builtin module.
+    #[test]
+    fn declarations_are_up_to_date() {
+        let types = concat!(env!("CARGO_MANIFEST_DIR"), "/../zmake_js/types");
+
+        for (mode, name) in [
+            (EngineMode::Project, "project"),
+            (EngineMode::Rule, "rule"),
+            (EngineMode::Script, "script"),
+        ] {
+            let path = format!("{}/builtin.{}.d.ts", types, name);
+
+            assert_eq!(
+                std::fs::read_to_string(&path).ok(),
+                Some(typescript_declaration(mode)),
+                "{} is outdated",
+                path
+            );
+        }
+    }
+}
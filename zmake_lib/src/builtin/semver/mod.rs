@@ -0,0 +1,213 @@
+//! `zmake:semver`, semantic versions for scripts, like the versions of tools.
+//!
+//! Ranges use the syntax of Cargo(see [`semver::VersionReq`]), like `^1.2` or `>=1.0, <2.0`.
+
+use crate::make_builtin::{SyscallError, TypeScriptType};
+use crate::make_builtin_js;
+use crate::module_specifier::ModuleSpecifier;
+use ::semver::{Version, VersionReq};
+use serde::Serialize;
+use v8::PinScope;
+
+#[::static_init::dynamic(lazy)]
+pub static SEMVER: ModuleSpecifier = ModuleSpecifier::Builtin("semver".to_string());
+
+make_builtin_js!(
+    typed:
+    {
+        parse: [Project, Rule, Script] (version: String) -> Option<SemVer>,
+        parse_lenient as "parseLenient": [Project, Rule, Script] (version: String) -> Option<SemVer>,
+        compare: [Project, Rule, Script] (a: String, b: String) -> Comparison,
+        satisfies: [Project, Rule, Script] (version: String, range: String) -> bool,
+        max_satisfying as "maxSatisfying": [Project, Rule, Script] (versions: Vec<String>, range: String) -> Option<String>
+    }
+    accessors:
+    {
+    }
+);
+
+/// The `SemVer` interface of `zmake_js/types/semver.d.ts`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SemVer {
+    major: u64,
+    minor: u64,
+    patch: u64,
+    prerelease: String,
+    build: String,
+    version: String,
+}
+
+impl TypeScriptType for SemVer {
+    fn typescript_type() -> String {
+        "SemVer".to_string()
+    }
+}
+
+impl From<Version> for SemVer {
+    fn from(version: Version) -> Self {
+        Self {
+            major: version.major,
+            minor: version.minor,
+            patch: version.patch,
+            prerelease: version.pre.to_string(),
+            build: version.build.to_string(),
+            version: version.to_string(),
+        }
+    }
+}
+
+/// `-1`, `0` or `1`.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Comparison(i32);
+
+impl TypeScriptType for Comparison {
+    fn typescript_type() -> String {
+        "-1 | 0 | 1".to_string()
+    }
+}
+
+/// Parse the version strictly, surrounding whitespace is ignored.
+fn parse_strict(version: &str) -> Option<Version> {
+    Version::parse(version.trim()).ok()
+}
+
+/// Parse the version like `1.2`, `v1.2.3.4` or `1.2.3-beta1` if it is not strict.
+fn parse_loose(version: &str) -> Option<Version> {
+    parse_strict(version).or_else(|| lenient_semver::parse(version.trim()).ok())
+}
+
+/// The versions of `compare`, `satisfies` and `maxSatisfying` are parsed leniently.
+fn parse_version(version: &str) -> Result<Version, SyscallError> {
+    parse_loose(version)
+        .ok_or_else(|| SyscallError::TypeError(format!("invalid version `{}`", version)))
+}
+
+fn parse_range(range: &str) -> Result<VersionReq, SyscallError> {
+    VersionReq::parse(range.trim()).map_err(|err| {
+        SyscallError::TypeError(format!("invalid version range `{}`: {}", range, err))
+    })
+}
+
+fn compare_versions(a: &str, b: &str) -> Result<Comparison, SyscallError> {
+    Ok(Comparison(parse_version(a)?.cmp(&parse_version(b)?) as i32))
+}
+
+fn satisfies_range(version: &str, range: &str) -> Result<bool, SyscallError> {
+    Ok(parse_range(range)?.matches(&parse_version(version)?))
+}
+
+fn max_satisfying_version(
+    versions: Vec<String>,
+    range: &str,
+) -> Result<Option<String>, SyscallError> {
+    let range = parse_range(range)?;
+
+    let max = versions
+        .into_iter()
+        .filter_map(|version| Some((parse_loose(&version)?, version)))
+        .filter(|(version, _)| range.matches(version))
+        .max_by(|(a, _), (b, _)| a.cmp(b));
+
+    Ok(max.map(|(_, version)| version))
+}
+
+/// Parse the version strictly, return `null` if it is invalid.
+pub fn parse<'s, 'i>(
+    _scope: &mut PinScope<'s, 'i>,
+    version: String,
+) -> Result<Option<SemVer>, SyscallError> {
+    Ok(parse_strict(&version).map(SemVer::from))
+}
+
+/// Parse the version like `1.2`, `v1.2.3.4` or `1.2.3-beta1`, which are common for tools.
+pub fn parse_lenient<'s, 'i>(
+    _scope: &mut PinScope<'s, 'i>,
+    version: String,
+) -> Result<Option<SemVer>, SyscallError> {
+    Ok(parse_loose(&version).map(SemVer::from))
+}
+
+pub fn compare<'s, 'i>(
+    _scope: &mut PinScope<'s, 'i>,
+    a: String,
+    b: String,
+) -> Result<Comparison, SyscallError> {
+    compare_versions(&a, &b)
+}
+
+pub fn satisfies<'s, 'i>(
+    _scope: &mut PinScope<'s, 'i>,
+    version: String,
+    range: String,
+) -> Result<bool, SyscallError> {
+    satisfies_range(&version, &range)
+}
+
+/// Invalid versions are ignored, like the `semver` package of npm. The satisfying version is
+/// returned as it is given.
+pub fn max_satisfying<'s, 'i>(
+    _scope: &mut PinScope<'s, 'i>,
+    versions: Vec<String>,
+    range: String,
+) -> Result<Option<String>, SyscallError> {
+    max_satisfying_version(versions, &range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn versions(versions: &[&str]) -> Vec<String> {
+        versions.iter().map(|version| version.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_ignores_whitespace() {
+        assert_eq!(parse_strict(" 1.2.3\n"), Some(Version::new(1, 2, 3)));
+        assert_eq!(parse_strict("1.2"), None);
+    }
+
+    #[test]
+    fn parse_loose_accepts_tool_versions() {
+        assert_eq!(parse_loose(" 1.2 "), Some(Version::new(1, 2, 0)));
+        assert_eq!(parse_loose("v1.2.3"), Some(Version::new(1, 2, 3)));
+        assert_eq!(parse_loose("not a version"), None);
+    }
+
+    #[test]
+    fn compare_is_lenient() -> Result<(), SyscallError> {
+        assert_eq!(compare_versions(" 1.2.3 ", "1.2.3")?.0, 0);
+        assert_eq!(compare_versions("1.2", "1.10.0")?.0, -1);
+        assert_eq!(compare_versions("v2", "1.2.3-beta")?.0, 1);
+        assert!(matches!(
+            compare_versions("not a version", "1.0.0"),
+            Err(SyscallError::TypeError(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn satisfies_is_lenient() -> Result<(), SyscallError> {
+        assert!(satisfies_range(" 1.2.3\n", " ^1.2 ")?);
+        assert!(satisfies_range("v1.4", ">=1.2, <2.0")?);
+        assert!(!satisfies_range("2.0", "^1.2")?);
+        assert!(matches!(
+            satisfies_range("1.0.0", "not a range"),
+            Err(SyscallError::TypeError(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn max_satisfying_is_lenient() -> Result<(), SyscallError> {
+        assert_eq!(
+            max_satisfying_version(versions(&["1.2.0", " 1.3 ", "v1.4", "2.0.0", "bad"]), "^1")?,
+            Some("v1.4".to_string())
+        );
+        assert_eq!(
+            max_satisfying_version(versions(&["1.0.0", "bad"]), "^2")?,
+            None
+        );
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use crate::chunking::ChunkManifest;
 use crate::digest::Digest;
 use async_trait::async_trait;
 use std::path::PathBuf;
@@ -28,6 +29,8 @@ pub trait Cas: Send + Sync + 'static + std::fmt::Debug {
     async fn contains(&self, digest: &Digest) -> bool;
     /// Fetch the data from the CAS.
     ///
+    /// If the data was stored chunked, it will be reassembled transparently.
+    ///
     /// If not found, it will return a `CasError::NotFound` error.
     async fn fetch(
         &self,
@@ -40,6 +43,21 @@ pub trait Cas: Send + Sync + 'static + std::fmt::Debug {
     ///
     /// This is helpful for API like `send_file`.
     async fn get_local_path(&self, digest: &Digest) -> Option<PathBuf>;
+    /// Store the data as a manifest of chunks.
+    ///
+    /// All chunks must be in the CAS already, otherwise it will return a `CasError::NotFound` error.
+    ///
+    /// The chunks are hashed together and must match the digest, otherwise it will return a
+    /// `CasError::ManifestMismatch` error.
+    async fn store_manifest(
+        &self,
+        digest: &Digest,
+        manifest: &ChunkManifest,
+    ) -> Result<(), CasError>;
+    /// Fetch the chunk manifest of the data.
+    ///
+    /// Returns None if the data was not stored chunked.
+    async fn fetch_manifest(&self, digest: &Digest) -> Result<Option<ChunkManifest>, CasError>;
 }
 
 #[derive(Error, Debug)]
@@ -48,6 +66,10 @@ pub enum CasError {
     Io(#[from] std::io::Error),
     #[error("Blob not found: {0}")]
     NotFound(String), // 存储 Digest 的 hex 字符串
+    #[error("Chunk manifest does not match blob: {0}")]
+    ManifestMismatch(String),
+    #[error("Data does not match its digest: {0}")]
+    DigestMismatch(String),
     #[error("Internal storage error: {0}")]
     Internal(String),
 }
@@ -1,9 +1,12 @@
-use crate::cas::Cas;
+use crate::cas::{Cas, CasError};
+use crate::chunking::ChunkManifest;
 use crate::digest::{Digest, DigestError}; // 假设你把 TryFrom 放到了这里
 use crate::proto::cas::{
-    GetTransportDetailsRequest, NegotiateBlobsRequest, NegotiateBlobsResponse, TransportDetails,
+    ChunkedBlob, CommitChunkedBlobResponse, GetTransportDetailsRequest, NegotiateBlobsRequest,
+    NegotiateBlobsResponse, TransportDetails,
     content_addressable_storage_server::ContentAddressableStorage,
 };
+use ahash::AHashSet;
 use dashmap::DashMap;
 use futures::{StreamExt, TryStreamExt}; // 引入 Stream 扩展方法
 use std::pin::Pin;
@@ -99,9 +102,40 @@ impl ContentAddressableStorage for CasServer {
                     }
                 }
 
-                if !missing_blobs.is_empty() {
+                // for chunked blobs, only report the chunks that the CAS does not have
+                let mut wanted_chunks = AHashSet::<Digest>::new();
+
+                for chunked in req.chunked_blobs {
+                    let (blob_digest, manifest) = parse_chunked_blob(chunked)?;
+
+                    if !cas.contains(&blob_digest).await {
+                        wanted_chunks.extend(manifest.chunks().iter().copied());
+                    }
+                }
+
+                let checks =
+                futures::stream::iter(wanted_chunks.into_iter())
+                    .map(|digest| {
+                        let cas_ref = cas.clone();
+                        async move {
+                            if !cas_ref.contains(&digest).await {
+                                Some(digest)
+                            } else {
+                                None
+                            }
+                        }
+                    })
+                .buffer_unordered(io_buffered_count);
+
+                let missing_chunks: Vec<crate::proto::digest::Digest> = checks
+                    .filter_map(|res| async move { res.map(|d| d.into()) })
+                    .collect()
+                    .await;
+
+                if !missing_blobs.is_empty() || !missing_chunks.is_empty() {
                     yield NegotiateBlobsResponse {
                         missing_blob_digests: missing_blobs,
+                        missing_chunk_digests: missing_chunks,
                     };
                 }
             }
@@ -144,4 +178,42 @@ impl ContentAddressableStorage for CasServer {
             recommended_concurrency: self.recommended_concurrency as u32,
         }))
     }
+    async fn commit_chunked_blob(
+        &self,
+        request: Request<ChunkedBlob>,
+    ) -> Result<Response<CommitChunkedBlobResponse>, Status> {
+        let (digest, manifest) = parse_chunked_blob(request.into_inner())?;
+
+        self.cas
+            .store_manifest(&digest, &manifest)
+            .await
+            .map_err(|err| match err {
+                CasError::NotFound(chunk) => {
+                    Status::failed_precondition(format!("chunk {} is not uploaded", chunk))
+                }
+                CasError::ManifestMismatch(err) | CasError::DigestMismatch(err) => {
+                    Status::invalid_argument(err)
+                }
+                CasError::Io(err) => Status::internal(err.to_string()),
+                CasError::Internal(err) => Status::internal(err),
+            })?;
+
+        Ok(Response::new(CommitChunkedBlobResponse {}))
+    }
+}
+
+fn parse_chunked_blob(chunked: ChunkedBlob) -> Result<(Digest, ChunkManifest), Status> {
+    let digest: Digest = chunked
+        .blob_digest
+        .ok_or(Status::invalid_argument("blob digest is required"))?
+        .try_into()
+        .map_err(|e: DigestError| Status::from(e))?;
+
+    let manifest: ChunkManifest = chunked
+        .manifest
+        .ok_or(Status::invalid_argument("chunk manifest is required"))?
+        .try_into()
+        .map_err(|e: DigestError| Status::from(e))?;
+
+    Ok((digest, manifest))
 }
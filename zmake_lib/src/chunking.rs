@@ -0,0 +1,314 @@
+//! Content-defined chunking for large blobs.
+//!
+//! The cut points are found by a FastCDC style gear hash with normalized chunking,
+//! so a small change in the blob only changes the chunks around it.
+
+use crate::digest::{Digest, DigestError};
+use thiserror::Error;
+
+/// The gear table used by the rolling hash.
+///
+/// It is generated by splitmix64 with a fixed seed.
+/// **Never change it**, or every chunk stored before will not be deduplicated anymore.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x5a4d_414b_4543_4443;
+    let mut index = 0;
+
+    while index < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[index] = z ^ (z >> 31);
+        index += 1;
+    }
+
+    table
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkingOptions {
+    /// Blobs smaller than this are stored whole.
+    pub threshold: u64,
+    pub min_size: usize,
+    pub avg_size: usize,
+    pub max_size: usize,
+}
+
+impl Default for ChunkingOptions {
+    fn default() -> Self {
+        Self {
+            threshold: 4 * 1024 * 1024,
+            min_size: 16 * 1024,
+            avg_size: 64 * 1024,
+            max_size: 256 * 1024,
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ChunkingError {
+    #[error("the chunk sizes must be 0 < min <= avg <= max, got {min}, {avg} and {max}")]
+    InvalidSizes { min: usize, avg: usize, max: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Chunker {
+    options: ChunkingOptions,
+    /// The mask used before reaching `avg_size`, it has more bits so it is harder to cut.
+    mask_small: u64,
+    /// The mask used after reaching `avg_size`, it has less bits so it is easier to cut.
+    mask_large: u64,
+}
+
+impl Chunker {
+    pub fn new(options: ChunkingOptions) -> Result<Self, ChunkingError> {
+        if options.min_size == 0
+            || options.min_size > options.avg_size
+            || options.avg_size > options.max_size
+        {
+            return Err(ChunkingError::InvalidSizes {
+                min: options.min_size,
+                avg: options.avg_size,
+                max: options.max_size,
+            });
+        }
+
+        let bits = options.avg_size.max(2).ilog2();
+
+        Ok(Self {
+            options,
+            mask_small: Self::mask(bits + 2),
+            mask_large: Self::mask(bits.saturating_sub(2)),
+        })
+    }
+
+    /// The gear hash shifts left, so the high bits depend on more bytes.
+    fn mask(bits: u32) -> u64 {
+        let bits = bits.clamp(1, 63);
+        ((1u64 << bits) - 1) << (64 - bits)
+    }
+
+    pub fn options(&self) -> &ChunkingOptions {
+        &self.options
+    }
+
+    /// Find the length of the first chunk of the data.
+    ///
+    /// If the data is the tail of the blob, the result may be the length of the data.
+    pub fn cut(&self, data: &[u8]) -> usize {
+        let length = data.len();
+
+        if length <= self.options.min_size {
+            return length;
+        }
+
+        let max = length.min(self.options.max_size);
+        let normal = max.min(self.options.avg_size);
+
+        let mut hash: u64 = 0;
+        let mut index = self.options.min_size;
+
+        while index < normal {
+            hash = (hash << 1).wrapping_add(GEAR[data[index] as usize]);
+            if hash & self.mask_small == 0 {
+                return index + 1;
+            }
+            index += 1;
+        }
+
+        while index < max {
+            hash = (hash << 1).wrapping_add(GEAR[data[index] as usize]);
+            if hash & self.mask_large == 0 {
+                return index + 1;
+            }
+            index += 1;
+        }
+
+        max
+    }
+
+    /// Split the whole blob into chunks.
+    pub fn split<'a>(&'a self, mut data: &'a [u8]) -> impl Iterator<Item = &'a [u8]> + 'a {
+        std::iter::from_fn(move || {
+            if data.is_empty() {
+                return None;
+            }
+
+            let (chunk, rest) = data.split_at(self.cut(data));
+            data = rest;
+            Some(chunk)
+        })
+    }
+}
+
+/// Calculate the digest of a chunk(or any in-memory blob).
+pub fn digest_of(data: &[u8]) -> Digest {
+    Digest::new(xxhash_rust::xxh3::xxh3_128(data), data.len() as u64)
+}
+
+/// Calculate the digest of a blob piece by piece, like the chunks of a manifest.
+#[derive(Clone, Default)]
+pub struct DigestBuilder {
+    hasher: xxhash_rust::xxh3::Xxh3,
+    size: u64,
+}
+
+impl DigestBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+        self.size += data.len() as u64;
+    }
+
+    pub fn finish(&self) -> Digest {
+        Digest::new(self.hasher.digest128(), self.size)
+    }
+}
+
+/// A large blob stored as the list of its chunks, in order.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct ChunkManifest {
+    chunks: Vec<Digest>,
+}
+
+impl ChunkManifest {
+    pub fn new(chunks: Vec<Digest>) -> Self {
+        Self { chunks }
+    }
+
+    pub fn from_blob(chunker: &Chunker, data: &[u8]) -> Self {
+        Self::new(chunker.split(data).map(digest_of).collect())
+    }
+
+    pub fn chunks(&self) -> &[Digest] {
+        &self.chunks
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.chunks.iter().map(|chunk| chunk.size_bytes).sum()
+    }
+}
+
+impl TryFrom<crate::proto::cas::ChunkManifest> for ChunkManifest {
+    type Error = DigestError;
+
+    fn try_from(value: crate::proto::cas::ChunkManifest) -> Result<Self, Self::Error> {
+        let chunks = value
+            .chunk_digests
+            .into_iter()
+            .map(Digest::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self::new(chunks))
+    }
+}
+
+impl Into<crate::proto::cas::ChunkManifest> for ChunkManifest {
+    fn into(self) -> crate::proto::cas::ChunkManifest {
+        crate::proto::cas::ChunkManifest {
+            chunk_digests: self.chunks.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMALL: ChunkingOptions = ChunkingOptions {
+        threshold: 1024,
+        min_size: 256,
+        avg_size: 1024,
+        max_size: 4096,
+    };
+
+    /// Bytes that look random, but are the same for every run.
+    fn noise(length: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed | 1;
+
+        (0..length)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn invalid_sizes_are_rejected() {
+        for (min_size, avg_size, max_size) in
+            [(0, 1024, 4096), (2048, 1024, 4096), (256, 8192, 4096)]
+        {
+            let options = ChunkingOptions {
+                min_size,
+                avg_size,
+                max_size,
+                ..SMALL
+            };
+
+            assert!(matches!(
+                Chunker::new(options),
+                Err(ChunkingError::InvalidSizes { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn chunks_are_within_the_sizes() -> Result<(), ChunkingError> {
+        let chunker = Chunker::new(SMALL)?;
+        let data = noise(256 * 1024, 1);
+
+        let chunks: Vec<&[u8]> = chunker.split(&data).collect();
+
+        assert_eq!(chunks.concat(), data);
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.len() >= SMALL.min_size && chunk.len() <= SMALL.max_size);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn boundaries_are_stable_around_an_edit() -> Result<(), ChunkingError> {
+        let chunker = Chunker::new(SMALL)?;
+        let data = noise(256 * 1024, 2);
+
+        let mut edited = data.clone();
+        edited.insert(data.len() / 2, 0x42);
+
+        let original = ChunkManifest::from_blob(&chunker, &data);
+        let changed = ChunkManifest::from_blob(&chunker, &edited);
+
+        // only the chunks around the edit differ
+        let unchanged = changed
+            .chunks()
+            .iter()
+            .filter(|chunk| original.chunks().contains(chunk))
+            .count();
+
+        assert!(changed.chunks().len() - unchanged <= 3);
+        assert_eq!(
+            original.chunks().first(),
+            changed.chunks().first(),
+            "the chunks before the edit must not move"
+        );
+        assert_eq!(original.chunks().last(), changed.chunks().last());
+        Ok(())
+    }
+
+    #[test]
+    fn cut_of_short_data_is_its_length() -> Result<(), ChunkingError> {
+        let chunker = Chunker::new(SMALL)?;
+        let data = noise(SMALL.min_size, 3);
+
+        assert_eq!(chunker.cut(&data), data.len());
+        assert_eq!(chunker.cut(&[]), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+//! Deterministic environment for build scripts.
+//!
+//! The output of build scripts must only depend on their inputs, so the non-deterministic
+//! APIs of JavaScript are replaced:
+//!
+//! - `Math.random` is a PRNG with a configured seed, reseeded for every evaluation.
+//! - `Date.now()` and `new Date()` return a configured clock, `Intl.DateTimeFormat` formats it
+//!   when no date is given. The local time zone is UTC.
+//! - `SharedArrayBuffer`, `Atomics.wait` and `Atomics.waitAsync`(timing channels) throw.
+//!
+//! Every use of them is reported, the report is kept in the engine.
+
+use crate::engine::State;
+use std::cell::RefCell;
+use tracing::{error, warn};
+use v8::{Local, PinScope};
+
+/// Takes `(seedHigh, seedLow, clock, report)`, replaces the globals and returns the function
+/// that restores the seed of `Math.random`.
+static INSTALL_CODE: &'static str = r#"
+(seedHigh, seedLow, clock, report) => {
+    "use strict";
+
+    // xorshift128+
+    let s0;
+    let s1;
+    const reseed = () => {
+        s0 = BigInt.asUintN(64, (BigInt(seedHigh) << 32n) | BigInt(seedLow));
+        s1 = BigInt.asUintN(64, s0 ^ 0x9e3779b97f4a7c15n);
+        if (s0 === 0n && s1 === 0n) {
+            s1 = 1n;
+        }
+    };
+    reseed();
+
+    const random = function random() {
+        report("Math.random");
+        let x = s0;
+        const y = s1;
+        s0 = y;
+        x = BigInt.asUintN(64, x ^ (x << 23n));
+        s1 = x ^ y ^ (x >> 17n) ^ (y >> 26n);
+        return Number(BigInt.asUintN(64, s1 + y) >> 11n) / 2 ** 53;
+    };
+    Object.defineProperty(Math, "random", { value: random, writable: false, configurable: false });
+
+    const OriginalDate = Date;
+
+    const DeterministicDate = function Date(...args) {
+        if (new.target === undefined) {
+            report("Date");
+            return new OriginalDate(clock).toString();
+        }
+        if (args.length === 0) {
+            report("Date");
+            return Reflect.construct(OriginalDate, [clock], new.target);
+        }
+        return Reflect.construct(OriginalDate, args, new.target);
+    };
+    DeterministicDate.prototype = OriginalDate.prototype;
+    DeterministicDate.parse = OriginalDate.parse;
+    DeterministicDate.UTC = OriginalDate.UTC;
+    DeterministicDate.now = function now() {
+        report("Date.now");
+        return clock;
+    };
+    Object.defineProperty(OriginalDate.prototype, "constructor", {
+        value: DeterministicDate,
+        writable: true,
+        configurable: true,
+    });
+    Object.defineProperty(globalThis, "Date", {
+        value: DeterministicDate,
+        writable: false,
+        configurable: false,
+    });
+
+    // without a date, `Intl.DateTimeFormat` formats the current time
+    const dateTimeFormat = Intl.DateTimeFormat.prototype;
+    const getFormat = Object.getOwnPropertyDescriptor(dateTimeFormat, "format").get;
+    // the bound `format` is the same function for every get, like the original one
+    const formats = new WeakMap();
+    Object.defineProperty(dateTimeFormat, "format", {
+        get() {
+            let format = formats.get(this);
+            if (format === undefined) {
+                const bound = getFormat.call(this);
+                format = (date) => {
+                    if (date === undefined) {
+                        report("Intl.DateTimeFormat.prototype.format");
+                        return bound(clock);
+                    }
+                    return bound(date);
+                };
+                formats.set(this, format);
+            }
+            return format;
+        },
+        configurable: false,
+    });
+    const originalFormatToParts = dateTimeFormat.formatToParts;
+    Object.defineProperty(dateTimeFormat, "formatToParts", {
+        value: function formatToParts(date) {
+            if (date === undefined) {
+                report("Intl.DateTimeFormat.prototype.formatToParts");
+                return originalFormatToParts.call(this, clock);
+            }
+            return originalFormatToParts.call(this, date);
+        },
+        writable: false,
+        configurable: false,
+    });
+
+    const disabled = (name) => function () {
+        report(name);
+        throw new Error(`\`${name}\` is disabled in build scripts, it is not deterministic`);
+    };
+    Object.defineProperty(globalThis, "SharedArrayBuffer", {
+        value: disabled("SharedArrayBuffer"),
+        writable: false,
+        configurable: false,
+    });
+    for (const name of ["wait", "waitAsync"]) {
+        Object.defineProperty(Atomics, name, {
+            value: disabled(`Atomics.${name}`),
+            writable: false,
+            configurable: false,
+        });
+    }
+
+    return reseed;
+}
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DeterminismOptions {
+    /// The seed of `Math.random`.
+    pub random_seed: u64,
+    /// The time of `Date.now()` and `new Date()`, in milliseconds since the unix epoch.
+    pub clock: f64,
+}
+
+/// A use of non-deterministic API by the script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NonDeterministicUse {
+    pub api: String,
+    /// `file:line:column` of the script, if it is known.
+    pub location: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct DeterminismReport {
+    uses: RefCell<Vec<NonDeterministicUse>>,
+    /// Restores the seed of `Math.random`, set by [install].
+    reseed: RefCell<Option<v8::Global<v8::Function>>>,
+}
+
+impl DeterminismReport {
+    pub fn record(&self, api: String, location: Option<String>) {
+        let mut uses = self.uses.borrow_mut();
+
+        // warn once for every place
+        if !uses.iter().any(|x| x.api == api && x.location == location) {
+            warn!(
+                "build script uses non-deterministic API `{}` at {}",
+                api,
+                location.as_deref().unwrap_or("unknown location")
+            );
+        }
+
+        uses.push(NonDeterministicUse { api, location });
+    }
+
+    pub fn take(&self) -> Vec<NonDeterministicUse> {
+        std::mem::take(&mut *self.uses.borrow_mut())
+    }
+}
+
+fn report<'s, 'i>(
+    scope: &mut PinScope<'s, 'i>,
+    args: v8::FunctionCallbackArguments<'s>,
+    _return_value: v8::ReturnValue<'s, v8::Value>,
+) {
+    let api = args.get(0).to_rust_string_lossy(scope);
+    let location = crate::builtin::js::script_location(scope);
+
+    match scope.get_current_context().get_slot::<State>() {
+        Some(state) => state.determinism.record(api, location),
+        None => error!("failed to get state from slot"),
+    }
+}
+
+/// Replace the non-deterministic globals of the current context.
+pub fn install<'s, 'i>(
+    scope: &mut PinScope<'s, 'i>,
+    options: &DeterminismOptions,
+) -> Result<(), &'static str> {
+    let code = v8::String::new(scope, INSTALL_CODE).ok_or("failed to allocate the code")?;
+
+    let installer = v8::Script::compile(scope, code, None)
+        .and_then(|script| script.run(scope))
+        .and_then(|installer| installer.try_cast::<v8::Function>().ok())
+        .ok_or("failed to compile the installer")?;
+
+    let report = v8::Function::new(scope, report).ok_or("failed to create the report function")?;
+
+    let args: [Local<v8::Value>; 4] = [
+        v8::Number::new(scope, (options.random_seed >> 32) as f64).into(),
+        v8::Number::new(scope, (options.random_seed & 0xffff_ffff) as f64).into(),
+        v8::Number::new(scope, options.clock).into(),
+        report.into(),
+    ];
+
+    let undefined = v8::undefined(scope).into();
+
+    let reseed = installer
+        .call(scope, undefined, &args)
+        .and_then(|reseed| reseed.try_cast::<v8::Function>().ok())
+        .ok_or("failed to install the deterministic environment")?;
+
+    let state = scope
+        .get_current_context()
+        .get_slot::<State>()
+        .ok_or("failed to get state from slot")?;
+
+    state
+        .determinism
+        .reseed
+        .replace(Some(v8::Global::new(scope, reseed)));
+
+    Ok(())
+}
+
+/// Restore the seed of `Math.random`, so every evaluation sees the same sequence.
+///
+/// Does nothing if the deterministic environment is not installed.
+pub fn reseed<'s, 'i>(scope: &mut PinScope<'s, 'i>, state: &State) -> Result<(), &'static str> {
+    let Some(reseed) = state.determinism.reseed.borrow().clone() else {
+        return Ok(());
+    };
+
+    let reseed = Local::new(scope, reseed);
+    let undefined = v8::undefined(scope).into();
+
+    reseed
+        .call(scope, undefined, &[])
+        .ok_or("failed to reseed `Math.random`")?;
+
+    Ok(())
+}
@@ -1,22 +1,46 @@
-use crate::module_loader::{ModuleLoader, Options};
+use crate::audit::{AccessAudit, FileAccess};
+use crate::builtin::console::ConsoleState;
+use crate::determinism::{DeterminismOptions, DeterminismReport, NonDeterministicUse};
+use crate::event_loop::EventLoop;
+use crate::import_map::ImportMap;
+use crate::limits::{HeapLimit, Watchdog};
+pub use crate::module_loader::ModuleLanguage;
+use crate::module_loader::{ModuleLoadError, ModuleLoader, Options};
 use crate::module_specifier::ModuleSpecifier;
 use crate::platform::get_initialized_or_default;
 use crate::sandbox::Sandbox;
 use std::cell::RefCell;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
 use v8::{Global, Local};
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum EngineMode {
     Project,
     Rule,
+    /// The task layer, the only one that may opt out of determinism.
+    Script,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EngineOptions {
     pub tokio_handle: tokio::runtime::Handle,
     pub mode: EngineMode,
+    /// Where to cache the work of loading scripts across runs, `None` to disable the cache.
+    pub cache_directory: Option<PathBuf>,
+    /// The startup snapshot created by `crate::snapshot::create_snapshot`.
+    ///
+    /// Without it, the runtime is evaluated when the engine is created.
+    pub snapshot: Option<&'static [u8]>,
+    /// `None` to keep the non-deterministic APIs, only allowed in `EngineMode::Script`.
+    pub determinism: Option<DeterminismOptions>,
+    /// The max size of the V8 heap in bytes, `None` for the default of V8.
+    pub max_heap_size: Option<usize>,
+    /// The wall-clock time that the execution of a module may take, `None` for no limit.
+    pub execution_timeout: Option<Duration>,
 }
 
 #[derive(Debug)]
@@ -24,29 +48,93 @@ pub struct State {
     pub mode: EngineMode,
     pub tokio_handle: tokio::runtime::Handle,
     pub module_loader: ModuleLoader,
+    pub event_loop: EventLoop,
+    pub determinism: DeterminismReport,
+    pub console: ConsoleState,
+}
+
+#[derive(Error, Debug)]
+pub enum EngineError {
+    #[error("failed to get the engine state from the context")]
+    StateNotFound,
+    #[error("{0}")]
+    ModuleLoadError(#[from] ModuleLoadError),
+    #[error("the engine in {0:?} mode must be deterministic")]
+    DeterminismRequired(EngineMode),
+    #[error("failed to set up the deterministic environment: {0}")]
+    DeterminismError(&'static str),
+    #[error("the script is terminated for it reaches the heap limit of {0} bytes")]
+    HeapLimitExceeded(usize),
+    #[error("the script is terminated for it runs longer than {0:?}")]
+    ExecutionTimeout(Duration),
+    #[error("failed to start the watchdog: {0}")]
+    WatchdogError(#[from] std::io::Error),
+    #[error("failed to serialize the exports of module `{0}` as json: {1:?}")]
+    SerializeExportsError(ModuleSpecifier, Option<serde_json::Error>),
+}
+
+/// The exports of an evaluated module and the files accessed by the evaluation.
+#[derive(Debug, Clone)]
+pub struct Evaluation {
+    pub exports: serde_json::Value,
+    /// The accesses of nested evaluations are not included, they have their own.
+    pub accesses: Vec<FileAccess>,
+    pub non_deterministic_uses: Vec<NonDeterministicUse>,
 }
 
 #[derive(Debug)]
 pub struct Engine {
     isolate: RefCell<v8::OwnedIsolate>,
     context: Global<v8::Context>,
+    sandbox: Arc<Sandbox>,
+    /// Dropped after the isolate, which refers to it.
+    heap_limit: Option<(usize, Box<HeapLimit>)>,
+    execution_timeout: Option<Duration>,
 }
 
 impl Engine {
     pub fn new(sandbox: Arc<Sandbox>, options: EngineOptions) -> eyre::Result<Self> {
         let _ = get_initialized_or_default();
 
-        let mut isolate = v8::Isolate::new(v8::CreateParams::default());
+        if options.determinism.is_none() && options.mode != EngineMode::Script {
+            return Err(EngineError::DeterminismRequired(options.mode).into());
+        }
+
+        let params = match options.snapshot {
+            Some(snapshot) => v8::CreateParams::default()
+                .snapshot_blob(v8::StartupData::from(snapshot))
+                .external_references(crate::snapshot::external_references()),
+            None => v8::CreateParams::default(),
+        };
+
+        let params = match options.max_heap_size {
+            Some(max_heap_size) => params.heap_limits(0, max_heap_size),
+            None => params,
+        };
+
+        let mut isolate = v8::Isolate::new(params);
+
+        let heap_limit = options
+            .max_heap_size
+            .map(|max_heap_size| (max_heap_size, HeapLimit::install(&mut isolate)));
+
+        // microtasks are run by the event loop
+        isolate.set_microtasks_policy(v8::MicrotasksPolicy::Explicit);
 
         let loader = ModuleLoader::new(
-            sandbox,
+            sandbox.clone(),
             Options {
                 enable_imports: true,
+                cache_directory: options.cache_directory.clone(),
             },
         );
 
         loader.apply(&mut isolate);
 
+        if let Some(import_map) = ImportMap::load(&sandbox)? {
+            loader.set_import_map(import_map);
+        }
+
         let context = {
             let handle_scope = std::pin::pin!(v8::HandleScope::new(&mut isolate));
             let mut handle_scope = handle_scope.init();
@@ -58,37 +146,602 @@ impl Engine {
                 mode: options.mode,
                 tokio_handle: options.tokio_handle.clone(),
                 module_loader: loader,
+                event_loop: EventLoop::new(options.tokio_handle.clone()),
+                determinism: DeterminismReport::default(),
+                console: ConsoleState::default(),
             };
 
+            if options.snapshot.is_some() {
+                // the modules can only be taken once, so register them for later imports
+                for (index, specifier) in
+                    crate::snapshot::snapshot_modules().into_iter().enumerate()
+                {
+                    let module = scope
+                        .get_context_data_from_snapshot_once::<v8::Module>(index)
+                        .map_err(|_| {
+                            ModuleLoadError::SnapshotError(
+                                "the builtin module is not in the snapshot",
+                            )
+                        })?;
+
+                    state
+                        .module_loader
+                        .register_module(scope, specifier, module);
+                }
+            }
+
             context.set_slot::<State>(Rc::from(state));
 
+            if let Some(determinism) = &options.determinism {
+                crate::determinism::install(scope, determinism)
+                    .map_err(EngineError::DeterminismError)?;
+            }
+
             Global::new(scope, context)
         };
 
         let engine = Engine {
             isolate: RefCell::from(isolate),
             context,
+            sandbox,
+            heap_limit,
+            execution_timeout: options.execution_timeout,
         };
 
-        engine.execute_module(&crate::builtin::js::RT);
+        if options.snapshot.is_none() {
+            engine.execute_module(&crate::builtin::js::RT)?;
+        }
 
         Ok(engine)
     }
 
-    pub fn execute_module(self: &Self, module: &ModuleSpecifier) {
+    pub fn get_sandbox(&self) -> &Arc<Sandbox> {
+        &self.sandbox
+    }
+
+    /// Take the uses of non-deterministic APIs since the last call.
+    pub fn take_determinism_report(self: &Self) -> Result<Vec<NonDeterministicUse>, EngineError> {
+        let context = self.context.clone();
+        let mut isoalte = self.isolate.borrow_mut();
+        let scope = std::pin::pin!(v8::HandleScope::new(&mut *isoalte));
+        let scope = scope.init();
+        let context = Local::new(&scope, context);
+
+        let state = context
+            .get_slot::<State>()
+            .ok_or(EngineError::StateNotFound)?;
+
+        Ok(state.determinism.take())
+    }
+
+    /// Register a module that scripts can import by `ModuleSpecifier::Memory`, without touching disk.
+    ///
+    /// Relative imports in the module are resolved from the sandbox root.
+    pub fn add_memory_module(
+        self: &Self,
+        name: &str,
+        source: String,
+        language: ModuleLanguage,
+    ) -> Result<ModuleSpecifier, EngineError> {
+        let context = self.context.clone();
+        let mut isoalte = self.isolate.borrow_mut();
+        let scope = std::pin::pin!(v8::HandleScope::new(&mut *isoalte));
+        let scope = scope.init();
+        let context = Local::new(&scope, context);
+
+        let state = context
+            .get_slot::<State>()
+            .ok_or(EngineError::StateNotFound)?;
+
+        Ok(state
+            .module_loader
+            .add_memory_module(name, source, language)?)
+    }
+
+    pub fn execute_module(self: &Self, module: &ModuleSpecifier) -> Result<(), EngineError> {
+        self.execute(module, false, Arc::new(AccessAudit::new()))
+            .map(|_| ())
+    }
+
+    /// Execute the module and serialize its exports as json, functions are dropped.
+    pub fn evaluate_module(
+        self: &Self,
+        module: &ModuleSpecifier,
+    ) -> Result<Evaluation, EngineError> {
+        let audit = Arc::new(AccessAudit::new());
+
+        let exports = self.execute(module, true, audit.clone());
+
+        // taken even if the execution fails, or the next evaluation would report them
+        let non_deterministic_uses = self.take_determinism_report()?;
+
+        let exports =
+            exports?.ok_or_else(|| EngineError::SerializeExportsError(module.clone(), None))?;
+
+        Ok(Evaluation {
+            exports: serde_json::from_str(&exports)
+                .map_err(|err| EngineError::SerializeExportsError(module.clone(), Some(err)))?,
+            accesses: audit.take(),
+            non_deterministic_uses,
+        })
+    }
+
+    /// The files opened by the execution are recorded to the audit.
+    fn execute(
+        self: &Self,
+        module: &ModuleSpecifier,
+        serialize_exports: bool,
+        audit: Arc<AccessAudit>,
+    ) -> Result<Option<String>, EngineError> {
         let context = self.context.clone();
         let mut isoalte = self.isolate.borrow_mut();
+        let handle = isoalte.thread_safe_handle();
         let scope = std::pin::pin!(v8::HandleScope::new(&mut *isoalte));
         let mut scope = scope.init();
         let context = Local::new(&scope, context);
 
-        let state = context.get_slot::<State>().unwrap();
+        let state = context
+            .get_slot::<State>()
+            .ok_or(EngineError::StateNotFound)?;
 
         let mut scope = &mut v8::ContextScope::new(&mut scope, context);
 
-        state
+        // every execution sees the same `Math.random` sequence
+        crate::determinism::reseed(&mut scope, &state).map_err(EngineError::DeterminismError)?;
+
+        // modules are instantiated again, so their state does not leak between executions
+        state.module_loader.clear_instances();
+        state.console.reset();
+
+        let watchdog = match self.execution_timeout {
+            Some(timeout) => Some(Watchdog::start(
+                handle,
+                state.event_loop.terminator(),
+                timeout,
+            )?),
+            None => None,
+        };
+
+        // restored after the execution, in case it is nested in another one
+        let outer_sandbox = state
             .module_loader
-            .execute_module(&mut scope, module)
-            .unwrap();
+            .replace_sandbox(Arc::new(self.sandbox.with_audit(audit)));
+
+        let result = (|| -> Result<Option<String>, EngineError> {
+            state.module_loader.record_import_map();
+
+            let result = state.module_loader.execute_module(&mut scope, module)?;
+
+            // the module is evaluated once its top-level await settles
+            if let Ok(promise) = result.try_cast::<v8::Promise>() {
+                state
+                    .event_loop
+                    .run_until_settled(&state, &mut scope, module, promise)?;
+            }
+
+            if !serialize_exports {
+                return Ok(None);
+            }
+
+            let namespace = state
+                .module_loader
+                .resolve_module(&scope, module)?
+                .get_module_namespace();
+
+            Ok(v8::json::stringify(&mut scope, namespace)
+                .map(|exports| exports.to_rust_string_lossy(&scope)))
+        })();
+
+        state.module_loader.replace_sandbox(outer_sandbox);
+
+        let timed_out = watchdog.is_some_and(Watchdog::stop);
+        let heap_exceeded = self
+            .heap_limit
+            .as_ref()
+            .is_some_and(|(_, heap_limit)| heap_limit.take_exceeded());
+
+        if timed_out || heap_exceeded {
+            // the engine can be used again
+            scope.cancel_terminate_execution();
+            state.event_loop.clear_terminated();
+        }
+
+        if let Some((max_heap_size, heap_limit)) = &self.heap_limit
+            && heap_exceeded
+        {
+            heap_limit.reset(&mut scope, *max_heap_size);
+            return Err(EngineError::HeapLimitExceeded(*max_heap_size));
+        }
+
+        if let Some(timeout) = self.execution_timeout
+            && timed_out
+        {
+            return Err(EngineError::ExecutionTimeout(timeout));
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AccessKind;
+    use crate::module_specifier::MEMORY_MODULE_PREFIX;
+    use crate::test_util::TempDir;
+    use crate::version_requirement::VersionRequirementError;
+    use serde_json::json;
+    use std::path::Path;
+
+    /// An engine whose sandbox is a fresh temporary directory.
+    struct TestEngine {
+        engine: Engine,
+        root: TempDir,
+        _runtime: tokio::runtime::Runtime,
+    }
+
+    impl TestEngine {
+        fn new() -> eyre::Result<Self> {
+            Self::with_files(&[])
+        }
+
+        fn with_files(files: &[(&str, &str)]) -> eyre::Result<Self> {
+            Self::with_mode(EngineMode::Project, files)
+        }
+
+        /// The files are written before the engine is created, their directories are created.
+        fn with_mode(mode: EngineMode, files: &[(&str, &str)]) -> eyre::Result<Self> {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()?;
+
+            let root = TempDir::new()?;
+
+            for (name, content) in files {
+                let path = root.path().join(name);
+
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+
+                std::fs::write(path, content)?;
+            }
+
+            let engine = Engine::new(
+                Arc::new(Sandbox::new(root.path())?),
+                EngineOptions {
+                    tokio_handle: runtime.handle().clone(),
+                    mode,
+                    cache_directory: None,
+                    snapshot: None,
+                    determinism: Some(DeterminismOptions::default()),
+                    max_heap_size: None,
+                    execution_timeout: None,
+                },
+            )?;
+
+            Ok(Self {
+                engine,
+                root,
+                _runtime: runtime,
+            })
+        }
+
+        fn root(&self) -> &Path {
+            self.root.path()
+        }
+    }
+
+    #[test]
+    fn import_memory_module() -> eyre::Result<()> {
+        let test = TestEngine::new()?;
+
+        test.engine.add_memory_module(
+            "answer",
+            "export const answer: number = 42;".to_string(),
+            ModuleLanguage::TypeScript,
+        )?;
+
+        let main = test.engine.add_memory_module(
+            "main",
+            format!(
+                "import {{ answer }} from \"{}answer\";\nexport const doubled = answer * 2;",
+                MEMORY_MODULE_PREFIX
+            ),
+            ModuleLanguage::JavaScript,
+        )?;
+
+        let evaluation = test.engine.evaluate_module(&main)?;
+
+        assert_eq!(evaluation.exports, json!({ "doubled": 84 }));
+        Ok(())
+    }
+
+    #[test]
+    fn relative_import_from_memory_module() -> eyre::Result<()> {
+        let test = TestEngine::new()?;
+
+        std::fs::write(
+            test.root().join("value.js"),
+            "export const value = \"from disk\";",
+        )?;
+
+        let main = test.engine.add_memory_module(
+            "main",
+            "export { value } from \"./value.js\";".to_string(),
+            ModuleLanguage::JavaScript,
+        )?;
+
+        let evaluation = test.engine.evaluate_module(&main)?;
+
+        assert_eq!(evaluation.exports, json!({ "value": "from disk" }));
+        assert!(
+            evaluation
+                .accesses
+                .iter()
+                .any(|access| access.path.to_string() == "value.js")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_memory_module_is_rejected() -> eyre::Result<()> {
+        let test = TestEngine::new()?;
+
+        test.engine.add_memory_module(
+            "same",
+            "export default 1;".to_string(),
+            ModuleLanguage::JavaScript,
+        )?;
+
+        let duplicate = test.engine.add_memory_module(
+            "same",
+            "export default 2;".to_string(),
+            ModuleLanguage::JavaScript,
+        );
+
+        assert!(matches!(
+            duplicate,
+            Err(EngineError::ModuleLoadError(
+                ModuleLoadError::DuplicateMemoryModule(_)
+            ))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn shared_import_is_recorded_for_every_evaluation() -> eyre::Result<()> {
+        let test = TestEngine::new()?;
+
+        std::fs::write(test.root().join("shared.js"), "export const value = 1;")?;
+
+        for name in ["a.js", "b.js"] {
+            std::fs::write(
+                test.root().join(name),
+                "export { value } from \"./shared.js\";",
+            )?;
+        }
+
+        for name in ["a.js", "b.js"] {
+            let module = ModuleSpecifier::File(std::fs::canonicalize(test.root().join(name))?);
+            let evaluation = test.engine.evaluate_module(&module)?;
+
+            assert!(
+                evaluation
+                    .accesses
+                    .iter()
+                    .any(|access| access.path.to_string() == "shared.js"),
+                "`{}` does not record the shared import",
+                name
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn random_sequence_is_the_same_for_every_evaluation() -> eyre::Result<()> {
+        let test = TestEngine::new()?;
+
+        let mut sequences = Vec::new();
+        for name in ["first", "second"] {
+            let module = test.engine.add_memory_module(
+                name,
+                "export const sequence = [Math.random(), Math.random(), Math.random()];"
+                    .to_string(),
+                ModuleLanguage::JavaScript,
+            )?;
+
+            sequences.push(test.engine.evaluate_module(&module)?.exports);
+        }
+
+        assert_eq!(sequences[0], sequences[1]);
+        Ok(())
+    }
+
+    #[test]
+    fn local_time_zone_is_utc() -> eyre::Result<()> {
+        let test = TestEngine::new()?;
+
+        let module = test.engine.add_memory_module(
+            "main",
+            "export const offset = new Date(0).getTimezoneOffset();".to_string(),
+            ModuleLanguage::JavaScript,
+        )?;
+
+        let evaluation = test.engine.evaluate_module(&module)?;
+
+        assert_eq!(evaluation.exports, json!({ "offset": 0 }));
+        Ok(())
+    }
+
+    #[test]
+    fn import_that_is_not_awaited_is_drained() -> eyre::Result<()> {
+        let test = TestEngine::new()?;
+
+        std::fs::write(test.root().join("value.js"), "export const value = 1;")?;
+        std::fs::write(
+            test.root().join("main.js"),
+            "import(\"./value.js\");\nexport const done = true;",
+        )?;
+
+        let main = ModuleSpecifier::File(std::fs::canonicalize(test.root().join("main.js"))?);
+        let evaluation = test.engine.evaluate_module(&main)?;
+
+        assert!(
+            evaluation
+                .accesses
+                .iter()
+                .any(|access| access.path.to_string() == "value.js")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dangling_import_is_an_error() -> eyre::Result<()> {
+        let test = TestEngine::new()?;
+
+        std::fs::write(test.root().join("never.js"), "await new Promise(() => {});")?;
+        std::fs::write(
+            test.root().join("main.js"),
+            "import(\"./never.js\");\nexport const done = true;",
+        )?;
+
+        let main = ModuleSpecifier::File(std::fs::canonicalize(test.root().join("main.js"))?);
+        let evaluation = test.engine.evaluate_module(&main);
+
+        assert!(matches!(
+            evaluation,
+            Err(EngineError::ModuleLoadError(
+                ModuleLoadError::DanglingAsyncWork(_)
+            ))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn literal_version_requirement_fails_before_evaluation() -> eyre::Result<()> {
+        let test = TestEngine::with_files(&[
+            (
+                "rules.js",
+                r#"
+                    import { requireZMakeVersion } from "zmake:core";
+                    requireZMakeVersion(">=99.0.0", "group:rules");
+                    throw new Error("the rules are evaluated");
+                "#,
+            ),
+            ("main.js", "import \"./rules.js\";\nexport default {};"),
+        ])?;
+
+        let main = ModuleSpecifier::File(std::fs::canonicalize(test.root().join("main.js"))?);
+        let evaluation = test.engine.evaluate_module(&main);
+
+        assert!(matches!(
+            evaluation,
+            Err(EngineError::ModuleLoadError(
+                ModuleLoadError::VersionRequirement(VersionRequirementError::Unsatisfied {
+                    ref package,
+                    ..
+                })
+            )) if package == "group:rules"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn import_map_is_recorded_for_every_evaluation() -> eyre::Result<()> {
+        let test = TestEngine::with_files(&[
+            (
+                crate::import_map::IMPORT_MAP_FILE,
+                r#"{ "imports": { "@value": "./value.js" } }"#,
+            ),
+            ("value.js", "export const value = 1;"),
+        ])?;
+
+        for name in ["first", "second"] {
+            let module = test.engine.add_memory_module(
+                name,
+                "export const answer = 42;".to_string(),
+                ModuleLanguage::JavaScript,
+            )?;
+
+            let evaluation = test.engine.evaluate_module(&module)?;
+
+            assert!(
+                evaluation
+                    .accesses
+                    .iter()
+                    .any(|access| access.path.to_string() == crate::import_map::IMPORT_MAP_FILE)
+            );
+        }
+
+        let main = test.engine.add_memory_module(
+            "main",
+            "export { value } from \"@value\";".to_string(),
+            ModuleLanguage::JavaScript,
+        )?;
+
+        assert_eq!(
+            test.engine.evaluate_module(&main)?.exports,
+            json!({ "value": 1 })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn file_system_accesses_are_recorded() -> eyre::Result<()> {
+        let test = TestEngine::with_mode(
+            EngineMode::Script,
+            &[
+                ("src/a.ts", "a"),
+                ("src/b.js", "b"),
+                ("src/nested/c.ts", "c"),
+            ],
+        )?;
+
+        let main = test.engine.add_memory_module(
+            "main",
+            r#"
+                import { readDir, stat, glob } from "zmake:fs";
+
+                export const entries = (await readDir("src")).map((entry) => entry.name);
+                export const kind = (await stat("src/a.ts")).kind;
+                export const matches = await glob("src/**/*.ts");
+                export const missing = await glob("missing/*.ts");
+            "#
+            .to_string(),
+            ModuleLanguage::JavaScript,
+        )?;
+
+        let evaluation = test.engine.evaluate_module(&main)?;
+
+        assert_eq!(
+            evaluation.exports,
+            json!({
+                "entries": ["a.ts", "b.js", "nested"],
+                "kind": "file",
+                "matches": ["src/a.ts", "src/nested/c.ts"],
+                "missing": [],
+            })
+        );
+
+        for (path, kind) in [
+            ("src", AccessKind::List),
+            ("src/a.ts", AccessKind::Read),
+            ("src/nested", AccessKind::List),
+            ("missing", AccessKind::List),
+        ] {
+            assert!(
+                evaluation
+                    .accesses
+                    .iter()
+                    .any(|access| access.path.to_string() == path && access.kind == kind),
+                "the {} of `{}` is not recorded",
+                kind,
+                path
+            );
+        }
+
+        Ok(())
     }
 }
@@ -0,0 +1,309 @@
+//! A pool of engines on dedicated threads, so independent modules are evaluated in parallel.
+//!
+//! V8 isolates can not move between threads, so every engine lives on its own thread and
+//! takes work from a shared queue.
+
+use crate::engine::{Engine, EngineError, EngineOptions, Evaluation};
+use crate::module_specifier::ModuleSpecifier;
+use crate::sandbox::Sandbox;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use thiserror::Error;
+use tracing::{error, trace_span};
+
+#[derive(Error, Debug)]
+pub enum EnginePoolError {
+    #[error("failed to start the engine thread: {0}")]
+    ThreadError(#[from] std::io::Error),
+    #[error("failed to create the engine: {0}")]
+    EngineCreationError(eyre::Report),
+    #[error("{0}")]
+    EngineError(#[from] EngineError),
+    #[error("the engine pool has been shut down")]
+    ShutDown,
+}
+
+#[derive(Debug, Clone)]
+pub struct EnginePoolOptions {
+    /// The count of engines, usually the concurrency of zmake.
+    pub size: usize,
+    /// Every engine is created with the options.
+    pub engine: EngineOptions,
+}
+
+type Evaluated = Result<Evaluation, EnginePoolError>;
+
+#[derive(Debug)]
+struct Job {
+    module: ModuleSpecifier,
+    reply: Sender<Evaluated>,
+}
+
+/// The result of a queued evaluation.
+#[derive(Debug)]
+pub struct PendingEvaluation {
+    receiver: Receiver<Evaluated>,
+}
+
+impl PendingEvaluation {
+    /// Wait for the serialized exports of the module and the files it accessed.
+    pub fn wait(self) -> Evaluated {
+        self.receiver
+            .recv()
+            .unwrap_or(Err(EnginePoolError::ShutDown))
+    }
+}
+
+#[derive(Debug)]
+pub struct EnginePool {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl EnginePool {
+    /// Start the engines, it returns after every engine is created.
+    ///
+    /// Every evaluation gets its own audit, so the accesses of parallel evaluations are not mixed.
+    pub fn new(sandbox: Arc<Sandbox>, options: EnginePoolOptions) -> Result<Self, EnginePoolError> {
+        let (sender, receiver) = channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let mut pool = Self {
+            sender: Some(sender),
+            workers: Vec::with_capacity(options.size),
+        };
+
+        let (ready_sender, ready_receiver) = channel::<Result<(), EnginePoolError>>();
+
+        for index in 0..options.size.max(1) {
+            let sandbox = sandbox.clone();
+            let engine_options = options.engine.clone();
+            let receiver = receiver.clone();
+            let ready_sender = ready_sender.clone();
+
+            let worker = std::thread::Builder::new()
+                .name(format!("zmake-engine-{}", index))
+                .spawn(move || {
+                    let engine = match Engine::new(sandbox, engine_options) {
+                        Ok(engine) => {
+                            let _ = ready_sender.send(Ok(()));
+                            engine
+                        }
+                        Err(err) => {
+                            let _ =
+                                ready_sender.send(Err(EnginePoolError::EngineCreationError(err)));
+                            return;
+                        }
+                    };
+
+                    Self::work(&engine, &receiver);
+                })?;
+
+            pool.workers.push(worker);
+        }
+
+        drop(ready_sender);
+
+        // the pool is shut down by drop if any engine fails
+        for ready in ready_receiver {
+            ready?;
+        }
+
+        Ok(pool)
+    }
+
+    fn work(engine: &Engine, receiver: &Mutex<Receiver<Job>>) {
+        loop {
+            let job = match receiver.lock() {
+                Ok(receiver) => receiver.recv(),
+                Err(_) => {
+                    error!("the job queue of engine pool is poisoned");
+                    return;
+                }
+            };
+
+            // the sender is dropped when the pool shuts down
+            let Ok(job) = job else {
+                return;
+            };
+
+            let span = trace_span!("evaluate module: {module}", module = job.module.to_string());
+            let _span = span.enter();
+
+            let _ = job
+                .reply
+                .send(engine.evaluate_module(&job.module).map_err(Into::into));
+        }
+    }
+
+    /// Queue the module, it is evaluated by the first idle engine.
+    pub fn evaluate(&self, module: ModuleSpecifier) -> PendingEvaluation {
+        let (reply, receiver) = channel();
+
+        match &self.sender {
+            Some(sender) => {
+                if let Err(err) = sender.send(Job { module, reply }) {
+                    let _ = err.0.reply.send(Err(EnginePoolError::ShutDown));
+                }
+            }
+            None => {
+                let _ = reply.send(Err(EnginePoolError::ShutDown));
+            }
+        }
+
+        PendingEvaluation { receiver }
+    }
+
+    /// Evaluate the modules in parallel, the results are in the same order as the modules.
+    pub fn evaluate_all(
+        &self,
+        modules: impl IntoIterator<Item = ModuleSpecifier>,
+    ) -> Vec<Evaluated> {
+        let pending: Vec<PendingEvaluation> = modules
+            .into_iter()
+            .map(|module| self.evaluate(module))
+            .collect();
+
+        pending.into_iter().map(PendingEvaluation::wait).collect()
+    }
+}
+
+impl Drop for EnginePool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                error!("the engine thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::determinism::DeterminismOptions;
+    use crate::engine::EngineMode;
+    use crate::test_util::TempDir;
+    use serde_json::json;
+
+    struct TestPool {
+        root: TempDir,
+        sandbox: Arc<Sandbox>,
+        runtime: tokio::runtime::Runtime,
+    }
+
+    impl TestPool {
+        fn new() -> eyre::Result<Self> {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()?;
+
+            let root = TempDir::new()?;
+            let sandbox = Arc::new(Sandbox::new(root.path())?);
+
+            Ok(Self {
+                root,
+                sandbox,
+                runtime,
+            })
+        }
+
+        fn options(&self, size: usize) -> EnginePoolOptions {
+            EnginePoolOptions {
+                size,
+                engine: EngineOptions {
+                    tokio_handle: self.runtime.handle().clone(),
+                    mode: EngineMode::Project,
+                    cache_directory: None,
+                    snapshot: None,
+                    determinism: Some(DeterminismOptions::default()),
+                    max_heap_size: None,
+                    execution_timeout: None,
+                },
+            }
+        }
+
+        fn write(&self, name: &str, source: &str) -> eyre::Result<ModuleSpecifier> {
+            let path = self.root.path().join(name);
+            std::fs::write(&path, source)?;
+            Ok(ModuleSpecifier::File(std::fs::canonicalize(path)?))
+        }
+    }
+
+    #[test]
+    fn results_are_in_the_order_of_modules() -> eyre::Result<()> {
+        let test = TestPool::new()?;
+        let pool = EnginePool::new(test.sandbox.clone(), test.options(3))?;
+
+        let modules = (0..8)
+            .map(|index| {
+                test.write(
+                    &format!("{}.js", index),
+                    &format!("export const index = {};", index),
+                )
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+
+        for (index, evaluated) in pool.evaluate_all(modules).into_iter().enumerate() {
+            assert_eq!(evaluated?.exports, json!({ "index": index }));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn module_state_is_not_shared_between_jobs() -> eyre::Result<()> {
+        let test = TestPool::new()?;
+        let pool = EnginePool::new(test.sandbox.clone(), test.options(1))?;
+
+        test.write(
+            "counter.js",
+            "let count = 0;\nexport function next() { return ++count; }",
+        )?;
+        let module = test.write(
+            "main.js",
+            "import { next } from \"./counter.js\";\nexport const count = next();",
+        )?;
+
+        for evaluated in pool.evaluate_all([module.clone(), module]) {
+            assert_eq!(evaluated?.exports, json!({ "count": 1 }));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn queued_jobs_finish_before_shut_down() -> eyre::Result<()> {
+        let test = TestPool::new()?;
+        let pool = EnginePool::new(test.sandbox.clone(), test.options(1))?;
+
+        let module = test.write("main.js", "export const done = true;")?;
+        let pending: Vec<PendingEvaluation> =
+            (0..4).map(|_| pool.evaluate(module.clone())).collect();
+
+        drop(pool);
+
+        for pending in pending {
+            assert_eq!(pending.wait()?.exports, json!({ "done": true }));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn engine_creation_failure_is_reported() -> eyre::Result<()> {
+        let test = TestPool::new()?;
+
+        let mut options = test.options(2);
+        // a project engine must be deterministic
+        options.engine.determinism = None;
+
+        let pool = EnginePool::new(test.sandbox.clone(), options);
+
+        assert!(matches!(pool, Err(EnginePoolError::EngineCreationError(_))));
+        Ok(())
+    }
+}
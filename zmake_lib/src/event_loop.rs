@@ -0,0 +1,502 @@
+//! The event loop of an engine.
+//!
+//! Dynamic `import()` returns a promise at once, the module is read on the tokio runtime and
+//! evaluated by the loop. Async syscalls are ops: a tokio future whose output resolves the
+//! promise returned to the script. The loop runs until the promise of the executed module settles.
+
+use crate::engine::State;
+use crate::module_loader::{ModuleLoadError, ModuleType};
+use crate::module_specifier::ModuleSpecifier;
+use ahash::AHashMap;
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tracing::error;
+use v8::{Global, Local, PinScope, Promise, PromiseResolver, Value};
+
+/// The output of an op, converted to a JavaScript value on the engine thread.
+pub trait OpOutput: Send + 'static {
+    fn into_v8<'s, 'i>(self: Box<Self>, scope: &mut PinScope<'s, 'i>) -> Option<Local<'s, Value>>;
+}
+
+impl OpOutput for () {
+    fn into_v8<'s, 'i>(self: Box<Self>, scope: &mut PinScope<'s, 'i>) -> Option<Local<'s, Value>> {
+        Some(v8::undefined(scope).into())
+    }
+}
+
+impl OpOutput for bool {
+    fn into_v8<'s, 'i>(self: Box<Self>, scope: &mut PinScope<'s, 'i>) -> Option<Local<'s, Value>> {
+        Some(v8::Boolean::new(scope, *self).into())
+    }
+}
+
+impl OpOutput for f64 {
+    fn into_v8<'s, 'i>(self: Box<Self>, scope: &mut PinScope<'s, 'i>) -> Option<Local<'s, Value>> {
+        Some(v8::Number::new(scope, *self).into())
+    }
+}
+
+impl OpOutput for String {
+    fn into_v8<'s, 'i>(self: Box<Self>, scope: &mut PinScope<'s, 'i>) -> Option<Local<'s, Value>> {
+        Some(v8::String::new(scope, &self)?.into())
+    }
+}
+
+impl OpOutput for Vec<u8> {
+    fn into_v8<'s, 'i>(self: Box<Self>, scope: &mut PinScope<'s, 'i>) -> Option<Local<'s, Value>> {
+        let length = self.len();
+        let store = v8::ArrayBuffer::new_backing_store_from_vec(*self).make_shared();
+        let buffer = v8::ArrayBuffer::with_backing_store(scope, &store);
+
+        Some(v8::Uint8Array::new(scope, buffer, 0, length)?.into())
+    }
+}
+
+/// Structured output is converted through json.
+impl OpOutput for serde_json::Value {
+    fn into_v8<'s, 'i>(self: Box<Self>, scope: &mut PinScope<'s, 'i>) -> Option<Local<'s, Value>> {
+        let json = v8::String::new(scope, &self.to_string())?;
+
+        v8::json::parse(scope, json)
+    }
+}
+
+/// The promise of the op is rejected with an `Error` of the message.
+pub type OpResult = Result<Box<dyn OpOutput>, String>;
+
+/// A dynamic import waiting for its source.
+#[derive(Debug)]
+struct PendingImport {
+    resolver: Global<PromiseResolver>,
+    specifier: ModuleSpecifier,
+    module_type: ModuleType,
+}
+
+/// A dynamic import whose module is waiting for top-level await.
+#[derive(Debug)]
+struct EvaluatingImport {
+    resolver: Global<PromiseResolver>,
+    specifier: ModuleSpecifier,
+    module: Global<v8::Module>,
+    promise: Global<Promise>,
+}
+
+/// Sent to the engine thread when the work on the tokio runtime finishes.
+enum Completion {
+    /// The source of dynamic import is read, `None` if it is not a file.
+    Import {
+        id: u64,
+        source: Option<Result<String, ModuleLoadError>>,
+    },
+    Op {
+        id: u64,
+        result: OpResult,
+    },
+    /// Wake up the loop to check whether the execution is terminated.
+    Wake,
+}
+
+/// Stop the loop from other threads, it does not wait for the work in flight.
+#[derive(Debug, Clone)]
+pub struct EventLoopTerminator {
+    sender: UnboundedSender<Completion>,
+    terminated: Arc<AtomicBool>,
+}
+
+impl EventLoopTerminator {
+    pub fn terminate(&self) {
+        self.terminated.store(true, Ordering::SeqCst);
+
+        // the receiver is dropped with the engine
+        let _ = self.sender.send(Completion::Wake);
+    }
+}
+
+impl std::fmt::Debug for Completion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Completion::Import { id, .. } => write!(f, "Import({})", id),
+            Completion::Op { id, .. } => write!(f, "Op({})", id),
+            Completion::Wake => write!(f, "Wake"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct EventLoop {
+    tokio_handle: tokio::runtime::Handle,
+    next_id: Cell<u64>,
+    imports: RefCell<AHashMap<u64, PendingImport>>,
+    evaluating: RefCell<Vec<EvaluatingImport>>,
+    /// The promises of ops in flight.
+    ops: RefCell<AHashMap<u64, Global<PromiseResolver>>>,
+    terminated: Arc<AtomicBool>,
+    sender: UnboundedSender<Completion>,
+    receiver: RefCell<UnboundedReceiver<Completion>>,
+}
+
+impl EventLoop {
+    pub fn new(tokio_handle: tokio::runtime::Handle) -> Self {
+        let (sender, receiver) = unbounded_channel();
+
+        Self {
+            tokio_handle,
+            next_id: Cell::new(0),
+            imports: RefCell::new(AHashMap::new()),
+            evaluating: RefCell::new(Vec::new()),
+            ops: RefCell::new(AHashMap::new()),
+            terminated: Arc::new(AtomicBool::new(false)),
+            sender,
+            receiver: RefCell::new(receiver),
+        }
+    }
+
+    pub fn terminator(&self) -> EventLoopTerminator {
+        EventLoopTerminator {
+            sender: self.sender.clone(),
+            terminated: self.terminated.clone(),
+        }
+    }
+
+    /// Clear the termination that happens after the loop stops.
+    pub fn clear_terminated(&self) {
+        self.terminated.store(false, Ordering::SeqCst);
+    }
+
+    fn next_id(&self) -> u64 {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        id
+    }
+
+    /// Spawn the future on the tokio runtime and return a promise settled by its output.
+    pub fn spawn_op<'s, 'i, F, T>(
+        &self,
+        scope: &mut PinScope<'s, 'i>,
+        future: F,
+    ) -> Option<Local<'s, Promise>>
+    where
+        F: Future<Output = Result<T, String>> + Send + 'static,
+        T: OpOutput,
+    {
+        let resolver = PromiseResolver::new(scope)?;
+
+        let id = self.next_id();
+        let sender = self.sender.clone();
+
+        self.tokio_handle.spawn(async move {
+            let result = future
+                .await
+                .map(|output| Box::new(output) as Box<dyn OpOutput>);
+
+            // the receiver is dropped with the engine
+            let _ = sender.send(Completion::Op { id, result });
+        });
+
+        self.ops
+            .borrow_mut()
+            .insert(id, Global::new(scope, resolver));
+
+        Some(resolver.get_promise(scope))
+    }
+
+    /// Like `spawn_op`, for blocking work like reading files.
+    pub fn spawn_blocking_op<'s, 'i, F, T>(
+        &self,
+        scope: &mut PinScope<'s, 'i>,
+        work: F,
+    ) -> Option<Local<'s, Promise>>
+    where
+        F: FnOnce() -> Result<T, String> + Send + 'static,
+        T: OpOutput,
+    {
+        let task = self.tokio_handle.spawn_blocking(work);
+
+        self.spawn_op(
+            scope,
+            async move { task.await.map_err(|err| err.to_string())? },
+        )
+    }
+
+    fn finish_op<'s, 'i>(&self, scope: &mut PinScope<'s, 'i>, id: u64, result: OpResult) {
+        let Some(resolver) = self.ops.borrow_mut().remove(&id) else {
+            return;
+        };
+
+        let resolver = Local::new(scope, &resolver);
+
+        let settled = match result {
+            Ok(output) => match output.into_v8(scope) {
+                Some(value) => resolver.resolve(scope, value),
+                None => Self::reject_with(scope, resolver, "failed to convert the output of op"),
+            },
+            Err(message) => Self::reject_with(scope, resolver, &message),
+        };
+
+        if settled.is_none() {
+            error!("failed to settle the promise of op");
+        }
+    }
+
+    /// Queue a dynamic import, the resolver is settled by the loop.
+    pub fn queue_import(
+        &self,
+        state: &State,
+        resolver: Global<PromiseResolver>,
+        specifier: ModuleSpecifier,
+        module_type: ModuleType,
+    ) {
+        let id = self.next_id();
+
+        let sender = self.sender.clone();
+
+        match &specifier {
+            ModuleSpecifier::File(path)
+                if !state.module_loader.is_loaded(&specifier, module_type) =>
+            {
+                let sandbox = state.module_loader.get_sandbox();
+                let path = path.clone();
+
+                self.tokio_handle.spawn_blocking(move || {
+                    let source = crate::module_loader::read_file_source(&sandbox, &path);
+
+                    // the receiver is dropped with the engine
+                    let _ = sender.send(Completion::Import {
+                        id,
+                        source: Some(source),
+                    });
+                });
+            }
+            _ => {
+                let _ = sender.send(Completion::Import { id, source: None });
+            }
+        }
+
+        self.imports.borrow_mut().insert(
+            id,
+            PendingImport {
+                resolver,
+                specifier,
+                module_type,
+            },
+        );
+    }
+
+    /// Whether there is work on the tokio runtime to wait for.
+    fn is_waiting(&self) -> bool {
+        !self.imports.borrow().is_empty() || !self.ops.borrow().is_empty()
+    }
+
+    fn reject_with<'s, 'i>(
+        scope: &mut PinScope<'s, 'i>,
+        resolver: Local<'s, PromiseResolver>,
+        message: &str,
+    ) -> Option<bool> {
+        let message = v8::String::new(scope, message)?;
+        let exception = v8::Exception::error(scope, message);
+
+        resolver.reject(scope, exception)
+    }
+
+    fn reject<'s, 'i>(
+        scope: &mut PinScope<'s, 'i>,
+        resolver: Local<'s, PromiseResolver>,
+        err: &ModuleLoadError,
+    ) {
+        if Self::reject_with(scope, resolver, &err.to_string()).is_none() {
+            error!("failed to reject the promise of dynamic import: {}", err);
+        }
+    }
+
+    /// Compile and evaluate the imported module.
+    fn finish_import<'s, 'i>(
+        &self,
+        state: &State,
+        scope: &mut PinScope<'s, 'i>,
+        id: u64,
+        source: Option<Result<String, ModuleLoadError>>,
+    ) {
+        let Some(pending) = self.imports.borrow_mut().remove(&id) else {
+            return;
+        };
+
+        let resolver = Local::new(scope, &pending.resolver);
+
+        if let Some(source) = source {
+            match source {
+                Ok(source) => state
+                    .module_loader
+                    .preload_source(&pending.specifier, source),
+                Err(err) => return Self::reject(scope, resolver, &err),
+            }
+        }
+
+        let module = match state.module_loader.resolve_module_with_type(
+            scope,
+            &pending.specifier,
+            pending.module_type,
+        ) {
+            Ok(module) => module,
+            Err(err) => return Self::reject(scope, resolver, &err),
+        };
+
+        let result = match state.module_loader.instantiate_and_evaluate_module(
+            scope,
+            &pending.specifier,
+            &module,
+        ) {
+            Ok(result) => result,
+            Err(err) => return Self::reject(scope, resolver, &err),
+        };
+
+        match result.try_cast::<Promise>() {
+            Ok(promise) => self.evaluating.borrow_mut().push(EvaluatingImport {
+                resolver: pending.resolver,
+                specifier: pending.specifier,
+                module: Global::new(scope, module),
+                promise: Global::new(scope, promise),
+            }),
+            Err(_) => {
+                let namespace = module.get_module_namespace();
+
+                if resolver.resolve(scope, namespace).is_none() {
+                    error!("failed to resolve the promise of dynamic import");
+                }
+            }
+        }
+    }
+
+    /// Settle the dynamic imports whose module finished top-level await.
+    fn settle_evaluating<'s, 'i>(&self, scope: &mut PinScope<'s, 'i>) -> bool {
+        let evaluating = std::mem::take(&mut *self.evaluating.borrow_mut());
+        let mut progressed = false;
+
+        for import in evaluating {
+            let promise = Local::new(scope, &import.promise);
+
+            let resolver = Local::new(scope, &import.resolver);
+
+            match promise.state() {
+                v8::PromiseState::Pending => {
+                    self.evaluating.borrow_mut().push(import);
+                    continue;
+                }
+                v8::PromiseState::Fulfilled => {
+                    let namespace = Local::new(scope, &import.module).get_module_namespace();
+
+                    if resolver.resolve(scope, namespace).is_none() {
+                        error!("failed to resolve the promise of dynamic import");
+                    }
+                }
+                v8::PromiseState::Rejected => {
+                    let exception = promise.result(scope);
+
+                    if resolver.reject(scope, exception).is_none() {
+                        error!(
+                            "failed to reject the promise of dynamic import `{}`",
+                            import.specifier
+                        );
+                    }
+                }
+            }
+
+            progressed = true;
+        }
+
+        progressed
+    }
+
+    /// Whether there is async work that is not settled, including the imports in evaluation.
+    fn is_pending(&self) -> bool {
+        self.is_waiting() || !self.evaluating.borrow().is_empty()
+    }
+
+    /// Drop the promises of the async work, the completions that arrive later are ignored.
+    fn cancel(&self) {
+        self.imports.borrow_mut().clear();
+        self.evaluating.borrow_mut().clear();
+        self.ops.borrow_mut().clear();
+    }
+
+    /// Run the loop until the promise settles and the async work it started is done.
+    ///
+    /// It is an error if the promise or the async work is pending but there is nothing to wait
+    /// for. If the execution fails, the work in flight is cancelled.
+    pub fn run_until_settled<'s, 'i>(
+        &self,
+        state: &State,
+        scope: &mut PinScope<'s, 'i>,
+        specifier: &ModuleSpecifier,
+        promise: Local<'s, Promise>,
+    ) -> Result<(), ModuleLoadError> {
+        let result = self.drive(state, scope, specifier, promise);
+
+        if result.is_err() {
+            self.cancel();
+        }
+
+        result
+    }
+
+    fn drive<'s, 'i>(
+        &self,
+        state: &State,
+        scope: &mut PinScope<'s, 'i>,
+        specifier: &ModuleSpecifier,
+        promise: Local<'s, Promise>,
+    ) -> Result<(), ModuleLoadError> {
+        loop {
+            scope.perform_microtask_checkpoint();
+
+            if self.terminated.load(Ordering::SeqCst) || scope.is_execution_terminating() {
+                return Err(ModuleLoadError::Terminated(specifier.clone()));
+            }
+
+            if self.settle_evaluating(scope) {
+                continue;
+            }
+
+            match promise.state() {
+                // the work that is not awaited, e.g. `import()` without `await`, is drained
+                v8::PromiseState::Fulfilled if !self.is_pending() => return Ok(()),
+                v8::PromiseState::Fulfilled | v8::PromiseState::Pending => {}
+                v8::PromiseState::Rejected => {
+                    let exception = promise.result(scope);
+
+                    return Err(state.module_loader.exception_to_error(
+                        scope,
+                        specifier,
+                        Some(exception),
+                    ));
+                }
+            }
+
+            let completion = {
+                let mut receiver = self.receiver.borrow_mut();
+
+                match receiver.try_recv() {
+                    Ok(completion) => Some(completion),
+                    Err(_) if self.is_waiting() => self.tokio_handle.block_on(receiver.recv()),
+                    Err(_) => None,
+                }
+            };
+
+            match completion {
+                Some(Completion::Import { id, source }) => {
+                    self.finish_import(state, scope, id, source)
+                }
+                Some(Completion::Op { id, result }) => self.finish_op(scope, id, result),
+                Some(Completion::Wake) => {}
+                None if matches!(promise.state(), v8::PromiseState::Fulfilled) => {
+                    return Err(ModuleLoadError::DanglingAsyncWork(specifier.clone()));
+                }
+                None => {
+                    return Err(ModuleLoadError::UnsettledTopLevelAwait(specifier.clone()));
+                }
+            }
+        }
+    }
+}
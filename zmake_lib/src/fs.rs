@@ -1,14 +1,76 @@
+use ahash::{AHashMap, AHashSet};
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::Path;
 use thiserror::Error;
 
 use crate::digest::{Digest, DigestError};
 
+/// The max count of symlinks followed when resolving a path in [VirtualTree].
+///
+/// Same as the `MAXSYMLINKS` of linux.
+pub const MAX_SYMLINK_FOLLOWS: usize = 40;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum FsItem {
     File(Digest),
-    Symlink(NeutralPath),
+    Symlink(SymlinkTarget),
     EmptyDirectory,
 }
 
+/// The target of a symlink, relative to the directory containing the link.
+///
+/// Unlike [NeutralPath], `..` is kept where it is, because `link/..` is the parent of the
+/// target of `link`, not the directory containing `link`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SymlinkTarget(String);
+
+impl SymlinkTarget {
+    pub fn new<S: AsRef<str>>(target: S) -> Result<Self, VirtualFileError> {
+        let target = target.as_ref();
+
+        // reuse the checks of absolute path and invalid names
+        NeutralPath::new(target).map_err(|err| match err {
+            PathError::PathIsAbsolute() => VirtualFileError::SymlinkTargetAbsolute,
+            err => VirtualFileError::PathError(err),
+        })?;
+
+        let components = target
+            .split(['/', '\\'])
+            .filter(|part| !part.is_empty() && *part != ".")
+            .collect::<Vec<_>>();
+
+        if components.is_empty() {
+            Ok(Self(".".to_string()))
+        } else {
+            Ok(Self(components.join("/")))
+        }
+    }
+
+    /// Iterate over the components of the target, `.` yields nothing.
+    pub fn components(&self) -> impl DoubleEndedIterator<Item = &str> {
+        self.0.split('/').filter(|part| *part != ".")
+    }
+}
+
+impl fmt::Display for SymlinkTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl AsRef<str> for SymlinkTarget {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for SymlinkTarget {
+    fn as_ref(&self) -> &Path {
+        Path::new(&self.0)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct VirtualFsItem {
     relative_path: NeutralPath,
@@ -31,6 +93,12 @@ pub enum VirtualFileError {
     SymlinkTargetAbsolute,
     #[error("symlink target try to access out of sandbox via '..'")]
     SymbolLinkEscape,
+    #[error("detect symlink cycle when resolving `{0}`")]
+    SymlinkCycle(NeutralPath),
+    #[error("follow more than {max} symlinks when resolving `{0}`", max = MAX_SYMLINK_FOLLOWS)]
+    TooManySymlinks(NeutralPath),
+    #[error("the path `{0}` is declared more than once in the virtual tree")]
+    DuplicatePath(NeutralPath),
     #[error("wrong digest: {0}")]
     WrongDigest(DigestError),
     #[error("the provided path is not normalized")]
@@ -52,11 +120,18 @@ impl VirtualFsItem {
         is_executable: bool,
         is_readonly: bool,
     ) -> Result<Self, VirtualFileError> {
+        if relative_path.is_escaping() {
+            return Err(VirtualFileError::TryAccessParentPath());
+        }
+
         if let FsItem::Symlink(ref target) = item {
-            let target = relative_path.join(target)?;
+            // the target of symlink is relative to the directory containing the link,
+            // the links inside the target are checked by `VirtualTree::resolve`
+            let depth = relative_path.parent().components().count();
+            let parents = target.components().take_while(|part| *part == "..").count();
 
-            if target.is_in_dir(&relative_path) {
-                return Err(VirtualFileError::TryAccessParentPath());
+            if parents > depth {
+                return Err(VirtualFileError::SymbolLinkEscape);
             }
         }
 
@@ -98,7 +173,7 @@ impl TryFrom<crate::proto::fs::VirtualFsItem> for VirtualFsItem {
                 d.try_into()
                     .map_err(|err| VirtualFileError::WrongDigest(err))?,
             ),
-            Some(ProtoItem::SymlinkTarget(s)) => FsItem::Symlink(NeutralPath::new(s)?),
+            Some(ProtoItem::SymlinkTarget(s)) => FsItem::Symlink(SymlinkTarget::new(s)?),
             Some(ProtoItem::EmptyDirectory(_)) => FsItem::EmptyDirectory,
             None => return Err(VirtualFileError::EmptyPath()),
         };
@@ -111,3 +186,273 @@ impl TryFrom<crate::proto::fs::VirtualFsItem> for VirtualFsItem {
         )
     }
 }
+
+/// A set of [VirtualFsItem] that forms a directory tree.
+///
+/// All symlinks in the tree are checked that they do not escape the tree and do not form a cycle.
+#[derive(Debug, Clone, Default)]
+pub struct VirtualTree {
+    items: AHashMap<NeutralPath, VirtualFsItem>,
+}
+
+impl VirtualTree {
+    pub fn new<I: IntoIterator<Item = VirtualFsItem>>(items: I) -> Result<Self, VirtualFileError> {
+        let mut tree = Self::default();
+
+        for item in items {
+            let path = item.get_relative_path().clone();
+
+            if tree.items.insert(path.clone(), item).is_some() {
+                return Err(VirtualFileError::DuplicatePath(path));
+            }
+        }
+
+        for (path, item) in tree.items.iter() {
+            if let FsItem::Symlink(_) = item.get_digest() {
+                tree.resolve(path)?;
+            }
+        }
+
+        Ok(tree)
+    }
+
+    pub fn get(&self, path: &NeutralPath) -> Option<&VirtualFsItem> {
+        self.items.get(path)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &VirtualFsItem> {
+        self.items.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Resolve the path to the final target by following every symlink in it.
+    ///
+    /// The returned path is not guaranteed to exist in the tree(dangling symlink is allowed).
+    pub fn resolve(&self, path: &NeutralPath) -> Result<NeutralPath, VirtualFileError> {
+        if path.is_escaping() {
+            return Err(VirtualFileError::TryAccessParentPath());
+        }
+
+        let mut remaining: VecDeque<String> = path.components().map(String::from).collect();
+        // `current` never contains a symlink, so `..` can be applied to it lexically
+        let mut current = NeutralPath::current_dir();
+        let mut followed = 0usize;
+        // following the same link with the same rest of path again is a cycle
+        let mut seen: AHashSet<(NeutralPath, VecDeque<String>)> = AHashSet::new();
+
+        while let Some(component) = remaining.pop_front() {
+            if component == ".." {
+                current = current.parent();
+
+                if current.is_escaping() {
+                    return Err(VirtualFileError::SymbolLinkEscape);
+                }
+
+                continue;
+            }
+
+            let next = current.join(&component)?;
+
+            match self.items.get(&next).map(|item| item.get_digest()) {
+                Some(FsItem::Symlink(target)) => {
+                    if !seen.insert((next, remaining.clone())) {
+                        return Err(VirtualFileError::SymlinkCycle(path.clone()));
+                    }
+
+                    followed += 1;
+
+                    if followed > MAX_SYMLINK_FOLLOWS {
+                        return Err(VirtualFileError::TooManySymlinks(path.clone()));
+                    }
+
+                    // continue from the directory containing the link
+                    for part in target.components().rev() {
+                        remaining.push_front(part.to_string());
+                    }
+                }
+                _ => {
+                    current = next;
+                }
+            }
+        }
+
+        Ok(current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(path: &str) -> Result<NeutralPath, VirtualFileError> {
+        Ok(NeutralPath::new(path)?)
+    }
+
+    fn file(at: &str) -> Result<VirtualFsItem, VirtualFileError> {
+        VirtualFsItem::new(path(at)?, FsItem::File(Digest::new(0, 0)), false, true)
+    }
+
+    fn directory(at: &str) -> Result<VirtualFsItem, VirtualFileError> {
+        VirtualFsItem::new(path(at)?, FsItem::EmptyDirectory, false, true)
+    }
+
+    fn link(at: &str, target: &str) -> Result<VirtualFsItem, VirtualFileError> {
+        VirtualFsItem::new(
+            path(at)?,
+            FsItem::Symlink(SymlinkTarget::new(target)?),
+            false,
+            true,
+        )
+    }
+
+    #[test]
+    fn absolute_target_is_rejected() {
+        assert!(matches!(
+            SymlinkTarget::new("/etc/passwd"),
+            Err(VirtualFileError::SymlinkTargetAbsolute)
+        ));
+        assert!(matches!(
+            SymlinkTarget::new("C:\\Windows"),
+            Err(VirtualFileError::SymlinkTargetAbsolute)
+        ));
+    }
+
+    #[test]
+    fn escaping_target_is_rejected() {
+        assert!(matches!(
+            link("link", "../outside"),
+            Err(VirtualFileError::SymbolLinkEscape)
+        ));
+        assert!(matches!(
+            link("a/link", "../../outside"),
+            Err(VirtualFileError::SymbolLinkEscape)
+        ));
+    }
+
+    #[test]
+    fn escaping_through_another_link_is_rejected() -> Result<(), VirtualFileError> {
+        // lexically `a/deep/../..` is the root, but `a/deep` is the root already
+        let items = vec![
+            directory("a")?,
+            link("a/deep", "..")?,
+            link("a/up", "deep/../..")?,
+        ];
+
+        assert!(matches!(
+            VirtualTree::new(items),
+            Err(VirtualFileError::SymbolLinkEscape)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn target_is_relative_to_parent_of_link() -> Result<(), VirtualFileError> {
+        let tree = VirtualTree::new(vec![
+            file("a/file")?,
+            link("a/b/link", "../file")?,
+            link("a/same", "file")?,
+        ])?;
+
+        assert_eq!(tree.resolve(&path("a/b/link")?)?, path("a/file")?);
+        assert_eq!(tree.resolve(&path("a/same")?)?, path("a/file")?);
+        Ok(())
+    }
+
+    #[test]
+    fn parent_of_link_is_resolved_after_the_link() -> Result<(), VirtualFileError> {
+        // `a/deep/../file` is `a/b/file`, not `a/file`
+        let tree = VirtualTree::new(vec![
+            file("a/b/file")?,
+            directory("a/b/c")?,
+            link("a/deep", "b/c")?,
+            link("a/link", "deep/../file")?,
+        ])?;
+
+        assert_eq!(tree.resolve(&path("a/link")?)?, path("a/b/file")?);
+        Ok(())
+    }
+
+    #[test]
+    fn link_inside_the_path_is_followed() -> Result<(), VirtualFileError> {
+        let tree = VirtualTree::new(vec![file("real/file")?, link("alias", "real")?])?;
+
+        assert_eq!(tree.resolve(&path("alias/file")?)?, path("real/file")?);
+        Ok(())
+    }
+
+    #[test]
+    fn dangling_link_is_allowed() -> Result<(), VirtualFileError> {
+        let tree = VirtualTree::new(vec![link("link", "missing")?])?;
+
+        assert_eq!(tree.resolve(&path("link")?)?, path("missing")?);
+        Ok(())
+    }
+
+    #[test]
+    fn two_link_cycle_is_detected() -> Result<(), VirtualFileError> {
+        let items = vec![link("a", "b")?, link("b", "a")?];
+
+        assert!(matches!(
+            VirtualTree::new(items),
+            Err(VirtualFileError::SymlinkCycle(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn self_loop_is_detected() -> Result<(), VirtualFileError> {
+        let items = vec![link("dir/self", "self")?];
+
+        assert!(matches!(
+            VirtualTree::new(items),
+            Err(VirtualFileError::SymlinkCycle(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_path_is_rejected() -> Result<(), VirtualFileError> {
+        let items = vec![file("same")?, link("same", "other")?];
+
+        assert!(matches!(
+            VirtualTree::new(items),
+            Err(VirtualFileError::DuplicatePath(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn long_chain_is_not_a_cycle() -> Result<(), VirtualFileError> {
+        let chain = |length: usize| -> Result<Vec<VirtualFsItem>, VirtualFileError> {
+            let mut items = vec![file("end")?];
+
+            for index in 0..length {
+                let target = if index + 1 == length {
+                    "end".to_string()
+                } else {
+                    format!("link{}", index + 1)
+                };
+
+                items.push(link(&format!("link{}", index), &target)?);
+            }
+
+            Ok(items)
+        };
+
+        let tree = VirtualTree::new(chain(MAX_SYMLINK_FOLLOWS)?)?;
+        assert_eq!(tree.resolve(&path("link0")?)?, path("end")?);
+
+        assert!(matches!(
+            VirtualTree::new(chain(MAX_SYMLINK_FOLLOWS + 1)?),
+            Err(VirtualFileError::TooManySymlinks(_))
+        ));
+        Ok(())
+    }
+}
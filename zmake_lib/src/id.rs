@@ -2,7 +2,7 @@ use semver::Version;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::str::FromStr;
-use strum::{EnumString, IntoStaticStr};
+use strum::{EnumIter, EnumString, IntoStaticStr};
 use thiserror::Error;
 use unicode_ident::{is_xid_continue, is_xid_start};
 
@@ -239,7 +239,17 @@ impl QualifiedArtifactId {
 
 /// modify [make_builtin](crate::make_builtin)'s use IdType::{} too
 #[derive(
-    IntoStaticStr, EnumString, Copy, Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize,
+    IntoStaticStr,
+    EnumString,
+    EnumIter,
+    Copy,
+    Debug,
+    Clone,
+    Eq,
+    PartialEq,
+    Hash,
+    Serialize,
+    Deserialize,
 )]
 #[strum(serialize_all = "snake_case")]
 pub enum IdType {
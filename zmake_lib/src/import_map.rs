@@ -0,0 +1,370 @@
+//! Import maps let build scripts import files by `@`-prefixed names.
+//!
+//! The import map is a json file at the project root:
+//!
+//! ```json
+//! {
+//!     "imports": {
+//!         "@utils": "./scripts/utils.ts",
+//!         "@toolchains/": "./scripts/toolchains/"
+//!     },
+//!     "scopes": {
+//!         "./vendor/": {
+//!             "@utils": "./vendor/utils.ts"
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! A key ending with `/` maps every specifier starting with it. Targets and scopes are
+//! relative to the project root and can not leave it. The mappings of the longest scope
+//! containing the importer are used first.
+//!
+//! The file is an input of every evaluation. Declaring the import map in `project()` is not
+//! supported: the declaration is evaluated by the engine whose imports it would configure.
+
+use crate::module_specifier::IMPORT_MAP_MODULE_PREFIX;
+use crate::path::{NeutralPath, PathError};
+use crate::sandbox::{Sandbox, SandboxError};
+use ahash::AHashMap;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Read;
+use thiserror::Error;
+
+pub static IMPORT_MAP_FILE: &'static str = "zmake.imports.json";
+
+#[derive(Error, Debug)]
+pub enum ImportMapError {
+    #[error("invalid import map json: {0}")]
+    InvalidJson(#[from] serde_json::Error),
+    #[error("invalid import map entry `{entry}`: the key must start with `@` and not be empty")]
+    InvalidKey { entry: String },
+    #[error(
+        "invalid import map entry `{entry}`: the target `{target}` is not a valid path: {source}"
+    )]
+    InvalidTarget {
+        entry: String,
+        target: String,
+        source: PathError,
+    },
+    #[error("invalid import map entry `{entry}`: the target `{target}` is out of the project")]
+    TargetEscape { entry: String, target: String },
+    #[error(
+        "invalid import map entry `{entry}`: the key ends with `/`, but the target `{target}` does not"
+    )]
+    PrefixMismatch { entry: String, target: String },
+    #[error("the import `{specifier}` is mapped out of the project")]
+    SpecifierEscape { specifier: String },
+    #[error("invalid import map scope `{scope}`: {reason}")]
+    InvalidScope { scope: String, reason: String },
+    #[error("failed to read import map: {0}")]
+    PathError(#[from] PathError),
+    #[error("failed to read import map: {0}")]
+    SandboxError(#[from] SandboxError),
+    #[error("failed to read import map: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawImportMap {
+    #[serde(default)]
+    imports: BTreeMap<String, String>,
+    #[serde(default)]
+    scopes: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Mappings {
+    /// Without `IMPORT_MAP_MODULE_PREFIX`.
+    exact: AHashMap<String, NeutralPath>,
+    /// Sorted by the length of the key, the longest first.
+    prefixes: Vec<(String, NeutralPath)>,
+}
+
+impl Mappings {
+    fn parse(raw: BTreeMap<String, String>) -> Result<Self, ImportMapError> {
+        let mut mappings = Self::default();
+
+        for (entry, target) in raw {
+            let Some(key) = entry.strip_prefix(IMPORT_MAP_MODULE_PREFIX) else {
+                return Err(ImportMapError::InvalidKey { entry });
+            };
+
+            if key.is_empty() {
+                return Err(ImportMapError::InvalidKey { entry });
+            }
+
+            let path = match NeutralPath::new(&target) {
+                Ok(path) => path,
+                Err(source) => {
+                    return Err(ImportMapError::InvalidTarget {
+                        entry,
+                        target,
+                        source,
+                    });
+                }
+            };
+
+            if path.is_escaping() {
+                return Err(ImportMapError::TargetEscape { entry, target });
+            }
+
+            if key.ends_with('/') {
+                if !target.ends_with('/') {
+                    return Err(ImportMapError::PrefixMismatch { entry, target });
+                }
+
+                mappings.prefixes.push((key.to_string(), path));
+            } else {
+                mappings.exact.insert(key.to_string(), path);
+            }
+        }
+
+        mappings.prefixes.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+
+        Ok(mappings)
+    }
+
+    fn resolve(&self, specifier: &str) -> Option<Result<NeutralPath, ImportMapError>> {
+        if let Some(path) = self.exact.get(specifier) {
+            return Some(Ok(path.clone()));
+        }
+
+        self.prefixes.iter().find_map(|(prefix, path)| {
+            let rest = specifier.strip_prefix(prefix.as_str())?;
+
+            // the rest may have `..`, e.g. `@lib/../../secret`
+            Some(match path.join(rest) {
+                Ok(path) if path.is_escaping() => Err(ImportMapError::SpecifierEscape {
+                    specifier: specifier.to_string(),
+                }),
+                Ok(path) => Ok(path),
+                Err(err) => Err(err.into()),
+            })
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ImportMap {
+    imports: Mappings,
+    /// Sorted by the depth of the scope, the deepest first.
+    scopes: Vec<(NeutralPath, Mappings)>,
+    /// The file the map is loaded from.
+    file: Option<NeutralPath>,
+}
+
+impl ImportMap {
+    pub fn from_json(json: &str) -> Result<Self, ImportMapError> {
+        let raw: RawImportMap = serde_json::from_str(json)?;
+
+        let mut scopes = Vec::with_capacity(raw.scopes.len());
+
+        for (scope, mappings) in raw.scopes {
+            let path = NeutralPath::new(&scope).map_err(|err| ImportMapError::InvalidScope {
+                scope: scope.clone(),
+                reason: err.to_string(),
+            })?;
+
+            if path.is_escaping() {
+                return Err(ImportMapError::InvalidScope {
+                    scope,
+                    reason: "the scope is out of the project".to_string(),
+                });
+            }
+
+            scopes.push((path, Mappings::parse(mappings)?));
+        }
+
+        scopes.sort_by(|a, b| b.0.components().count().cmp(&a.0.components().count()));
+
+        Ok(Self {
+            imports: Mappings::parse(raw.imports)?,
+            scopes,
+            file: None,
+        })
+    }
+
+    /// Load `IMPORT_MAP_FILE` at the sandbox root, `None` if it does not exist.
+    pub fn load(sandbox: &Sandbox) -> Result<Option<Self>, ImportMapError> {
+        let path = NeutralPath::new(IMPORT_MAP_FILE)?;
+
+        let mut file = match sandbox.open_file(&path) {
+            Ok(file) => file,
+            Err(SandboxError::IoError(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(None);
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut json = String::new();
+        file.read_to_string(&mut json)?;
+
+        Ok(Some(Self {
+            file: Some(path),
+            ..Self::from_json(&json)?
+        }))
+    }
+
+    /// The file the map is loaded from, `None` if it is not loaded from a file.
+    pub fn get_file(&self) -> Option<&NeutralPath> {
+        self.file.as_ref()
+    }
+
+    /// Resolve the specifier(without `IMPORT_MAP_MODULE_PREFIX`) to a path relative to the project root.
+    ///
+    /// The referrer is relative to the project root, it selects the scope.
+    pub fn resolve(
+        &self,
+        referrer: Option<&NeutralPath>,
+        specifier: &str,
+    ) -> Option<Result<NeutralPath, ImportMapError>> {
+        if let Some(referrer) = referrer {
+            for (scope, mappings) in &self.scopes {
+                if referrer.is_in_dir(scope)
+                    && let Some(resolved) = mappings.resolve(specifier)
+                {
+                    return Some(resolved);
+                }
+            }
+        }
+
+        self.imports.resolve(specifier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(path: &str) -> Result<NeutralPath, PathError> {
+        NeutralPath::new(path)
+    }
+
+    fn resolve(
+        map: &ImportMap,
+        referrer: Option<&str>,
+        specifier: &str,
+    ) -> Result<Option<NeutralPath>, ImportMapError> {
+        let referrer = referrer.map(path).transpose()?;
+
+        map.resolve(referrer.as_ref(), specifier).transpose()
+    }
+
+    #[test]
+    fn exact_and_prefix_mappings() -> Result<(), ImportMapError> {
+        let map = ImportMap::from_json(
+            r#"{
+                "imports": {
+                    "@utils": "./scripts/utils.ts",
+                    "@toolchains/": "./scripts/toolchains/",
+                    "@toolchains/gcc/": "./vendor/gcc/"
+                }
+            }"#,
+        )?;
+
+        assert_eq!(
+            resolve(&map, None, "utils")?,
+            Some(path("scripts/utils.ts")?)
+        );
+        assert_eq!(
+            resolve(&map, None, "toolchains/clang.ts")?,
+            Some(path("scripts/toolchains/clang.ts")?)
+        );
+        // the longest prefix wins
+        assert_eq!(
+            resolve(&map, None, "toolchains/gcc/index.ts")?,
+            Some(path("vendor/gcc/index.ts")?)
+        );
+        assert_eq!(resolve(&map, None, "unknown")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn deepest_scope_is_used_first() -> Result<(), ImportMapError> {
+        let map = ImportMap::from_json(
+            r#"{
+                "imports": { "@utils": "./utils.ts" },
+                "scopes": {
+                    "./vendor/": { "@utils": "./vendor/utils.ts" },
+                    "./vendor/legacy/": { "@utils": "./vendor/legacy/utils.ts" }
+                }
+            }"#,
+        )?;
+
+        assert_eq!(
+            resolve(&map, Some("main.ts"), "utils")?,
+            Some(path("utils.ts")?)
+        );
+        assert_eq!(
+            resolve(&map, Some("vendor/lib.ts"), "utils")?,
+            Some(path("vendor/utils.ts")?)
+        );
+        assert_eq!(
+            resolve(&map, Some("vendor/legacy/lib.ts"), "utils")?,
+            Some(path("vendor/legacy/utils.ts")?)
+        );
+        // memory modules have no path, only the top-level imports apply
+        assert_eq!(resolve(&map, None, "utils")?, Some(path("utils.ts")?));
+        Ok(())
+    }
+
+    #[test]
+    fn scope_falls_back_to_imports() -> Result<(), ImportMapError> {
+        let map = ImportMap::from_json(
+            r#"{
+                "imports": { "@config": "./config.ts" },
+                "scopes": { "./vendor/": { "@utils": "./vendor/utils.ts" } }
+            }"#,
+        )?;
+
+        assert_eq!(
+            resolve(&map, Some("vendor/lib.ts"), "config")?,
+            Some(path("config.ts")?)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn escaping_targets_are_rejected() {
+        assert!(matches!(
+            ImportMap::from_json(r#"{ "imports": { "@secret": "../secret.ts" } }"#),
+            Err(ImportMapError::TargetEscape { .. })
+        ));
+        assert!(matches!(
+            ImportMap::from_json(r#"{ "scopes": { "../": { "@utils": "./utils.ts" } } }"#),
+            Err(ImportMapError::InvalidScope { .. })
+        ));
+    }
+
+    #[test]
+    fn escaping_specifier_is_rejected() -> Result<(), ImportMapError> {
+        let map = ImportMap::from_json(r#"{ "imports": { "@lib/": "./lib/" } }"#)?;
+
+        assert!(matches!(
+            resolve(&map, None, "lib/../../secret.ts"),
+            Err(ImportMapError::SpecifierEscape { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_entries_are_named() {
+        for (json, entry) in [
+            (r#"{ "imports": { "utils": "./utils.ts" } }"#, "utils"),
+            (r#"{ "imports": { "@": "./utils.ts" } }"#, "@"),
+        ] {
+            match ImportMap::from_json(json) {
+                Err(ImportMapError::InvalidKey { entry: bad }) => assert_eq!(bad, entry),
+                other => panic!("`{}` is accepted: {:?}", json, other),
+            }
+        }
+
+        assert!(matches!(
+            ImportMap::from_json(r#"{ "imports": { "@lib/": "./lib" } }"#),
+            Err(ImportMapError::PrefixMismatch { .. })
+        ));
+    }
+}
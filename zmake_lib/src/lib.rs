@@ -1,16 +1,24 @@
 pub mod access_control;
+mod action;
+pub mod audit;
 pub mod build_constants;
 pub mod builtin;
 mod cas;
 mod cas_server;
+mod chunking;
 pub mod configuration;
+pub mod determinism;
 mod digest;
 pub mod engine;
+pub mod engine_pool;
 mod error;
+mod event_loop;
 mod extension;
 pub mod file_finder;
 pub mod fs;
 pub mod id;
+pub mod import_map;
+mod limits;
 mod local_cas;
 mod make_builtin;
 mod module_loader;
@@ -21,12 +29,19 @@ mod platform;
 pub mod project;
 pub mod project_resolver;
 pub mod sandbox;
+mod script_cache;
+pub mod script_error;
+pub mod snapshot;
 pub mod socket_address;
+mod source_map;
 pub mod target;
+#[cfg(test)]
+mod test_util;
 mod tool;
 mod transformer;
 mod transport_server;
 pub mod version_extractor;
+pub mod version_requirement;
 
 pub mod proto {
     pub mod digest {
@@ -0,0 +1,125 @@
+//! Resource limits of engines, a runaway script is terminated instead of hanging or
+//! crashing zmake.
+
+use crate::event_loop::EventLoopTerminator;
+use std::ffi::c_void;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tracing::error;
+
+/// Terminate the execution when the heap is near the limit.
+pub struct HeapLimit {
+    handle: v8::IsolateHandle,
+    exceeded: AtomicBool,
+}
+
+impl std::fmt::Debug for HeapLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HeapLimit")
+            .field("exceeded", &self.exceeded)
+            .finish_non_exhaustive()
+    }
+}
+
+extern "C" fn near_heap_limit_callback(
+    data: *mut c_void,
+    current_heap_limit: usize,
+    _initial_heap_limit: usize,
+) -> usize {
+    // SAFETY: the data is the boxed `HeapLimit` of the engine, which is dropped after the isolate
+    let limit = unsafe { &*(data as *const HeapLimit) };
+
+    limit.exceeded.store(true, Ordering::SeqCst);
+    limit.handle.terminate_execution();
+
+    // give V8 room to unwind the script instead of aborting the process,
+    // `HeapLimit::reset` restores the limit afterwards
+    current_heap_limit.saturating_mul(2)
+}
+
+impl HeapLimit {
+    /// The returned box must outlive the isolate.
+    pub fn install(isolate: &mut v8::OwnedIsolate) -> Box<Self> {
+        let limit = Box::new(Self {
+            handle: isolate.thread_safe_handle(),
+            exceeded: AtomicBool::new(false),
+        });
+
+        isolate.add_near_heap_limit_callback(
+            near_heap_limit_callback,
+            &*limit as *const Self as *mut c_void,
+        );
+
+        limit
+    }
+
+    /// Restore the heap limit raised by the callback, so that a reused engine keeps its limit.
+    pub fn reset(&self, isolate: &mut v8::Isolate, max_heap_size: usize) {
+        isolate.remove_near_heap_limit_callback(near_heap_limit_callback, max_heap_size);
+        isolate.add_near_heap_limit_callback(
+            near_heap_limit_callback,
+            self as *const Self as *mut c_void,
+        );
+    }
+
+    /// Whether the limit was reached since the last call.
+    pub fn take_exceeded(&self) -> bool {
+        self.exceeded.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// Terminate the execution if it runs longer than the timeout.
+#[derive(Debug)]
+pub struct Watchdog {
+    sender: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+    fired: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    pub fn start(
+        handle: v8::IsolateHandle,
+        terminator: EventLoopTerminator,
+        timeout: Duration,
+    ) -> std::io::Result<Self> {
+        let (sender, receiver) = std::sync::mpsc::channel::<()>();
+        let fired = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let fired = fired.clone();
+
+            std::thread::Builder::new()
+                .name("zmake-watchdog".to_string())
+                .spawn(move || {
+                    // the sender is dropped when the execution finishes
+                    if let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(timeout) {
+                        fired.store(true, Ordering::SeqCst);
+                        handle.terminate_execution();
+                        terminator.terminate();
+                    }
+                })?
+        };
+
+        Ok(Self {
+            sender: Some(sender),
+            thread: Some(thread),
+            fired,
+        })
+    }
+
+    /// Stop watching, return whether the execution was terminated.
+    pub fn stop(mut self) -> bool {
+        drop(self.sender.take());
+
+        if let Some(thread) = self.thread.take()
+            && thread.join().is_err()
+        {
+            error!("the watchdog thread panicked");
+        }
+
+        self.fired.load(Ordering::SeqCst)
+    }
+}
@@ -1,77 +1,220 @@
 use crate::cas::{Cas, CasError};
+use crate::chunking::{
+    ChunkManifest, Chunker, ChunkingError, ChunkingOptions, DigestBuilder, digest_of,
+};
 use crate::digest::Digest;
 use async_trait::async_trait;
-use std::path::PathBuf;
+use bytes::{Bytes, BytesMut};
+use prost::Message;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use tokio::fs;
-use tokio::io::{AsyncRead, AsyncSeekExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+use tokio_stream::{Stream, StreamExt};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+static MANIFEST_DIRECTORY: &'static str = "manifests";
 
 #[derive(Debug)]
 pub struct LocalCas {
     root: PathBuf,
+    chunker: Option<Chunker>,
 }
 
 impl LocalCas {
     pub fn new(root: PathBuf) -> Self {
-        Self { root }
+        Self {
+            root,
+            chunker: None,
+        }
+    }
+
+    /// Large blobs will be stored as a manifest of content-defined chunks.
+    pub fn new_with_chunking(
+        root: PathBuf,
+        options: ChunkingOptions,
+    ) -> Result<Self, ChunkingError> {
+        Ok(Self {
+            root,
+            chunker: Some(Chunker::new(options)?),
+        })
     }
 
     pub fn get_root(&self) -> &PathBuf {
         &self.root
     }
-}
 
-#[async_trait]
-impl Cas for LocalCas {
-    async fn store(
-        &self,
-        digest: &Digest,
-        mut data: Box<dyn AsyncRead + Send + Unpin + 'static>,
-    ) -> Result<(), CasError> {
+    fn blob_path(&self, digest: &Digest) -> PathBuf {
         let hex = digest.hex_fast_xxhash3_128();
 
-        let first_prefix = &hex[0..2];
-        let second_prefix = &hex[2..4];
-        let suffix = &hex[4..];
+        self.root.join(&hex[0..2]).join(&hex[2..4]).join(&hex[4..])
+    }
 
-        let first_dir = self.root.join(first_prefix);
-        let second_dir = first_dir.join(second_prefix);
-        let target_path = second_dir.join(suffix);
+    fn manifest_path(&self, digest: &Digest) -> PathBuf {
+        let hex = digest.hex_fast_xxhash3_128();
 
-        if target_path.exists() {
-            return Ok(());
-        }
+        self.root
+            .join(MANIFEST_DIRECTORY)
+            .join(&hex[0..2])
+            .join(&hex[2..4])
+            .join(&hex[4..])
+    }
 
-        fs::create_dir_all(&target_path)
+    async fn write_atomically<R: AsyncRead + Unpin + ?Sized>(
+        target_path: &Path,
+        data: &mut R,
+    ) -> Result<(), CasError> {
+        let parent = target_path.parent().ok_or_else(|| {
+            CasError::Internal(format!("invalid cas path {}", target_path.display()))
+        })?;
+
+        fs::create_dir_all(parent)
             .await
             .map_err(|err| CasError::Io(err.into()))?;
 
         let temp_name = format!("tmp_{}", uuid::Uuid::new_v4());
-        let temp_path = target_path.join(temp_name);
+        let temp_path = parent.join(temp_name);
 
         let mut file = fs::File::create(&temp_path)
             .await
             .map_err(|err| CasError::Io(err.into()))?;
 
-        tokio::io::copy(&mut data, &mut file).await?;
+        tokio::io::copy(data, &mut file).await?;
 
         file.sync_all()
             .await
             .map_err(|err| CasError::Io(err.into()))?;
 
         // 在 POSIX 系统上，rename 是原子的。
-        fs::rename(&temp_path, &target_path)
+        fs::rename(&temp_path, target_path)
             .await
             .map_err(|err| CasError::Io(err.into()))?;
 
         Ok(())
     }
 
+    async fn store_chunked(
+        &self,
+        chunker: &Chunker,
+        digest: &Digest,
+        mut data: Box<dyn AsyncRead + Send + Unpin + 'static>,
+    ) -> Result<(), CasError> {
+        let max_size = chunker.options().max_size;
+
+        let mut buffer = BytesMut::with_capacity(max_size * 2);
+        let mut chunks = Vec::new();
+        let mut hasher = DigestBuilder::new();
+        let mut eof = false;
+
+        loop {
+            while !eof && buffer.len() < max_size {
+                if data.read_buf(&mut buffer).await? == 0 {
+                    eof = true;
+                }
+            }
+
+            if buffer.is_empty() {
+                break;
+            }
+
+            let chunk = buffer.split_to(chunker.cut(&buffer)).freeze();
+            let chunk_digest = digest_of(&chunk);
+            hasher.update(&chunk);
+
+            if !fs::try_exists(self.blob_path(&chunk_digest)).await? {
+                Self::write_atomically(&self.blob_path(&chunk_digest), &mut &chunk[..]).await?;
+            }
+
+            chunks.push(chunk_digest);
+        }
+
+        // the chunks are content addressed by themselves, only the manifest needs the check
+        if !hasher.finish().refers_to_same_content(digest) {
+            return Err(CasError::DigestMismatch(digest.hex_fast_xxhash3_128()));
+        }
+
+        self.write_manifest(digest, &ChunkManifest::new(chunks))
+            .await
+    }
+
+    async fn write_manifest(
+        &self,
+        digest: &Digest,
+        manifest: &ChunkManifest,
+    ) -> Result<(), CasError> {
+        let encoded =
+            <ChunkManifest as Into<crate::proto::cas::ChunkManifest>>::into(manifest.clone())
+                .encode_to_vec();
+
+        Self::write_atomically(&self.manifest_path(digest), &mut &encoded[..]).await
+    }
+
+    async fn fetch_chunked(
+        &self,
+        manifest: ChunkManifest,
+        mut offset: u64,
+    ) -> Result<Box<dyn AsyncRead + Send + Unpin>, CasError> {
+        let mut parts = Vec::<(PathBuf, u64)>::new();
+
+        for chunk in manifest.chunks() {
+            if offset >= chunk.size_bytes {
+                offset -= chunk.size_bytes;
+                continue;
+            }
+
+            parts.push((self.blob_path(chunk), offset));
+            offset = 0;
+        }
+
+        // open chunk lazily, or a huge blob will use up the file descriptors
+        let stream: Pin<Box<dyn Stream<Item = std::io::Result<Bytes>> + Send>> =
+            Box::pin(async_stream::try_stream! {
+                for (path, start) in parts {
+                    let mut file = fs::File::open(&path).await?;
+                    file.seek(std::io::SeekFrom::Start(start)).await?;
+
+                    let mut reader = ReaderStream::new(file);
+
+                    while let Some(data) = reader.next().await {
+                        yield data?;
+                    }
+                }
+            });
+
+        Ok(Box::new(StreamReader::new(stream)))
+    }
+}
+
+#[async_trait]
+impl Cas for LocalCas {
+    async fn store(
+        &self,
+        digest: &Digest,
+        mut data: Box<dyn AsyncRead + Send + Unpin + 'static>,
+    ) -> Result<(), CasError> {
+        if self.contains(digest).await {
+            return Ok(());
+        }
+
+        if let Some(chunker) = &self.chunker
+            && digest.size_bytes >= chunker.options().threshold
+        {
+            return self.store_chunked(chunker, digest, data).await;
+        }
+
+        Self::write_atomically(&self.blob_path(digest), &mut data).await
+    }
+
     async fn check(&self, digest: &Digest) -> Option<u64> {
-        let hex = digest.hex_fast_xxhash3_128();
+        if let Ok(meta) = fs::metadata(self.blob_path(digest)).await {
+            return Some(meta.len());
+        }
 
-        let path = self.root.join(&hex[0..2]).join(&hex[2..4]).join(&hex[4..]);
+        if let Ok(true) = fs::try_exists(self.manifest_path(digest)).await {
+            return Some(digest.size_bytes);
+        }
 
-        fs::metadata(path).await.ok().map(|meta| meta.len())
+        None
     }
 
     async fn contains(&self, digest: &Digest) -> bool {
@@ -83,17 +226,17 @@ impl Cas for LocalCas {
         digest: &Digest,
         offset: u64,
     ) -> Result<Box<dyn AsyncRead + Send + Unpin>, CasError> {
-        let hex = digest.hex_fast_xxhash3_128();
-
-        let path = self.root.join(&hex[0..2]).join(&hex[2..4]).join(&hex[4..]);
-
-        let mut file = tokio::fs::File::open(path).await.map_err(|e| {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                CasError::NotFound(digest.hex_fast_xxhash3_128())
-            } else {
-                CasError::Io(e)
+        let mut file = match fs::File::open(self.blob_path(digest)).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return if let Some(manifest) = self.fetch_manifest(digest).await? {
+                    self.fetch_chunked(manifest, offset).await
+                } else {
+                    Err(CasError::NotFound(digest.hex_fast_xxhash3_128()))
+                };
             }
-        })?;
+            Err(e) => return Err(CasError::Io(e)),
+        };
 
         file.seek(std::io::SeekFrom::Start(offset))
             .await
@@ -103,14 +246,160 @@ impl Cas for LocalCas {
     }
 
     async fn get_local_path(&self, digest: &Digest) -> Option<PathBuf> {
-        let hex = digest.hex_fast_xxhash3_128();
-
-        let path = self.root.join(&hex[0..2]).join(&hex[2..4]).join(&hex[4..]);
+        let path = self.blob_path(digest);
 
-        if self.contains(digest).await {
+        // chunked data has no single local file
+        if let Ok(true) = fs::try_exists(&path).await {
             Some(path)
         } else {
             None
         }
     }
+
+    async fn store_manifest(
+        &self,
+        digest: &Digest,
+        manifest: &ChunkManifest,
+    ) -> Result<(), CasError> {
+        if manifest.total_size() != digest.size_bytes {
+            return Err(CasError::ManifestMismatch(digest.hex_fast_xxhash3_128()));
+        }
+
+        // or anyone could bind the existing chunks to any digest
+        let mut hasher = DigestBuilder::new();
+        let mut buffer = vec![0u8; 64 * 1024];
+
+        for chunk in manifest.chunks() {
+            let mut file = match fs::File::open(self.blob_path(chunk)).await {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(CasError::NotFound(chunk.hex_fast_xxhash3_128()));
+                }
+                Err(e) => return Err(CasError::Io(e)),
+            };
+
+            loop {
+                let read = file.read(&mut buffer).await?;
+
+                if read == 0 {
+                    break;
+                }
+
+                hasher.update(&buffer[..read]);
+            }
+        }
+
+        if !hasher.finish().refers_to_same_content(digest) {
+            return Err(CasError::ManifestMismatch(digest.hex_fast_xxhash3_128()));
+        }
+
+        self.write_manifest(digest, manifest).await
+    }
+
+    async fn fetch_manifest(&self, digest: &Digest) -> Result<Option<ChunkManifest>, CasError> {
+        let encoded = match fs::read(self.manifest_path(digest)).await {
+            Ok(encoded) => encoded,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(CasError::Io(e)),
+        };
+
+        let manifest = crate::proto::cas::ChunkManifest::decode(encoded.as_slice())
+            .map_err(|err| CasError::Internal(err.to_string()))?;
+
+        Ok(Some(manifest.try_into().map_err(
+            |err: crate::digest::DigestError| CasError::Internal(err.to_string()),
+        )?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    /// Chunks are smaller than the threshold, so they are stored whole.
+    const OPTIONS: ChunkingOptions = ChunkingOptions {
+        threshold: 16 * 1024,
+        min_size: 256,
+        avg_size: 1024,
+        max_size: 4096,
+    };
+
+    fn blob(length: usize) -> Vec<u8> {
+        (0..length)
+            .map(|index| (index.wrapping_mul(2654435761) >> 7) as u8)
+            .collect()
+    }
+
+    async fn read_all(mut data: Box<dyn AsyncRead + Send + Unpin>) -> std::io::Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        data.read_to_end(&mut buffer).await?;
+        Ok(buffer)
+    }
+
+    #[tokio::test]
+    async fn chunked_blob_round_trips() -> eyre::Result<()> {
+        let root = TempDir::new()?;
+        let cas = LocalCas::new_with_chunking(root.path().to_path_buf(), OPTIONS)?;
+
+        let data = blob(64 * 1024);
+        let digest = digest_of(&data);
+
+        cas.store(&digest, Box::new(std::io::Cursor::new(data.clone())))
+            .await?;
+
+        assert!(cas.fetch_manifest(&digest).await?.is_some());
+        assert_eq!(cas.get_local_path(&digest).await, None);
+        assert_eq!(read_all(cas.fetch(&digest, 0).await?).await?, data);
+        assert_eq!(
+            read_all(cas.fetch(&digest, 5000).await?).await?,
+            data[5000..]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn blobs_are_whole_without_chunking() -> eyre::Result<()> {
+        let root = TempDir::new()?;
+        let cas = LocalCas::new(root.path().to_path_buf());
+
+        let data = blob(64 * 1024);
+        let digest = digest_of(&data);
+
+        cas.store(&digest, Box::new(std::io::Cursor::new(data.clone())))
+            .await?;
+
+        assert!(cas.fetch_manifest(&digest).await?.is_none());
+        assert_eq!(read_all(cas.fetch(&digest, 0).await?).await?, data);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn manifest_of_other_content_is_rejected() -> eyre::Result<()> {
+        let root = TempDir::new()?;
+        let cas = LocalCas::new_with_chunking(root.path().to_path_buf(), OPTIONS)?;
+
+        let data = blob(8 * 1024);
+        let manifest = ChunkManifest::from_blob(&Chunker::new(OPTIONS)?, &data);
+
+        for chunk in Chunker::new(OPTIONS)?.split(&data) {
+            cas.store(
+                &digest_of(chunk),
+                Box::new(std::io::Cursor::new(chunk.to_vec())),
+            )
+            .await?;
+        }
+
+        // the same size, but another content
+        let mut other = data.clone();
+        other[0] ^= 0xff;
+
+        assert!(matches!(
+            cas.store_manifest(&digest_of(&other), &manifest).await,
+            Err(CasError::ManifestMismatch(_))
+        ));
+
+        cas.store_manifest(&digest_of(&data), &manifest).await?;
+        Ok(())
+    }
 }
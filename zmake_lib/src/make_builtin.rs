@@ -1,4 +1,9 @@
+use crate::engine::{EngineMode, State};
+use crate::event_loop::OpOutput;
 use crate::module_loader::ModuleLoadError;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use thiserror::Error;
 
 #[macro_export]
 macro_rules! make_builtin_id {
@@ -13,26 +18,14 @@ macro_rules! make_builtin_id {
             pub mod $submodule;
         )*
 
-        #[::static_init::dynamic(lazy)]
-        pub static TYPESCRIPT_EXPORT: ::std::string::String = {
-            let mut typescript = ::std::string::String::new();
-
-            for (key,value) in BUILTIN.iter(){
-                typescript.push_str(&format!("\t{}: \"{}\",\n",key,value));
-            }
-
-            $(
-                typescript.push_str(&format!("\t{}: {{\n", ::std::stringify!($submodule)));
-
-                for (key,value) in $submodule::BUILTIN.iter(){
-                    typescript.push_str(&format!("\t\t{}: \"{}\",\n",key,value));
-                }
-
-                typescript.push_str("\t},\n");
-            )*
-
-            typescript
-        };
+        /// The builtin ids of the submodules, with the name of submodule.
+        pub fn submodules() -> ::std::vec::Vec<(&'static str, &'static ::std::collections::BTreeMap<::std::string::String, $crate::id::Id>)> {
+            ::std::vec![
+                $(
+                    (::std::stringify!($submodule), &*$submodule::BUILTIN),
+                )*
+            ]
+        }
 
         $(
             $(
@@ -87,55 +80,447 @@ pub type SysAccessor = for<'s, 'i> fn(
     &mut ::v8::PinScope<'s, 'i>,
 ) -> Result<v8::Local<'s, v8::Value>, ModuleLoadError>;
 
+/// Check that the engine mode may call the syscall, or throw an `Error` to the script.
+pub fn check_capability<'s, 'i>(
+    scope: &mut ::v8::PinScope<'s, 'i>,
+    syscall: &str,
+    modes: &[EngineMode],
+) -> bool {
+    let message = match scope.get_current_context().get_slot::<State>() {
+        Some(state) if modes.contains(&state.mode) => return true,
+        Some(state) => format!(
+            "permission denied: `{}` is not available in {:?} mode, it is available in {:?} mode",
+            syscall, state.mode, modes
+        ),
+        None => format!(
+            "permission denied: `{}` is called out of an engine",
+            syscall
+        ),
+    };
+
+    match ::v8::String::new(scope, &message) {
+        Some(message) => {
+            let exception = ::v8::Exception::error(scope, message);
+            scope.throw_exception(exception);
+        }
+        None => ::tracing::error!("failed to allocate the message of error: {}", message),
+    }
+
+    false
+}
+
+/// The error of typed syscalls, it is thrown to the script.
+#[derive(Error, Debug)]
+pub enum SyscallError {
+    #[error("{0}")]
+    TypeError(String),
+    #[error("{0}")]
+    Error(String),
+}
+
+/// The typescript type of the arguments and results of typed syscalls.
+pub trait TypeScriptType {
+    fn typescript_type() -> String;
+
+    /// Whether the argument can be omitted.
+    const OPTIONAL: bool = false;
+}
+
+macro_rules! impl_typescript_type {
+    ($($type:ty => $typescript:literal),*) => {
+        $(
+            impl TypeScriptType for $type {
+                fn typescript_type() -> String {
+                    $typescript.to_string()
+                }
+            }
+        )*
+    };
+}
+
+impl_typescript_type!(
+    () => "void",
+    bool => "boolean",
+    String => "string",
+    f64 => "number",
+    i32 => "number",
+    u32 => "number",
+    u64 => "number"
+);
+
+impl<T: TypeScriptType> TypeScriptType for Option<T> {
+    fn typescript_type() -> String {
+        format!("{} | null", T::typescript_type())
+    }
+
+    const OPTIONAL: bool = true;
+}
+
+impl<T: TypeScriptType> TypeScriptType for Vec<T> {
+    fn typescript_type() -> String {
+        let element = T::typescript_type();
+
+        if element.contains(' ') {
+            format!("({})[]", element)
+        } else {
+            format!("{}[]", element)
+        }
+    }
+}
+
+/// A value that is computed on the blocking threads of tokio runtime, the script gets a promise.
+pub struct Promise<T> {
+    work: Box<dyn FnOnce() -> Result<T, String> + Send>,
+}
+
+impl<T> Promise<T> {
+    /// The promise is rejected with an `Error` of the message if the work fails.
+    pub fn blocking<F>(work: F) -> Self
+    where
+        F: FnOnce() -> Result<T, String> + Send + 'static,
+    {
+        Self {
+            work: Box::new(work),
+        }
+    }
+}
+
+impl<T: TypeScriptType> TypeScriptType for Promise<T> {
+    fn typescript_type() -> String {
+        format!("Promise<{}>", T::typescript_type())
+    }
+}
+
+/// The value of promise is converted through json.
+pub struct Json<T>(pub T);
+
+impl<T: Serialize + Send + 'static> OpOutput for Json<T> {
+    fn into_v8<'s, 'i>(
+        self: Box<Self>,
+        scope: &mut ::v8::PinScope<'s, 'i>,
+    ) -> Option<::v8::Local<'s, ::v8::Value>> {
+        match serde_json::to_value(self.0) {
+            Ok(value) => OpOutput::into_v8(Box::new(value), scope),
+            Err(err) => {
+                ::tracing::error!("failed to convert the result of promise: {}", err);
+                None
+            }
+        }
+    }
+}
+
+impl<T: TypeScriptType> TypeScriptType for Json<T> {
+    fn typescript_type() -> String {
+        T::typescript_type()
+    }
+}
+
+/// The bytes are resolved as an `Uint8Array` instead of an array of numbers.
+pub struct Uint8Array(pub Vec<u8>);
+
+impl OpOutput for Uint8Array {
+    fn into_v8<'s, 'i>(
+        self: Box<Self>,
+        scope: &mut ::v8::PinScope<'s, 'i>,
+    ) -> Option<::v8::Local<'s, ::v8::Value>> {
+        OpOutput::into_v8(Box::new(self.0), scope)
+    }
+}
+
+impl TypeScriptType for Uint8Array {
+    fn typescript_type() -> String {
+        "Uint8Array".to_string()
+    }
+}
+
+fn throw<'s, 'i>(scope: &mut ::v8::PinScope<'s, 'i>, message: &str, type_error: bool) {
+    let Some(message) = ::v8::String::new(scope, message) else {
+        ::tracing::error!("failed to allocate the message of error: {}", message);
+        return;
+    };
+
+    let exception = if type_error {
+        ::v8::Exception::type_error(scope, message)
+    } else {
+        ::v8::Exception::error(scope, message)
+    };
+
+    scope.throw_exception(exception);
+}
+
+/// A parameter of typed syscall, taken from the arguments of the call.
+pub trait SyscallParameter: Sized {
+    /// Take the parameter from the argument at the index, or throw a `TypeError` to the script.
+    fn take<'s, 'i>(
+        scope: &mut ::v8::PinScope<'s, 'i>,
+        args: &::v8::FunctionCallbackArguments<'s>,
+        index: i32,
+        name: &str,
+        syscall: &str,
+    ) -> Option<Self>;
+
+    /// The declaration of the parameter.
+    fn typescript_parameter(name: &str) -> String;
+}
+
+/// Deserialize the argument, a value of other type is not coerced.
+impl<T: DeserializeOwned + TypeScriptType> SyscallParameter for T {
+    fn take<'s, 'i>(
+        scope: &mut ::v8::PinScope<'s, 'i>,
+        args: &::v8::FunctionCallbackArguments<'s>,
+        index: i32,
+        name: &str,
+        syscall: &str,
+    ) -> Option<Self> {
+        match serde_v8::from_v8::<T>(scope, args.get(index)) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                throw(
+                    scope,
+                    &format!("invalid argument `{}` of `{}`: {}", name, syscall, err),
+                    true,
+                );
+                None
+            }
+        }
+    }
+
+    fn typescript_parameter(name: &str) -> String {
+        if T::OPTIONAL {
+            format!("{}?: {}", name, T::typescript_type())
+        } else {
+            format!("{}: {}", name, T::typescript_type())
+        }
+    }
+}
+
+/// The result of typed syscall, it is converted to the return value.
+pub trait SyscallOutput {
+    fn into_v8<'s, 'i>(
+        self,
+        scope: &mut ::v8::PinScope<'s, 'i>,
+        syscall: &str,
+    ) -> Result<::v8::Local<'s, ::v8::Value>, SyscallError>;
+}
+
+impl<T: Serialize> SyscallOutput for T {
+    fn into_v8<'s, 'i>(
+        self,
+        scope: &mut ::v8::PinScope<'s, 'i>,
+        syscall: &str,
+    ) -> Result<::v8::Local<'s, ::v8::Value>, SyscallError> {
+        serde_v8::to_v8(scope, self).map_err(|err| {
+            SyscallError::Error(format!(
+                "failed to convert the result of `{}`: {}",
+                syscall, err
+            ))
+        })
+    }
+}
+
+impl<T: OpOutput> SyscallOutput for Promise<T> {
+    fn into_v8<'s, 'i>(
+        self,
+        scope: &mut ::v8::PinScope<'s, 'i>,
+        syscall: &str,
+    ) -> Result<::v8::Local<'s, ::v8::Value>, SyscallError> {
+        let Some(state) = scope.get_current_context().get_slot::<State>() else {
+            return Err(SyscallError::Error(format!(
+                "`{}` is called out of an engine",
+                syscall
+            )));
+        };
+
+        match state.event_loop.spawn_blocking_op(scope, self.work) {
+            Some(promise) => Ok(promise.into()),
+            None => Err(SyscallError::Error(format!(
+                "failed to create the promise of `{}`",
+                syscall
+            ))),
+        }
+    }
+}
+
+/// Convert the result of typed syscall, or throw the error to the script.
+pub fn set_result<'s, 'i, T: SyscallOutput>(
+    scope: &mut ::v8::PinScope<'s, 'i>,
+    return_value: &mut ::v8::ReturnValue<'s, ::v8::Value>,
+    syscall: &str,
+    result: Result<T, SyscallError>,
+) {
+    match result.and_then(|value| value.into_v8(scope, syscall)) {
+        Ok(value) => return_value.set(value),
+        Err(SyscallError::TypeError(message)) => throw(scope, &message, true),
+        Err(SyscallError::Error(message)) => throw(scope, &message, false),
+    }
+}
+
+/// The name of syscall or accessor in javascript, the identifier if it is not renamed by `as`.
+#[macro_export]
+macro_rules! builtin_export_name {
+    ($syscall:ident) => {
+        ::std::stringify!($syscall)
+    };
+    ($syscall:ident, $export:literal) => {
+        $export
+    };
+}
+
 #[macro_export]
 macro_rules! make_builtin_js {
-    ( syscalls: { $($syscall:ident),* } accessors: { $($accessor:ident),* } ) => {
+    (
+        typed: { $($typed:ident $(as $typed_export:literal)? : [$($typed_mode:ident),*] ($($param:ident : $param_type:ty),*) -> $return_type:ty),* }
+        accessors: { $($accessor:ident $(as $accessor_export:literal)? => $accessor_declaration:literal),* }
+    ) => {
+        /// The syscalls exported to scripts, they check the capability of engine mode before calling.
+        mod guarded {
+            // the types of parameters and results are named in the parent module
+            #[allow(unused_imports)]
+            use super::*;
+
+            $(
+                #[allow(unused_variables)]
+                pub fn $typed<'s, 'i>(
+                    scope: &mut ::v8::PinScope<'s, 'i>,
+                    args: ::v8::FunctionCallbackArguments<'s>,
+                    mut return_value: ::v8::ReturnValue<'s, ::v8::Value>,
+                ) {
+                    let name = $crate::builtin_export_name!($typed $(, $typed_export)?);
+
+                    if !$crate::make_builtin::check_capability(
+                        scope,
+                        name,
+                        &[$($crate::engine::EngineMode::$typed_mode),*],
+                    ) {
+                        return;
+                    }
+
+                    let index: i32 = 0;
+                    $(
+                        let Some($param) = <$param_type as $crate::make_builtin::SyscallParameter>::take(
+                            scope,
+                            &args,
+                            index,
+                            &::convert_case::ccase!(camel, ::std::stringify!($param)),
+                            name,
+                        ) else {
+                            return;
+                        };
+                        let index = index + 1;
+                    )*
+
+                    let result: ::std::result::Result<$return_type, $crate::make_builtin::SyscallError> =
+                        super::$typed(scope, $($param),*);
+
+                    $crate::make_builtin::set_result(scope, &mut return_value, name, result);
+                }
+            )*
+        }
+
         #[::static_init::dynamic(lazy)]
         pub static BUILTIN_SYSCALLS: ::std::collections::BTreeMap<::std::string::String, $crate::make_builtin::Syscall> = {
             let mut map = ::std::collections::BTreeMap::<::std::string::String, $crate::make_builtin::Syscall>::new();
 
             $(
-                let _ = map.insert(::std::format!("{}", std::stringify!($syscall)), $syscall as $crate::make_builtin::Syscall);
+                let _ = map.insert(::std::format!("{}", $crate::builtin_export_name!($typed $(, $typed_export)?)), guarded::$typed as $crate::make_builtin::Syscall);
             )*
 
             map
         };
 
+        /// The engine modes that may call the syscall.
+        #[::static_init::dynamic(lazy)]
+        pub static BUILTIN_CAPABILITIES: ::std::collections::BTreeMap<::std::string::String, ::std::vec::Vec<$crate::engine::EngineMode>> = {
+            let mut map = ::std::collections::BTreeMap::<::std::string::String, ::std::vec::Vec<$crate::engine::EngineMode>>::new();
+
+            $(
+                let _ = map.insert(::std::format!("{}", $crate::builtin_export_name!($typed $(, $typed_export)?)), ::std::vec![$($crate::engine::EngineMode::$typed_mode),*]);
+            )*
+
+            map
+        };
+
+        /// The typescript declaration of the builtin module, with the syscalls available in the mode.
+        pub fn typescript_declaration(module: &str, mode: $crate::engine::EngineMode) -> ::std::string::String {
+            let mut declaration = ::std::format!("declare module \"{}\" {{\n", module);
+
+            $(
+                if [$($crate::engine::EngineMode::$typed_mode),*].contains(&mode) {
+                    #[allow(unused_mut)]
+                    let mut parameters = ::std::vec::Vec::<::std::string::String>::new();
+                    $(
+                        parameters.push(<$param_type as $crate::make_builtin::SyscallParameter>::typescript_parameter(
+                            &::convert_case::ccase!(camel, ::std::stringify!($param)),
+                        ));
+                    )*
+
+                    declaration.push_str(&::std::format!(
+                        "    export function {}({}): {};\n",
+                        $crate::builtin_export_name!($typed $(, $typed_export)?),
+                        parameters.join(", "),
+                        <$return_type as $crate::make_builtin::TypeScriptType>::typescript_type(),
+                    ));
+                }
+            )*
+            $(
+                declaration.push_str(&::std::format!("    {}\n", $accessor_declaration));
+            )*
+
+            declaration.push_str("}\n");
+            declaration
+        }
+
         #[::static_init::dynamic(lazy)]
         pub static BUILTIN_ACCESSORS: ::std::collections::BTreeMap<::std::string::String, $crate::make_builtin::SysAccessor> = {
+            // most builtins have no accessor
+            #[allow(unused_mut)]
             let mut map = ::std::collections::BTreeMap::<::std::string::String, $crate::make_builtin::SysAccessor>::new();
 
             $(
-                let _ = map.insert(::std::format!("{}", std::stringify!($accessor)), $accessor as $crate::make_builtin::SysAccessor);
+                let _ = map.insert(::std::format!("{}", $crate::builtin_export_name!($accessor $(, $accessor_export)?)), $accessor as $crate::make_builtin::SysAccessor);
             )*
 
             map
         };
 
+        #[allow(unused_variables)]
         pub fn set_syscalls<'s,'i>(scope: &mut ::v8::PinScope<'s, 'i>,module: &::v8::Local<'s,::v8::Module>)->
             std::result::Result<(),$crate::module_loader::ModuleLoadError>{
                 $(
-                let function = ::v8::FunctionTemplate::new(scope, $syscall)
-                    .get_function(scope)
-                    .ok_or_else(|| {
-                    $crate::module_loader::ModuleLoadError::V8ObjectAllocationError("failed to create function")
-                })?;
-
-                if let Some(true) = module.set_synthetic_module_export(
-                    scope,
-                    ::v8::String::new(scope, ::std::stringify!($syscall))
-                    .ok_or_else(|| {
-                        $crate::module_loader::ModuleLoadError::V8ObjectAllocationError("failed to create function name")
-                    })?,
-                    function.into()){}
-                else{
-                    return Err($crate::module_loader::ModuleLoadError::V8SyntheticModuleBuildingError(::std::stringify!($syscall)));
-                }
+                    set_syscall(scope, module, $crate::builtin_export_name!($typed $(, $typed_export)?), guarded::$typed)?;
                 )*
 
             Ok(())
         }
 
+        #[allow(dead_code)]
+        fn set_syscall<'s,'i>(
+            scope: &mut ::v8::PinScope<'s, 'i>,
+            module: &::v8::Local<'s,::v8::Module>,
+            name: &'static str,
+            callback: impl ::v8::MapFnTo<::v8::FunctionCallback>,
+        ) -> std::result::Result<(),$crate::module_loader::ModuleLoadError>{
+            let function = ::v8::FunctionTemplate::new(scope, callback)
+                .get_function(scope)
+                .ok_or_else(|| {
+                $crate::module_loader::ModuleLoadError::V8ObjectAllocationError("failed to create function")
+            })?;
+
+            if let Some(true) = module.set_synthetic_module_export(
+                scope,
+                ::v8::String::new(scope, name)
+                .ok_or_else(|| {
+                    $crate::module_loader::ModuleLoadError::V8ObjectAllocationError("failed to create function name")
+                })?,
+                function.into()){}
+            else{
+                return Err($crate::module_loader::ModuleLoadError::V8SyntheticModuleBuildingError(name));
+            }
+
+            Ok(())
+        }
+
+        #[allow(unused_variables)]
         pub fn set_accessors<'s,'i>(scope: &mut ::v8::PinScope<'s, 'i>,module: &::v8::Local<'s,::v8::Module>)->
             std::result::Result<(),$crate::module_loader::ModuleLoadError>{
 
@@ -144,13 +529,13 @@ macro_rules! make_builtin_js {
 
                     if let Some(true) = module.set_synthetic_module_export(
                         scope,
-                        ::v8::String::new(scope, ::std::stringify!($accessor))
+                        ::v8::String::new(scope, $crate::builtin_export_name!($accessor $(, $accessor_export)?))
                         .ok_or_else(|| {
                             $crate::module_loader::ModuleLoadError::V8ObjectAllocationError("failed to create accessor name")
                         })?,
                         accessor){}
                     else{
-                        return Err($crate::module_loader::ModuleLoadError::V8SyntheticModuleBuildingError(::std::stringify!($syscall)));
+                        return Err($crate::module_loader::ModuleLoadError::V8SyntheticModuleBuildingError($crate::builtin_export_name!($accessor $(, $accessor_export)?)));
                     }
                 )*
 
@@ -177,6 +562,37 @@ macro_rules! make_builtin_js {
             Ok(exports)
         }
 
+        /// Create the synthetic module, its exports are set when it is evaluated.
+        pub fn create_module<'s,'i>(scope: &::v8::PinScope<'s, 'i>, specifier: &$crate::module_specifier::ModuleSpecifier) ->
+                ::std::result::Result<::v8::Local<'s, ::v8::Module>,$crate::module_loader::ModuleLoadError>{
+            let name = ::v8::String::new(scope, specifier.to_string().as_ref())
+                .ok_or_else(|| {
+                    $crate::module_loader::ModuleLoadError::V8ObjectAllocationError("failed to create module name")
+                })?;
+
+            Ok(::v8::Module::create_synthetic_module(
+                scope,
+                name,
+                get_exports(scope)?.as_slice(),
+                evalution_callback,
+            ))
+        }
+
+        /// The native functions referred by the builtin modules, the order must be stable
+        /// because a startup snapshot refers to them by index.
+        pub fn external_references() -> ::std::vec::Vec<::v8::ExternalReference> {
+            use ::v8::MapFnTo;
+
+            let evalution_steps: ::v8::SyntheticModuleEvaluationSteps = evalution_callback.map_fn_to();
+
+            ::std::vec![
+                $(
+                    ::v8::ExternalReference { function: guarded::$typed.map_fn_to() },
+                )*
+                ::v8::ExternalReference { pointer: evalution_steps as *mut ::std::ffi::c_void },
+            ]
+        }
+
         pub fn evalution_callback<'a>(context:v8::Local<'a, ::v8::Context>, module:v8::Local<'a, ::v8::Module>)
             -> ::std::option::Option<::v8::Local<'a, ::v8::Value>>{
                 // unsafe这一块
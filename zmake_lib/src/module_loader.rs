@@ -1,17 +1,25 @@
+use crate::audit::AccessKind;
 use crate::builtin;
 use crate::engine::State;
+use crate::import_map::{ImportMap, ImportMapError};
 use crate::module_loader::ModuleLoadError::NotSupported;
 use crate::module_specifier::ModuleSpecifier;
 use crate::path::NeutralPath;
 use crate::sandbox::{Sandbox, SandboxError};
+use crate::script_cache::ScriptCache;
+use crate::script_error::ScriptError;
+use crate::source_map::SourceMap;
 use crate::transformer::transform_typescript;
-use ahash::AHashMap;
+use crate::version_requirement::{VersionRequirementError, check_declared_requirements};
+use ahash::{AHashMap, AHashSet};
 use eyre::Result;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::{cell::RefCell, rc::Rc};
 use thiserror::Error;
-use tracing::error;
 use tracing::trace_span;
+use tracing::{error, warn};
 use v8::callback_scope;
 use v8::script_compiler::Source;
 use v8::{Data, FixedArray, Local, PinScope, Promise, PromiseResolver, ScriptOrigin, Value};
@@ -21,16 +29,52 @@ pub static NEEDED_TRANSFORMED_FILE_EXTENSION: &[&'static str] = &["ts", "mts"];
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Options {
     pub enable_imports: bool,
+    /// Where to cache the transpiled and compiled scripts, `None` to disable the cache.
+    pub cache_directory: Option<PathBuf>,
 }
 
 #[derive(Debug)]
 pub struct ModuleLoader {
     options: Options,
-    sandbox: Arc<Sandbox>,
+    /// The sandbox of the running evaluation, its audit collects the accesses of the evaluation.
+    sandbox: RefCell<Arc<Sandbox>>,
+    cache: Option<ScriptCache>,
     module_map: RefCell<AHashMap<v8::Global<v8::Module>, ModuleSpecifier>>,
-    module_cache: RefCell<AHashMap<ModuleSpecifier, v8::Global<v8::Module>>>,
+    /// The same file can be imported as different module types.
+    module_cache: RefCell<AHashMap<(ModuleSpecifier, ModuleType), v8::Global<v8::Module>>>,
+    /// The default export of json and text modules, set when the synthetic module is evaluated.
+    synthetic_defaults: RefCell<AHashMap<v8::Global<v8::Module>, v8::Global<v8::Value>>>,
     dependencies: RefCell<AHashMap<ModuleSpecifier, Vec<ModuleSpecifier>>>,
-    import_map: RefCell<AHashMap<String, ModuleSpecifier>>,
+    import_map: RefCell<ImportMap>,
+    /// Source maps of transpiled modules, used to report positions in the original source.
+    source_maps: RefCell<AHashMap<ModuleSpecifier, Rc<SourceMap>>>,
+    /// Keyed by the name without `MEMORY_MODULE_PREFIX`.
+    memory_modules: RefCell<AHashMap<String, MemoryModule>>,
+    /// Sources of dynamic imports read by the event loop, taken when the module is compiled.
+    preloaded_sources: RefCell<AHashMap<ModuleSpecifier, String>>,
+}
+
+/// Selected by the `type` import attribute, e.g. `import config from "./config.json" with { type: "json" }`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModuleType {
+    JavaScript,
+    /// The default export is the parsed json.
+    Json,
+    /// The default export is the content as a string.
+    Text,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModuleLanguage {
+    JavaScript,
+    TypeScript,
+}
+
+/// A module that is not backed by a file, e.g. generated wrappers.
+#[derive(Debug, Clone)]
+struct MemoryModule {
+    source: String,
+    language: ModuleLanguage,
 }
 
 #[derive(Error, Debug)]
@@ -40,9 +84,7 @@ pub enum ModuleLoadError {
         referer: ModuleSpecifier,
         specifier: ModuleSpecifier,
     },
-    #[error(
-        "Can not load memory module or load esm file from memory/builtin/import-map esm:`{specifier}` referer `{referer}`"
-    )]
+    #[error("Can not load esm file from builtin/import-map esm:`{specifier}` referer `{referer}`")]
     NotSupported {
         referer: ModuleSpecifier,
         specifier: ModuleSpecifier,
@@ -53,6 +95,8 @@ pub enum ModuleLoadError {
     IoError(#[from] std::io::Error),
     #[error("Sandbox error: {0}")]
     SandboxError(#[from] SandboxError),
+    #[error("Import map error: {0}")]
+    ImportMapError(#[from] ImportMapError),
     #[error(
         "Failed to allocate V8 object. It may because v8 run out of memory or the object is too large:{0}"
     )]
@@ -61,27 +105,63 @@ pub enum ModuleLoadError {
     V8CompileError(ModuleSpecifier),
     #[error("Failed to instantiate and evaluate module: {0:?}")]
     V8InstaniateAndEvaluateError(ModuleSpecifier),
+    #[error("Uncaught exception in module `{0}`: {1}")]
+    ScriptError(ModuleSpecifier, Box<ScriptError>),
     #[error(
         "Failed to set synthetic module export `{0}`(Note: it may because of duplicated export or unknown export)"
     )]
     V8SyntheticModuleBuildingError(&'static str),
+    #[error("Unsupported import attribute `{0}`")]
+    UnsupportedImportAttribute(String),
+    #[error("Unsupported module type `{0}`, expect `json` or `text`")]
+    UnsupportedModuleType(String),
+    #[error("Invalid json module `{0}`: {1}")]
+    InvalidJsonModule(ModuleSpecifier, serde_json::Error),
+    #[error("The memory module `{0}` has been registered")]
+    DuplicateMemoryModule(String),
+    #[error("Failed to create or use the startup snapshot: {0}")]
+    SnapshotError(&'static str),
+    #[error("The execution of module `{0}` is terminated")]
+    Terminated(ModuleSpecifier),
+    #[error("The top-level await of module `{0}` never settles")]
+    UnsettledTopLevelAwait(ModuleSpecifier),
+    #[error("The module `{0}` is evaluated, but its dynamic imports never settle")]
+    DanglingAsyncWork(ModuleSpecifier),
     #[error("Failed to find resolved module specifier: {0:?}")]
     UnknownModuleSpecifier(ModuleSpecifier),
     #[error("Failed to find builtin module: {0}")]
     UnknownBuiltinModuleSpecifier(String),
     #[error("Failed to transform typescript module `{0:?}`:{1}")]
     FailedToTransformTypescript(ModuleSpecifier, String),
+    #[error("Unsatisfied version requirement: {0}")]
+    VersionRequirement(#[from] VersionRequirementError),
+}
+
+/// Read the source of file through the sandbox, it can be called out of the engine thread.
+pub fn read_file_source(sandbox: &Sandbox, path: &Path) -> Result<String, ModuleLoadError> {
+    let mut source_code = String::new();
+
+    sandbox
+        .open_file(&sandbox.get_relative_path(&path)?)?
+        .read_to_string(&mut source_code)?;
+
+    Ok(source_code)
 }
 
 impl ModuleLoader {
     pub fn new(sandbox: Arc<Sandbox>, options: Options) -> Self {
         Self {
+            cache: options.cache_directory.clone().map(ScriptCache::new),
             options,
-            sandbox,
+            sandbox: RefCell::new(sandbox),
             module_map: RefCell::from(AHashMap::new()),
             module_cache: RefCell::from(AHashMap::new()),
+            synthetic_defaults: RefCell::from(AHashMap::new()),
             dependencies: RefCell::from(AHashMap::new()),
-            import_map: RefCell::from(AHashMap::new()),
+            import_map: RefCell::from(ImportMap::default()),
+            source_maps: RefCell::from(AHashMap::new()),
+            memory_modules: RefCell::from(AHashMap::new()),
+            preloaded_sources: RefCell::from(AHashMap::new()),
         }
     }
 
@@ -93,25 +173,62 @@ impl ModuleLoader {
     ) -> Result<ModuleSpecifier, ModuleLoadError> {
         match specifier.clone() {
             ModuleSpecifier::Builtin(builtin) => Ok(ModuleSpecifier::Builtin(builtin)),
-            ModuleSpecifier::Memory(_memory) => Err(NotSupported {
-                referer: referrer.clone(),
-                specifier: specifier.clone(),
-            }),
-            ModuleSpecifier::ImportMap(import_map) => {
-                if let Some(mapped) = self.import_map.borrow().get(&import_map) {
-                    Ok(mapped.clone())
-                } else {
-                    Err(ModuleLoadError::NotFound {
+            ModuleSpecifier::Memory(memory) => {
+                if !self.memory_modules.borrow().contains_key(&memory) {
+                    return Err(ModuleLoadError::NotFound {
                         referer: referrer.clone(),
                         specifier: specifier.clone(),
-                    })
+                    });
                 }
+
+                Ok(ModuleSpecifier::Memory(memory))
+            }
+            ModuleSpecifier::ImportMap(import_map) => {
+                let referrer_path = match referrer {
+                    ModuleSpecifier::File(referrer_path) => {
+                        Some(self.get_sandbox().get_relative_path(referrer_path)?)
+                    }
+                    _ => None,
+                };
+
+                let Some(mapped) = self
+                    .import_map
+                    .borrow()
+                    .resolve(referrer_path.as_ref(), &import_map)
+                else {
+                    return Err(ModuleLoadError::NotFound {
+                        referer: referrer.clone(),
+                        specifier: specifier.clone(),
+                    });
+                };
+
+                let target = ModuleSpecifier::File(self.get_sandbox().join_path_for(&mapped?)?);
+
+                self.dependencies
+                    .borrow_mut()
+                    .entry(referrer.clone())
+                    .or_default()
+                    .push(target.clone());
+
+                Ok(target)
             }
             ModuleSpecifier::File(target) => {
-                if let ModuleSpecifier::File(referrer_path) = referrer {
+                let sandbox = self.get_sandbox();
+
+                // relative import is resolved from the directory of the referrer,
+                // or the sandbox root for memory modules
+                let referrer_directory = match referrer {
+                    ModuleSpecifier::File(referrer_path) => {
+                        Some(referrer_path.parent().unwrap_or(referrer_path))
+                    }
+                    ModuleSpecifier::Memory(_) => Some(sandbox.get_root()),
+                    _ => None,
+                };
+
+                if let Some(referrer_directory) = referrer_directory {
                     let target = NeutralPath::new(target.to_string_lossy())?;
 
-                    let target = self.sandbox.get_path_safe(referrer_path, &target)?;
+                    let target = sandbox.get_path_safe(&referrer_directory, &target)?;
 
                     let target = ModuleSpecifier::File(target);
 
@@ -132,17 +249,378 @@ impl ModuleLoader {
         }
     }
 
+    /// Register a module that can be imported by `MEMORY_MODULE_PREFIX` and the name.
+    ///
+    /// A registered module can not be replaced, because it may have been compiled.
+    pub fn add_memory_module(
+        self: &Self,
+        name: &str,
+        source: String,
+        language: ModuleLanguage,
+    ) -> Result<ModuleSpecifier, ModuleLoadError> {
+        let mut memory_modules = self.memory_modules.borrow_mut();
+
+        if memory_modules.contains_key(name) {
+            return Err(ModuleLoadError::DuplicateMemoryModule(name.to_string()));
+        }
+
+        memory_modules.insert(name.to_string(), MemoryModule { source, language });
+
+        Ok(ModuleSpecifier::Memory(name.to_string()))
+    }
+
+    pub fn set_import_map(self: &Self, import_map: ImportMap) {
+        *self.import_map.borrow_mut() = import_map;
+    }
+
+    /// Record the file of the import map, every import of the evaluation may depend on it.
+    pub fn record_import_map(self: &Self) {
+        if let Some(file) = self.import_map.borrow().get_file() {
+            self.get_sandbox()
+                .get_audit()
+                .record(file.clone(), AccessKind::Read);
+        }
+    }
+
+    /// Register a module that was compiled elsewhere, e.g. deserialized from the startup snapshot.
+    pub fn register_module<'s, 'i>(
+        self: &Self,
+        scope: &PinScope<'s, 'i>,
+        specifier: &ModuleSpecifier,
+        module: Local<'s, v8::Module>,
+    ) {
+        let global_mod = v8::Global::new(scope, module);
+
+        self.module_cache.borrow_mut().insert(
+            (specifier.clone(), ModuleType::JavaScript),
+            global_mod.clone(),
+        );
+        self.module_map
+            .borrow_mut()
+            .insert(global_mod, specifier.clone());
+    }
+
+    /// Compile the module, consume and produce the code cache if the script cache is enabled.
+    fn compile_module<'s, 'i>(
+        self: &Self,
+        scope: &PinScope<'s, 'i>,
+        specifier: &ModuleSpecifier,
+        source_code: &str,
+        origin: &ScriptOrigin<'s>,
+    ) -> Result<Local<'s, v8::Module>, ModuleLoadError> {
+        let v8_source = v8::String::new(scope, source_code).ok_or(
+            ModuleLoadError::V8ObjectAllocationError("v8::String::new(scope, source_code)"),
+        )?;
+
+        let Some(cache) = &self.cache else {
+            return v8::script_compiler::compile_module(
+                scope,
+                &mut Source::new(v8_source, Some(origin)),
+            )
+            .ok_or_else(|| ModuleLoadError::V8CompileError(specifier.clone()));
+        };
+
+        let cached_data = cache.get_code_cache(source_code);
+
+        let (module, rejected) = match &cached_data {
+            Some(cached_data) => {
+                let mut source = Source::new_with_cached_data(
+                    v8_source,
+                    Some(origin),
+                    v8::script_compiler::CachedData::new(cached_data),
+                );
+
+                let module = v8::script_compiler::compile_module2(
+                    scope,
+                    &mut source,
+                    v8::script_compiler::CompileOptions::ConsumeCodeCache,
+                    v8::script_compiler::NoCacheReason::NoReason,
+                )
+                .ok_or_else(|| ModuleLoadError::V8CompileError(specifier.clone()))?;
+
+                // V8 rejects the cache if the flags or the source does not match
+                let rejected = source
+                    .get_cached_data()
+                    .map(|data| data.rejected())
+                    .unwrap_or(true);
+
+                (module, rejected)
+            }
+            None => (
+                v8::script_compiler::compile_module(
+                    scope,
+                    &mut Source::new(v8_source, Some(origin)),
+                )
+                .ok_or_else(|| ModuleLoadError::V8CompileError(specifier.clone()))?,
+                true,
+            ),
+        };
+
+        if rejected {
+            match module.get_unbound_module_script(scope).create_code_cache() {
+                Some(code_cache) => cache.put_code_cache(source_code, &code_cache),
+                None => warn!("failed to create code cache of `{}`", specifier),
+            }
+        }
+
+        Ok(module)
+    }
+
+    /// Transpile typescript to javascript, the result is cached if the script cache is enabled.
+    fn transpile(
+        self: &Self,
+        specifier: &ModuleSpecifier,
+        source_code: String,
+        source_name: &str,
+    ) -> Result<String, ModuleLoadError> {
+        let cached = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.get_transpiled(&source_code, source_name));
+
+        let transpiled = match cached {
+            Some(transpiled) => transpiled,
+            None => {
+                let transpiled =
+                    transform_typescript(source_code.as_str(), source_name).map_err(|err| {
+                        ModuleLoadError::FailedToTransformTypescript(specifier.clone(), err)
+                    })?;
+
+                if let Some(cache) = &self.cache {
+                    cache.put_transpiled(&source_code, source_name, &transpiled);
+                }
+
+                transpiled
+            }
+        };
+
+        if let Some(source_map) = transpiled.source_map {
+            self.add_source_map(specifier, &source_map);
+        }
+
+        Ok(transpiled.code)
+    }
+
+    pub fn get_sandbox(self: &Self) -> Arc<Sandbox> {
+        self.sandbox.borrow().clone()
+    }
+
+    /// Use another sandbox for the following loads, returns the previous one.
+    pub fn replace_sandbox(self: &Self, sandbox: Arc<Sandbox>) -> Arc<Sandbox> {
+        self.sandbox.replace(sandbox)
+    }
+
+    /// Drop the instances of file and memory modules, so an evaluation does not see the module
+    /// state of earlier ones. Builtin modules and the script cache are kept.
+    pub fn clear_instances(self: &Self) {
+        let is_builtin =
+            |specifier: &ModuleSpecifier| matches!(specifier, ModuleSpecifier::Builtin(_));
+
+        self.module_cache
+            .borrow_mut()
+            .retain(|(specifier, _), _| is_builtin(specifier));
+        self.module_map
+            .borrow_mut()
+            .retain(|_, specifier| is_builtin(specifier));
+
+        let module_map = self.module_map.borrow();
+        self.synthetic_defaults
+            .borrow_mut()
+            .retain(|module, _| module_map.contains_key(module));
+
+        self.dependencies
+            .borrow_mut()
+            .retain(|specifier, _| is_builtin(specifier));
+        self.source_maps
+            .borrow_mut()
+            .retain(|specifier, _| is_builtin(specifier));
+        self.preloaded_sources.borrow_mut().clear();
+    }
+
+    /// Whether the module has been compiled, so its source is not needed.
+    pub fn is_loaded(self: &Self, specifier: &ModuleSpecifier, module_type: ModuleType) -> bool {
+        self.module_cache
+            .borrow()
+            .contains_key(&(specifier.clone(), module_type))
+    }
+
+    /// Provide the source of the module, it is used instead of reading the file again.
+    pub fn preload_source(self: &Self, specifier: &ModuleSpecifier, source: String) {
+        self.preloaded_sources
+            .borrow_mut()
+            .insert(specifier.clone(), source);
+    }
+
+    /// Read the source of file or memory module, file is read through the sandbox.
+    fn read_source(self: &Self, specifier: &ModuleSpecifier) -> Result<String, ModuleLoadError> {
+        if let Some(source) = self.preloaded_sources.borrow_mut().remove(specifier) {
+            return Ok(source);
+        }
+
+        match specifier {
+            ModuleSpecifier::File(path_buf) => read_file_source(&self.get_sandbox(), path_buf),
+            ModuleSpecifier::Memory(name) => match self.memory_modules.borrow().get(name) {
+                Some(memory_module) => Ok(memory_module.source.clone()),
+                None => Err(ModuleLoadError::UnknownModuleSpecifier(specifier.clone())),
+            },
+            _ => Err(ModuleLoadError::UnknownModuleSpecifier(specifier.clone())),
+        }
+    }
+
+    /// Get the module type from the import attributes.
+    ///
+    /// The attributes are `[key, value, (source offset)]...`, the `stride` is 3 for static imports
+    /// and 2 for dynamic imports.
+    fn get_module_type<'s, 'i>(
+        scope: &PinScope<'s, 'i>,
+        import_attributes: Local<'s, FixedArray>,
+        stride: usize,
+    ) -> Result<ModuleType, ModuleLoadError> {
+        let mut module_type = ModuleType::JavaScript;
+
+        for index in (0..import_attributes.length()).step_by(stride) {
+            let (Some(key), Some(value)) = (
+                import_attributes.get(scope, index),
+                import_attributes.get(scope, index + 1),
+            ) else {
+                break;
+            };
+
+            let (Ok(key), Ok(value)) = (
+                Local::<v8::String>::try_from(key),
+                Local::<v8::String>::try_from(value),
+            ) else {
+                continue;
+            };
+
+            let key = key.to_rust_string_lossy(scope);
+            let value = value.to_rust_string_lossy(scope);
+
+            if key != "type" {
+                return Err(ModuleLoadError::UnsupportedImportAttribute(key));
+            }
+
+            module_type = match value.as_str() {
+                "json" => ModuleType::Json,
+                "text" => ModuleType::Text,
+                _ => return Err(ModuleLoadError::UnsupportedModuleType(value)),
+            };
+        }
+
+        Ok(module_type)
+    }
+
+    /// Create the synthetic module of json or text, it only has the default export.
+    fn create_data_module<'s, 'i>(
+        self: &Self,
+        scope: &PinScope<'s, 'i>,
+        specifier: &ModuleSpecifier,
+        module_type: ModuleType,
+    ) -> Result<Local<'s, v8::Module>, ModuleLoadError> {
+        let source = self.read_source(specifier)?;
+
+        let v8_source = v8::String::new(scope, &source).ok_or(
+            ModuleLoadError::V8ObjectAllocationError("v8::String::new(scope, &source)"),
+        )?;
+
+        let value: Local<Value> = match module_type {
+            ModuleType::Json => {
+                // validate first, so the error has the position and the exception is not pending
+                if let Err(err) = serde_json::from_str::<serde::de::IgnoredAny>(&source) {
+                    return Err(ModuleLoadError::InvalidJsonModule(specifier.clone(), err));
+                }
+
+                v8::json::parse(scope, v8_source).ok_or(
+                    ModuleLoadError::V8ObjectAllocationError("v8::json::parse(scope, source)"),
+                )?
+            }
+            _ => v8_source.into(),
+        };
+
+        let default_name = v8::String::new(scope, "default").ok_or(
+            ModuleLoadError::V8ObjectAllocationError("v8::String::new(scope, \"default\")"),
+        )?;
+
+        let module = v8::Module::create_synthetic_module(
+            scope,
+            v8::String::new(scope, specifier.to_string().as_ref()).ok_or(
+                ModuleLoadError::V8ObjectAllocationError(
+                    "v8::String::new(scope, specifier.to_string())",
+                ),
+            )?,
+            &[default_name],
+            Self::data_module_evaluation_callback,
+        );
+
+        self.synthetic_defaults.borrow_mut().insert(
+            v8::Global::new(scope, module),
+            v8::Global::new(scope, value),
+        );
+
+        Ok(module)
+    }
+
+    fn data_module_evaluation_callback<'s>(
+        context: Local<'s, v8::Context>,
+        module: Local<'s, v8::Module>,
+    ) -> Option<Local<'s, Value>> {
+        callback_scope!(unsafe scope, context);
+
+        let state = match scope.get_current_context().get_slot::<State>() {
+            Some(state) => state,
+            None => {
+                error!("failed to get state from slot");
+                return None;
+            }
+        };
+
+        let value = match state
+            .module_loader
+            .synthetic_defaults
+            .borrow()
+            .get(&v8::Global::new(scope, module))
+        {
+            Some(value) => Local::new(scope, value),
+            None => {
+                error!("failed to get the default export of synthetic module");
+                return None;
+            }
+        };
+
+        let default_name = v8::String::new(scope, "default")?;
+
+        if module.set_synthetic_module_export(scope, default_name, value) != Some(true) {
+            error!("failed to set the default export of synthetic module");
+            return None;
+        }
+
+        Some(v8::undefined(scope).into())
+    }
+
     /// Get and compile module
     ///
     /// We process file modules and builtin modules here.
     ///
-    /// Import-map and memory module has been resolved in `resolve` method.
+    /// Import-map module has been resolved in `resolve` method.
     pub fn resolve_module<'s, 'i>(
         self: &Self,
         scope: &PinScope<'s, 'i>,
         specifier: &ModuleSpecifier,
     ) -> Result<Local<'s, v8::Module>, ModuleLoadError> {
-        let module = if let Some(global_mod) = self.module_cache.borrow().get(specifier) {
+        self.resolve_module_with_type(scope, specifier, ModuleType::JavaScript)
+    }
+
+    pub fn resolve_module_with_type<'s, 'i>(
+        self: &Self,
+        scope: &PinScope<'s, 'i>,
+        specifier: &ModuleSpecifier,
+        module_type: ModuleType,
+    ) -> Result<Local<'s, v8::Module>, ModuleLoadError> {
+        let cache_key = (specifier.clone(), module_type);
+
+        let module = if let Some(global_mod) = self.module_cache.borrow().get(&cache_key) {
+            self.record_cached(specifier)?;
+
             Local::new(scope, global_mod)
         } else {
             let origin = ScriptOrigin::new(
@@ -164,33 +642,14 @@ impl ModuleLoader {
             );
 
             let module = match specifier {
+                _ if module_type != ModuleType::JavaScript => {
+                    self.create_data_module(scope, specifier, module_type)?
+                }
                 ModuleSpecifier::Builtin(builtin_name) => {
                     if specifier.eq(&crate::builtin::js::RT) {
-                        let v8_source = v8::String::new(scope, crate::builtin::js::RT_CODE).ok_or(
-                            ModuleLoadError::V8ObjectAllocationError(
-                                "v8::String::new(scope, &crate::builtin::js::RT_CODE)",
-                            ),
-                        )?;
-
-                        let module = v8::script_compiler::compile_module(
-                            scope,
-                            &mut Source::new(v8_source, Some(&origin)),
-                        )
-                        .ok_or_else(|| ModuleLoadError::V8CompileError(specifier.clone()))?;
-
-                        module
-                    } else if specifier.eq(&crate::builtin::js::SYSCALL) {
-                        // note: to modify syscall,see crate::builtin::js
-                        v8::Module::create_synthetic_module(
-                            scope,
-                            v8::String::new(scope, specifier.to_string().as_ref()).ok_or(
-                                ModuleLoadError::V8ObjectAllocationError(
-                                    "v8::String::new(scope, &crate::builtin::js::SYSCALL)",
-                                ),
-                            )?,
-                            builtin::js::get_exports(scope)?.as_slice(),
-                            builtin::js::evalution_callback,
-                        )
+                        self.compile_module(scope, specifier, crate::builtin::js::RT_CODE, &origin)?
+                    } else if let Some(synthetic) = builtin::find_synthetic_module(specifier) {
+                        (synthetic.create)(scope, specifier)?
                     } else {
                         return Err(ModuleLoadError::UnknownBuiltinModuleSpecifier(
                             builtin_name.clone(),
@@ -198,7 +657,7 @@ impl ModuleLoader {
                     }
                 }
                 ModuleSpecifier::File(path_buf) => {
-                    let mut source_code = std::fs::read_to_string(path_buf)?;
+                    let mut source_code = self.read_source(specifier)?;
 
                     if {
                         let mut need_transform = false;
@@ -212,28 +671,30 @@ impl ModuleLoader {
                         }
                         need_transform
                     } {
-                        source_code = transform_typescript(
-                            source_code.as_str(),
-                            path_buf.to_string_lossy().to_string().as_str(),
-                        )
-                        .map_err(|err| {
-                            ModuleLoadError::FailedToTransformTypescript(specifier.clone(), err)
-                        })?;
+                        source_code =
+                            self.transpile(specifier, source_code, &path_buf.to_string_lossy())?;
                     }
 
-                    let v8_source = v8::String::new(scope, &source_code).ok_or(
-                        ModuleLoadError::V8ObjectAllocationError(
-                            "v8::String::new(scope, &source_code)",
-                        ),
-                    )?;
+                    check_declared_requirements(&path_buf.to_string_lossy(), &source_code)?;
+
+                    self.compile_module(scope, specifier, &source_code, &origin)?
+                }
+                ModuleSpecifier::Memory(name) => {
+                    let Some(memory_module) = self.memory_modules.borrow().get(name).cloned()
+                    else {
+                        return Err(ModuleLoadError::UnknownModuleSpecifier(specifier.clone()));
+                    };
+
+                    let source_code = match memory_module.language {
+                        ModuleLanguage::JavaScript => memory_module.source,
+                        ModuleLanguage::TypeScript => {
+                            self.transpile(specifier, memory_module.source, &specifier.to_string())?
+                        }
+                    };
 
-                    let module = v8::script_compiler::compile_module(
-                        scope,
-                        &mut Source::new(v8_source, Some(&origin)),
-                    )
-                    .ok_or_else(|| ModuleLoadError::V8CompileError(specifier.clone()))?;
+                    check_declared_requirements(&specifier.to_string(), &source_code)?;
 
-                    module
+                    self.compile_module(scope, specifier, &source_code, &origin)?
                 }
                 _ => return Err(ModuleLoadError::UnknownModuleSpecifier(specifier.clone())),
             };
@@ -242,7 +703,7 @@ impl ModuleLoader {
 
             self.module_cache
                 .borrow_mut()
-                .insert(specifier.clone(), global_mod.clone());
+                .insert(cache_key, global_mod.clone());
             self.module_map
                 .borrow_mut()
                 .insert(global_mod.clone(), specifier.clone());
@@ -255,28 +716,179 @@ impl ModuleLoader {
         Ok(module)
     }
 
+    /// Record the files of a compiled module and its dependencies to the audit of the running
+    /// evaluation, they are not read again when the module is reused.
+    fn record_cached(self: &Self, specifier: &ModuleSpecifier) -> Result<(), ModuleLoadError> {
+        let sandbox = self.get_sandbox();
+        let dependencies = self.dependencies.borrow();
+
+        let mut visited = AHashSet::new();
+        let mut pending = vec![specifier.clone()];
+
+        while let Some(specifier) = pending.pop() {
+            if !visited.insert(specifier.clone()) {
+                continue;
+            }
+
+            if let ModuleSpecifier::File(path) = &specifier {
+                sandbox
+                    .get_audit()
+                    .record(sandbox.get_relative_path(path)?, AccessKind::Read);
+            }
+
+            if let Some(dependencies) = dependencies.get(&specifier) {
+                pending.extend(dependencies.iter().cloned());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Instantiate and evaluate the module, and return the result of evaluation.
+    ///
+    /// The result is a promise that settles when the top-level await of the module finishes,
+    /// it is driven by `crate::event_loop::EventLoop`. Exceptions thrown synchronously are
+    /// returned as `ScriptError`.
     pub fn instantiate_and_evaluate_module<'s, 'i>(
         self: &Self,
-        scope: &PinScope<'s, 'i>,
-        module: &Local<v8::Module>,
-    ) -> Option<Local<'s, v8::Value>> {
+        scope: &mut PinScope<'s, 'i>,
+        specifier: &ModuleSpecifier,
+        module: &Local<'s, v8::Module>,
+    ) -> Result<Local<'s, v8::Value>, ModuleLoadError> {
+        let try_catch = std::pin::pin!(v8::TryCatch::new(scope));
+        let try_catch = &mut try_catch.init();
+
         if module.get_status() == v8::ModuleStatus::Uninstantiated {
-            if !module.instantiate_module(scope, Self::resolve_module_hook)? {
-                return None;
+            if module.instantiate_module(try_catch, Self::resolve_module_hook) != Some(true) {
+                let exception = try_catch.exception();
+                return Err(self.exception_to_error(try_catch, specifier, exception));
+            }
+        }
+
+        // evaluating an evaluated module returns the promise of its first evaluation
+        let result = match module.get_status() {
+            v8::ModuleStatus::Instantiated | v8::ModuleStatus::Evaluated => {
+                match module.evaluate(try_catch) {
+                    Some(result) => result,
+                    None => {
+                        let exception = try_catch.exception();
+                        return Err(self.exception_to_error(try_catch, specifier, exception));
+                    }
+                }
             }
+            _ => v8::undefined(try_catch).into(),
+        };
+
+        if module.get_status() == v8::ModuleStatus::Errored {
+            let exception = module.get_exception();
+            return Err(self.exception_to_error(try_catch, specifier, Some(exception)));
         }
 
-        if module.get_status() == v8::ModuleStatus::Instantiated {
-            let result = module.evaluate(scope)?;
+        Ok(result)
+    }
 
-            return Some(result);
+    pub(crate) fn exception_to_error<'s, 'i>(
+        self: &Self,
+        scope: &PinScope<'s, 'i>,
+        specifier: &ModuleSpecifier,
+        exception: Option<Local<'s, Value>>,
+    ) -> ModuleLoadError {
+        match exception {
+            Some(exception) => ModuleLoadError::ScriptError(
+                specifier.clone(),
+                Box::new(self.remap_script_error(ScriptError::from_exception(scope, exception))),
+            ),
+            None => ModuleLoadError::V8InstaniateAndEvaluateError(specifier.clone()),
         }
+    }
+
+    fn add_source_map(self: &Self, specifier: &ModuleSpecifier, json: &str) {
+        match SourceMap::from_json(json) {
+            Ok(source_map) => {
+                self.source_maps
+                    .borrow_mut()
+                    .insert(specifier.clone(), Rc::new(source_map));
+            }
+            Err(err) => {
+                // only the reported positions are affected
+                warn!("ignore invalid source map of `{}`: {}", specifier, err);
+            }
+        }
+    }
+
+    pub fn get_source_map(self: &Self, specifier: &ModuleSpecifier) -> Option<Rc<SourceMap>> {
+        self.source_maps.borrow().get(specifier).cloned()
+    }
+
+    /// Map the 1-based position in the executed code to the original source.
+    ///
+    /// The position is returned unchanged if the module was not transpiled.
+    pub fn remap_position(self: &Self, file: &str, line: usize, column: usize) -> (usize, usize) {
+        self.get_source_map(&ModuleSpecifier::from(file))
+            .and_then(|source_map| {
+                source_map
+                    .lookup(line, column)
+                    .map(|position| (position.line, position.column))
+            })
+            .unwrap_or((line, column))
+    }
 
-        if module.get_status() == v8::ModuleStatus::Evaluated {
-            return Some(module.get_module_namespace());
+    /// Remap the location like `/path/to/file.ts:12:5` at the end of stack frame.
+    fn remap_stack_frame(self: &Self, frame: &str) -> String {
+        let (head, location, tail) = match frame.strip_suffix(')') {
+            Some(stripped) => match stripped.rsplit_once('(') {
+                Some((head, location)) => (format!("{}(", head), location, ")"),
+                None => return frame.to_string(),
+            },
+            None => match frame.rsplit_once(' ') {
+                Some((head, location)) => (format!("{} ", head), location, ""),
+                None => return frame.to_string(),
+            },
+        };
+
+        let mut parts = location.rsplitn(3, ':');
+
+        let (Some(column), Some(line), Some(file)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return frame.to_string();
+        };
+
+        let (Ok(line), Ok(column)) = (line.parse::<usize>(), column.parse::<usize>()) else {
+            return frame.to_string();
+        };
+
+        let (line, column) = self.remap_position(file, line, column);
+
+        format!("{}{}:{}:{}{}", head, file, line, column, tail)
+    }
+
+    pub fn remap_script_error(self: &Self, mut error: ScriptError) -> ScriptError {
+        if let (Some(file), Some(line), Some(column)) = (&error.file, error.line, error.column) {
+            let (line, column) = self.remap_position(file, line, column);
+            error.line = Some(line);
+            error.column = Some(column);
         }
 
-        None
+        error.stack = error.stack.map(|stack| {
+            stack
+                .lines()
+                .map(|frame| self.remap_stack_frame(frame))
+                .collect::<Vec<String>>()
+                .join("\n")
+        });
+
+        error
+    }
+
+    /// Throw an `Error` to the script, so the host error is not lost in the callbacks.
+    fn throw_error<'s, 'i>(scope: &PinScope<'s, 'i>, message: &str) {
+        let Some(message) = v8::String::new(scope, message) else {
+            error!("failed to allocate the message of error: {}", message);
+            return;
+        };
+
+        let exception = v8::Exception::error(scope, message);
+        scope.throw_exception(exception);
     }
 
     fn resolve_module_hook<'s, 'i>(
@@ -320,15 +932,21 @@ impl ModuleLoader {
         {
             Ok(resolved) => resolved,
             Err(err) => {
-                error!("failed to resolve module specifier: {}", err);
+                Self::throw_error(scope, &err.to_string());
                 return None;
             }
         };
 
-        match state.module_loader.resolve_module(scope, &resolved) {
+        let module = Self::get_module_type(scope, import_attributes, 3).and_then(|module_type| {
+            state
+                .module_loader
+                .resolve_module_with_type(scope, &resolved, module_type)
+        });
+
+        match module {
             Ok(module) => Some(module),
             Err(err) => {
-                error!("failed to resolve module: {}", err);
+                Self::throw_error(scope, &err.to_string());
                 None
             }
         }
@@ -349,53 +967,48 @@ impl ModuleLoader {
             }
         };
 
+        let resolver = match PromiseResolver::new(scope) {
+            Some(resolver) => resolver,
+            None => {
+                error!("failed to create PromiseResolver");
+                return None;
+            }
+        };
+
         let referer = ModuleSpecifier::from(resource_name.to_rust_string_lossy(scope));
         let specifier = specifier.to_rust_string_lossy(scope);
         let specifier = ModuleSpecifier::from(specifier);
 
-        let resolved = match state
+        let result = state
             .module_loader
             .resolve_module_specifier(&referer, &specifier)
-        {
-            Ok(resolved) => resolved,
-            Err(err) => {
-                error!("failed to resolve module specifier: {}", err);
-                return None;
-            }
-        };
+            .and_then(|resolved| {
+                Ok((
+                    resolved,
+                    Self::get_module_type(scope, import_attributes, 2)?,
+                ))
+            });
 
-        let module = match state.module_loader.resolve_module(scope, &resolved) {
-            Ok(module) => module,
-            Err(err) => {
-                error!("failed to resolve module: {}", err);
-                return None;
-            }
-        };
+        match result {
+            Ok((resolved, module_type)) => {
+                // the module is loaded and evaluated by the event loop
+                let global_resolver = v8::Global::new(scope, resolver);
 
-        let module = match state
-            .module_loader
-            .instantiate_and_evaluate_module(scope, &module)
-        {
-            Some(module) => module,
-            None => {
-                error!(
-                    "failed to instantiate and evaluate module: specifier {}, request from {}",
-                    specifier.to_string(),
-                    resource_name.to_rust_string_lossy(scope)
-                );
-                return None;
+                state
+                    .event_loop
+                    .queue_import(&state, global_resolver, resolved, module_type);
             }
-        };
-
-        let resolver = PromiseResolver::new(scope).unwrap();
+            Err(err) => {
+                // the import() expression rejects instead of crashing the host
+                let message = v8::String::new(scope, &err.to_string())?;
+                let exception = v8::Exception::error(scope, message);
 
-        match resolver.resolve(scope, module) {
-            Some(_) => (),
-            None => {
-                error!("failed to resolve PromiseResolver");
-                return None;
+                if resolver.reject(scope, exception).is_none() {
+                    error!("failed to reject the promise of dynamic import");
+                    return None;
+                }
             }
-        };
+        }
 
         Some(resolver.get_promise(scope))
     }
@@ -415,11 +1028,7 @@ impl ModuleLoader {
 
         let module = self.resolve_module(scope, module_specifier)?;
 
-        let module = self.instantiate_and_evaluate_module(scope, &module).ok_or(
-            ModuleLoadError::V8InstaniateAndEvaluateError(module_specifier.clone()),
-        )?;
-
-        Ok(module)
+        self.instantiate_and_evaluate_module(scope, module_specifier, &module)
     }
 
     pub fn apply(&self, isolate: &mut v8::OwnedIsolate) {
@@ -7,6 +7,9 @@ pub static IMPORT_MAP_MODULE_PREFIX: &'static str = "@";
 
 pub static BUILTIN_MODULE_PREFIX: &'static str = "zmake:";
 
+/// The node modules implemented by builtin modules, and the name of builtin module.
+pub static NODE_MODULE_ALIASES: &[(&str, &str)] = &[("node:console", "console")];
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum ModuleSpecifier {
     /// Built-in module,start with `BUILTIN_MODULE_PREFIX`
@@ -35,7 +38,9 @@ impl From<&str> for ModuleSpecifier {
 
 impl From<String> for ModuleSpecifier {
     fn from(mut s: String) -> Self {
-        if s.starts_with(BUILTIN_MODULE_PREFIX) {
+        if let Some((_, builtin)) = NODE_MODULE_ALIASES.iter().find(|(alias, _)| *alias == s) {
+            ModuleSpecifier::Builtin(builtin.to_string())
+        } else if s.starts_with(BUILTIN_MODULE_PREFIX) {
             ModuleSpecifier::Builtin(s.split_off(BUILTIN_MODULE_PREFIX.len()))
         } else if s.starts_with(MEMORY_MODULE_PREFIX) {
             ModuleSpecifier::Memory(s.split_off(MEMORY_MODULE_PREFIX.len()))
@@ -17,7 +17,7 @@ type StackString<'a> = smallvec::SmallVec<[&'a str; 8]>;
 /// - 不包含绝对路径前缀
 /// - 不包含诸如C:之类的驱动器前缀
 /// - 是有效的 UTF-8 字符串
-#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 #[serde(try_from = "String", into = "String")]
 pub struct NeutralPath(String);
 
@@ -332,6 +332,18 @@ impl NeutralPath {
         Some(NeutralPath(relative_parts.join("/")))
     }
 
+    /// Check if the path try to access the parent of its root via `..`.
+    ///
+    /// Because the path is normalized, only leading `..` components can escape.
+    pub fn is_escaping(&self) -> bool {
+        self.0 == ".." || self.0.starts_with("../")
+    }
+
+    /// Iterate over the components of the path, `.` yields nothing.
+    pub fn components(&self) -> impl Iterator<Item = &str> {
+        self.0.split('/').filter(|part| *part != ".")
+    }
+
     pub fn is_in_dir(&self, dir: &NeutralPath) -> bool {
         let relative = dir.get_relative_path_to(self);
         if let Some(rel_path) = relative {
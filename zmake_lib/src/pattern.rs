@@ -1,3 +1,4 @@
+use crate::path::NeutralPath;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -17,3 +18,36 @@ impl Pattern {
         }
     }
 }
+
+impl Pattern {
+    /// Check if the path matches the pattern, patterns are globs relative to the same root.
+    ///
+    /// If there is no includes, every path is included.
+    pub fn matches(&self, path: &NeutralPath) -> Result<bool, glob::PatternError> {
+        let any_match = |patterns: &Vec<String>| -> Result<bool, glob::PatternError> {
+            for pattern in patterns {
+                if glob::Pattern::new(pattern)?.matches(path.as_ref()) {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        };
+
+        match self {
+            Pattern::Includes(includes) => any_match(includes),
+            Pattern::IncludesAndExcludes { includes, excludes } => {
+                let included = match includes {
+                    Some(includes) => any_match(includes)?,
+                    None => true,
+                };
+
+                let excluded = match excludes {
+                    Some(excludes) => any_match(excludes)?,
+                    None => false,
+                };
+
+                Ok(included && !excluded)
+            }
+        }
+    }
+}
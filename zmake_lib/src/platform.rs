@@ -10,6 +10,13 @@ pub fn get_initialized_or_default() -> v8::SharedRef<v8::Platform> {
 pub fn initialize(thread_pool_size: u32, idle_task_support: bool) -> v8::SharedRef<v8::Platform> {
     let platform = PLATFORM
         .get_or_init(|| {
+            // pin the local time of scripts, ICU reads the default time zone from `TZ`
+            // when v8 creates the first isolate
+            //
+            // SAFETY: set once before v8 is initialized, zmake does not read the environment from
+            // other threads at the same time
+            unsafe { std::env::set_var("TZ", "UTC") };
+
             let platform =
                 v8::new_default_platform(thread_pool_size, idle_task_support).make_shared();
             v8::V8::initialize_platform(platform.clone());
@@ -1,12 +1,17 @@
-use crate::engine::Engine;
+use crate::audit::{AuditError, AuditPolicy, FileAccess, check_accesses};
+use crate::determinism::NonDeterministicUse;
+use crate::engine::{EngineError, Evaluation};
+use crate::engine_pool::{EnginePool, EnginePoolError};
 use crate::module_specifier::ModuleSpecifier;
+use crate::pattern::Pattern;
 use crate::project::ProjectExported;
 use crate::project_resolver::ProjectResolveError::{
     CircularDependency, FileNotExists, IOError, NotAFile,
 };
+use crate::version_requirement::{VersionRequirementError, check_zmake_version};
 use ahash::AHashMap;
 use std::cell::RefCell;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::Arc;
 use std::{fs, io};
@@ -23,53 +28,201 @@ pub enum ProjectResolveError {
     CircularDependency(PathBuf),
     #[error("get an io error")]
     IOError(#[from] io::Error),
+    #[error("failed to execute the project script: {0}")]
+    ScriptError(#[from] EngineError),
+    #[error("{0}")]
+    EnginePoolError(EnginePoolError),
+    #[error("the project script accessed undeclared file: {0}")]
+    UndeclaredAccess(#[from] AuditError),
+    #[error("{0}")]
+    VersionRequirement(#[from] VersionRequirementError),
+    #[error("the project script `{file}` uses non-deterministic API `{api}` at {location}")]
+    NonDeterministic {
+        file: PathBuf,
+        api: String,
+        location: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct ProjectResolverOptions {
+    /// The files that project scripts are allowed to read, relative to the sandbox root.
+    pub declared_inputs: Pattern,
+    pub audit_policy: AuditPolicy,
+    /// Fail if the project script uses a non-deterministic API, or they are only reported.
+    pub deny_non_deterministic: bool,
+}
+
+impl Default for ProjectResolverOptions {
+    fn default() -> Self {
+        Self {
+            declared_inputs: Pattern::Includes(
+                ["**/*.ts", "**/*.mts", "**/*.js", "**/*.mjs", "**/*.json"]
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect(),
+            ),
+            audit_policy: AuditPolicy::default(),
+            deny_non_deterministic: false,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct ProjectResolver {
-    engine: Engine,
+    pool: EnginePool,
+    options: ProjectResolverOptions,
     result: RefCell<AHashMap<PathBuf, ProjectExported>>,
     resolving: RefCell<AHashMap<PathBuf, bool>>,
+    accesses: RefCell<AHashMap<PathBuf, Vec<FileAccess>>>,
+    non_deterministic_uses: RefCell<AHashMap<PathBuf, Vec<NonDeterministicUse>>>,
 }
 
 impl ProjectResolver {
-    pub fn new(engine: Engine) -> Self {
+    pub fn new(pool: EnginePool, options: ProjectResolverOptions) -> Self {
         ProjectResolver {
-            engine,
+            pool,
+            options,
             result: RefCell::new(AHashMap::default()),
             resolving: RefCell::new(AHashMap::default()),
+            accesses: RefCell::new(AHashMap::default()),
+            non_deterministic_uses: RefCell::new(AHashMap::default()),
         }
     }
 
+    /// Get the files accessed when resolving the project.
+    pub fn get_accesses(&self, project_file: &PathBuf) -> Option<Vec<FileAccess>> {
+        self.accesses.borrow().get(project_file).cloned()
+    }
+
+    /// Get the uses of non-deterministic APIs when resolving the project.
+    pub fn get_non_deterministic_uses(
+        &self,
+        project_file: &PathBuf,
+    ) -> Option<Vec<NonDeterministicUse>> {
+        self.non_deterministic_uses
+            .borrow()
+            .get(project_file)
+            .cloned()
+    }
+
+    /// Check the `zmake` range of the exported project, the same as `requireZMakeVersion`.
+    ///
+    /// The literal ranges are checked when the module is loaded, this catches the computed ones.
+    fn check_requirement(
+        file: &Path,
+        exports: &serde_json::Value,
+    ) -> Result<(), VersionRequirementError> {
+        let project = &exports["default"];
+
+        let Some(range) = project["zmake"].as_str() else {
+            return Ok(());
+        };
+
+        let package = match (project["group"].as_str(), project["artifact"].as_str()) {
+            (Some(group), Some(artifact)) => format!("{}:{}", group, artifact),
+            _ => file.to_string_lossy().to_string(),
+        };
+
+        check_zmake_version(&package, range)
+    }
+
     #[instrument]
     pub fn resolve_project(
         self: &Self,
         project_file_path: String,
     ) -> Result<(), ProjectResolveError> {
-        let file = fs::canonicalize(project_file_path)?;
+        self.resolve_projects(vec![project_file_path])
+    }
 
-        if !file.exists() {
-            return Err(FileNotExists(file));
-        }
+    /// Resolve independent projects, their scripts are evaluated in parallel by the engine pool.
+    #[instrument]
+    pub fn resolve_projects(
+        self: &Self,
+        project_file_paths: Vec<String>,
+    ) -> Result<(), ProjectResolveError> {
+        let mut files = Vec::with_capacity(project_file_paths.len());
 
-        if !file.is_file() {
-            return Err(NotAFile(file));
+        for project_file_path in project_file_paths {
+            let file = fs::canonicalize(project_file_path)?;
+
+            if !file.exists() {
+                return Err(FileNotExists(file));
+            }
+
+            if !file.is_file() {
+                return Err(NotAFile(file));
+            }
+
+            if files.contains(&file) {
+                continue;
+            }
+
+            if let Some(status) = self.resolving.borrow().get(&file) {
+                if *status {
+                    return Err(CircularDependency(file));
+                }
+                continue;
+            }
+
+            files.push(file);
         }
 
-        if let Some(status) = self.resolving.borrow_mut().get(&file) {
-            return if *status {
-                Err(CircularDependency(file))
-            } else {
-                Ok(())
-            };
-        } else {
+        for file in &files {
             self.resolving.borrow_mut().insert(file.clone(), true);
         }
 
-        self.engine
-            .execute_module(&ModuleSpecifier::File(file.clone()));
+        let evaluations = self
+            .pool
+            .evaluate_all(files.iter().map(|file| ModuleSpecifier::File(file.clone())));
+
+        for file in &files {
+            self.resolving.borrow_mut().insert(file.clone(), false);
+        }
+
+        for (file, evaluated) in files.into_iter().zip(evaluations) {
+            let evaluated = evaluated.map_err(|err| match err {
+                EnginePoolError::EngineError(err) => ProjectResolveError::ScriptError(err),
+                err => ProjectResolveError::EnginePoolError(err),
+            })?;
+
+            self.check_evaluation(file, evaluated)?;
+        }
+
+        Ok(())
+    }
+
+    fn check_evaluation(
+        self: &Self,
+        file: PathBuf,
+        evaluated: Evaluation,
+    ) -> Result<(), ProjectResolveError> {
+        if self.options.deny_non_deterministic
+            && let Some(first) = evaluated.non_deterministic_uses.first()
+        {
+            return Err(ProjectResolveError::NonDeterministic {
+                file,
+                api: first.api.clone(),
+                location: first
+                    .location
+                    .clone()
+                    .unwrap_or_else(|| "unknown location".to_string()),
+            });
+        }
+
+        self.non_deterministic_uses
+            .borrow_mut()
+            .insert(file.clone(), evaluated.non_deterministic_uses);
+
+        Self::check_requirement(&file, &evaluated.exports)?;
+
+        check_accesses(
+            &evaluated.accesses,
+            &self.options.declared_inputs,
+            self.options.audit_policy,
+        )?;
 
-        self.resolving.borrow_mut().insert(file, false);
+        self.accesses.borrow_mut().insert(file, evaluated.accesses);
 
         Ok(())
     }
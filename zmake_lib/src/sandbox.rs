@@ -1,11 +1,33 @@
-use crate::path::NeutralPath;
+use crate::audit::{AccessAudit, AccessKind};
+use crate::path::{NeutralPath, PathError};
 use std::ffi::OsStr;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use thiserror::Error;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone)]
 pub struct Sandbox {
     root: PathBuf,
+    /// Every file opened through the sandbox is recorded here.
+    ///
+    /// Each evaluation uses its own audit, see [Sandbox::with_audit].
+    audit: Arc<AccessAudit>,
+}
+
+impl PartialEq for Sandbox {
+    fn eq(&self, other: &Self) -> bool {
+        self.root == other.root
+    }
+}
+
+impl Eq for Sandbox {}
+
+impl Hash for Sandbox {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.root.hash(state);
+    }
 }
 
 impl AsRef<OsStr> for Sandbox {
@@ -18,54 +40,820 @@ impl AsRef<OsStr> for Sandbox {
 pub enum SandboxError {
     #[error("try access file({target}) out of sandbox({sandbox})")]
     TryAccessFileOutOfSandbox { sandbox: PathBuf, target: PathBuf },
+    #[error("try access file({0}) through too many symlinks")]
+    SymlinkLoop(PathBuf),
+    #[error("get an path error:{0}")]
+    PathError(#[from] PathError),
     #[error("get an io error:{0}")]
     IoError(#[from] std::io::Error),
 }
 
+/// The kind of directory entry, symlinks are not followed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+#[cfg(unix)]
+impl EntryKind {
+    fn from_mode(mode: libc::mode_t) -> Self {
+        match mode & libc::S_IFMT {
+            libc::S_IFLNK => EntryKind::Symlink,
+            libc::S_IFDIR => EntryKind::Directory,
+            libc::S_IFREG => EntryKind::File,
+            _ => EntryKind::Other,
+        }
+    }
+}
+
+impl From<std::fs::FileType> for EntryKind {
+    fn from(file_type: std::fs::FileType) -> Self {
+        if file_type.is_symlink() {
+            EntryKind::Symlink
+        } else if file_type.is_dir() {
+            EntryKind::Directory
+        } else if file_type.is_file() {
+            EntryKind::File
+        } else {
+            EntryKind::Other
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryEntry {
+    pub name: String,
+    pub kind: EntryKind,
+}
+
+/// The metadata of a file, symlinks are not followed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStat {
+    pub kind: EntryKind,
+    pub size: u64,
+    pub readonly: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OpenKind {
+    Read,
+    Create,
+    Directory,
+}
+
+#[cfg(unix)]
+impl OpenKind {
+    fn flags(self) -> libc::c_int {
+        match self {
+            OpenKind::Read => libc::O_RDONLY,
+            OpenKind::Create => libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC,
+            OpenKind::Directory => libc::O_RDONLY | libc::O_DIRECTORY,
+        }
+    }
+
+    fn mode(self) -> libc::mode_t {
+        match self {
+            OpenKind::Create => 0o644,
+            _ => 0,
+        }
+    }
+}
+
 impl Sandbox {
     pub fn new<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
         let path = path.as_ref();
         let root = std::fs::canonicalize(path)?;
-        Ok(Self { root })
+        Ok(Self {
+            root,
+            audit: Arc::new(AccessAudit::new()),
+        })
+    }
+
+    pub fn get_root(&self) -> &Path {
+        &self.root
     }
 
+    pub fn get_audit(&self) -> &Arc<AccessAudit> {
+        &self.audit
+    }
+
+    /// The same sandbox that records accesses to another audit.
+    pub fn with_audit(&self, audit: Arc<AccessAudit>) -> Self {
+        Self {
+            root: self.root.clone(),
+            audit,
+        }
+    }
+
+    fn out_of_sandbox(&self, target: PathBuf) -> SandboxError {
+        SandboxError::TryAccessFileOutOfSandbox {
+            sandbox: self.root.clone(),
+            target,
+        }
+    }
+
+    fn check_contained(&self, relative_path: NeutralPath) -> Result<NeutralPath, SandboxError> {
+        if relative_path.is_escaping() {
+            Err(self.out_of_sandbox(self.root.join(relative_path)))
+        } else {
+            Ok(relative_path)
+        }
+    }
+
+    /// Get the path relative to the sandbox root.
+    ///
+    /// It is lexical, so the path does not need to exist.
+    pub fn get_relative_path<P: AsRef<Path>>(&self, path: &P) -> Result<NeutralPath, SandboxError> {
+        let path = path.as_ref();
+
+        let relative = path
+            .strip_prefix(&self.root)
+            .map_err(|_| self.out_of_sandbox(path.to_path_buf()))?;
+
+        if relative.as_os_str().is_empty() {
+            return Ok(NeutralPath::current_dir());
+        }
+
+        self.check_contained(NeutralPath::try_from(relative)?)
+    }
+
+    /// Join the target to the referer directory and check that it is in the sandbox.
+    ///
+    /// The check is lexical, so it works for files that do not exist yet.
+    /// Symlinks are not resolved, use `open_*` methods to access the file safely.
     pub fn get_path_safe<R: AsRef<Path>, T: AsRef<NeutralPath>>(
         &self,
         referer: &R,
         target: &T,
     ) -> Result<PathBuf, SandboxError> {
-        let referer = referer.as_ref();
-        let target = target.as_ref();
-
-        let referer = PathBuf::from(referer);
-        let target = referer.join(target);
-        let target = std::fs::canonicalize(target)?;
+        let referer = self.get_relative_path(referer)?;
+        let target = self.check_contained(referer.join(target.as_ref())?)?;
 
-        if target.starts_with(&self.root) {
-            Ok(target)
-        } else {
-            Err(SandboxError::TryAccessFileOutOfSandbox {
-                sandbox: self.root.clone(),
-                target,
-            })
-        }
+        Ok(self.root.join(target))
     }
 
     pub fn join_path_for<P: AsRef<NeutralPath>>(
         &self,
         relative_path: &P,
     ) -> Result<PathBuf, SandboxError> {
-        let relative_path = relative_path.as_ref();
-        let combined_path = self.root.join(relative_path);
-        let canonicalized_path = std::fs::canonicalize(&combined_path)?;
+        let relative_path = self.check_contained(relative_path.as_ref().clone())?;
+
+        Ok(self.root.join(relative_path))
+    }
+
+    /// Open the file for reading, the file and every symlink on the way must be in the sandbox.
+    pub fn open_file<P: AsRef<NeutralPath>>(&self, path: &P) -> Result<File, SandboxError> {
+        self.open_beneath(path.as_ref(), OpenKind::Read)
+    }
+
+    /// Create or truncate the file for writing, the parent directory must exist.
+    pub fn create_file<P: AsRef<NeutralPath>>(&self, path: &P) -> Result<File, SandboxError> {
+        self.open_beneath(path.as_ref(), OpenKind::Create)
+    }
+
+    pub fn open_directory<P: AsRef<NeutralPath>>(&self, path: &P) -> Result<File, SandboxError> {
+        self.open_beneath(path.as_ref(), OpenKind::Directory)
+    }
+
+    /// List the directory, the entries are sorted by name.
+    ///
+    /// Entries whose name is not valid unicode are skipped.
+    pub fn read_directory<P: AsRef<NeutralPath>>(
+        &self,
+        path: &P,
+    ) -> Result<Vec<DirectoryEntry>, SandboxError> {
+        // list the opened directory, so a symlink swapped in after the check is not followed
+        #[cfg(unix)]
+        let mut entries = list_directory(self.open_directory(path)?)?;
+        #[cfg(not(unix))]
+        let mut entries = self.list_directory_checked(path.as_ref())?;
+
+        // the order of the entries depends on the file system
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(entries)
+    }
+
+    /// Best effort for platforms without `fdopendir`.
+    #[cfg(not(unix))]
+    fn list_directory_checked(
+        &self,
+        path: &NeutralPath,
+    ) -> Result<Vec<DirectoryEntry>, SandboxError> {
+        // check the directory is in the sandbox, it is recorded as listed
+        drop(self.open_directory(path)?);
+
+        let mut entries = Vec::new();
+
+        for entry in std::fs::read_dir(self.root.join(path))? {
+            let entry = entry?;
+
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+
+            entries.push(DirectoryEntry {
+                name,
+                kind: entry.file_type()?.into(),
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// Get the metadata of the file, a symlink is reported as itself instead of its target.
+    pub fn stat<P: AsRef<NeutralPath>>(&self, path: &P) -> Result<FileStat, SandboxError> {
+        let path = self.check_contained(path.as_ref().clone())?;
+
+        self.audit.record(path.clone(), AccessKind::Read);
+
+        cfg_if::cfg_if! {
+            if #[cfg(unix)] {
+                self.stat_beneath(&path)
+            } else {
+                let metadata = std::fs::symlink_metadata(self.root.join(&path))?;
+
+                Ok(FileStat {
+                    kind: metadata.file_type().into(),
+                    size: metadata.len(),
+                    readonly: metadata.permissions().readonly(),
+                })
+            }
+        }
+    }
+
+    /// Open the parent directory beneath the root, then stat the last component with
+    /// `AT_SYMLINK_NOFOLLOW`, so neither can be redirected out of the sandbox.
+    #[cfg(unix)]
+    fn stat_beneath(&self, path: &NeutralPath) -> Result<FileStat, SandboxError> {
+        use std::ffi::CString;
+        use std::os::fd::AsRawFd;
+
+        let (parent, name) = match path.filename() {
+            Some(name) => (
+                self.open_contained(&path.parent(), OpenKind::Directory)?,
+                name,
+            ),
+            // the root itself
+            None => (File::open(&self.root)?, "."),
+        };
+
+        let c_name = CString::new(name)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+
+        let result = unsafe {
+            libc::fstatat(
+                parent.as_raw_fd(),
+                c_name.as_ptr(),
+                &mut stat,
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        };
+
+        if result != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(FileStat {
+            kind: EntryKind::from_mode(stat.st_mode),
+            size: stat.st_size as u64,
+            readonly: stat.st_mode & 0o222 == 0,
+        })
+    }
+
+    fn open_beneath(&self, path: &NeutralPath, kind: OpenKind) -> Result<File, SandboxError> {
+        let path = self.check_contained(path.clone())?;
+
+        // record the attempt even if it fails, a missing input is still an input
+        self.audit.record(
+            path.clone(),
+            match kind {
+                OpenKind::Read => AccessKind::Read,
+                OpenKind::Create => AccessKind::Write,
+                OpenKind::Directory => AccessKind::List,
+            },
+        );
+
+        self.open_contained(&path, kind)
+    }
+
+    /// Open the checked path without recording it.
+    fn open_contained(&self, path: &NeutralPath, kind: OpenKind) -> Result<File, SandboxError> {
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                self.open_beneath_openat2(path, kind)
+            } else if #[cfg(unix)] {
+                self.open_beneath_walk(path, kind)
+            } else {
+                self.open_beneath_checked(path, kind)
+            }
+        }
+    }
+
+    /// `RESOLVE_BENEATH` let the kernel reject any resolution out of the root(via `..` or symlinks),
+    /// so there is no race between check and use.
+    ///
+    /// Symlinks in the sandbox are followed, absolute symlinks and symlinks out of the sandbox
+    /// are refused, the same as [Sandbox::open_beneath_walk].
+    #[cfg(target_os = "linux")]
+    fn open_beneath_openat2(
+        &self,
+        path: &NeutralPath,
+        kind: OpenKind,
+    ) -> Result<File, SandboxError> {
+        use std::ffi::CString;
+        use std::os::fd::{AsRawFd, FromRawFd};
+
+        let root = File::open(&self.root)?;
+        let c_path = CString::new(path.to_string())
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+
+        let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+        how.flags = (kind.flags() | libc::O_CLOEXEC) as u64;
+        how.mode = kind.mode() as u64;
+        how.resolve = libc::RESOLVE_BENEATH | libc::RESOLVE_NO_MAGICLINKS;
+
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_openat2,
+                root.as_raw_fd(),
+                c_path.as_ptr(),
+                &how as *const libc::open_how,
+                std::mem::size_of::<libc::open_how>(),
+            )
+        };
+
+        if fd >= 0 {
+            return Ok(unsafe { File::from_raw_fd(fd as std::os::fd::RawFd) });
+        }
+
+        let error = std::io::Error::last_os_error();
+
+        match error.raw_os_error() {
+            Some(libc::EXDEV) => Err(self.out_of_sandbox(self.root.join(path))),
+            Some(libc::ELOOP) => Err(SandboxError::SymlinkLoop(self.root.join(path))),
+            // kernel before 5.6, or blocked by seccomp
+            Some(libc::ENOSYS) | Some(libc::EPERM) => self.open_beneath_walk(path, kind),
+            _ => Err(error.into()),
+        }
+    }
+
+    /// Resolve the path component by component, like `RESOLVE_BENEATH` does.
+    ///
+    /// Every component is opened with `O_NOFOLLOW`. Symlinks are read and resolved by hand from
+    /// the opened directories, so a symlink is followed only while it stays in the sandbox.
+    #[cfg(unix)]
+    fn open_beneath_walk(&self, path: &NeutralPath, kind: OpenKind) -> Result<File, SandboxError> {
+        use std::collections::VecDeque;
+        use std::ffi::CString;
+        use std::os::fd::{AsRawFd, FromRawFd};
+
+        let open_at = |directory: &File, name: &CString, flags: libc::c_int| {
+            let fd = unsafe {
+                libc::openat(
+                    directory.as_raw_fd(),
+                    name.as_ptr(),
+                    flags | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+                    kind.mode() as libc::c_uint,
+                )
+            };
+
+            if fd >= 0 {
+                Ok(unsafe { File::from_raw_fd(fd) })
+            } else {
+                Err(std::io::Error::last_os_error())
+            }
+        };
+
+        // the opened directories from the root to the current one
+        let mut directories = vec![File::open(&self.root)?];
+        let mut pending: VecDeque<String> = path.components().map(str::to_string).collect();
+        let mut follows = 0;
 
-        if canonicalized_path.starts_with(&self.root) {
-            Ok(canonicalized_path)
+        if pending.is_empty() {
+            pending.push_back(".".to_string());
+        }
+
+        while let Some(component) = pending.pop_front() {
+            let is_last = pending.is_empty();
+
+            match component.as_str() {
+                "" | "." => {}
+                ".." if directories.len() == 1 => {
+                    return Err(self.out_of_sandbox(self.root.join(path)));
+                }
+                ".." => {
+                    directories.pop();
+                }
+                _ => {
+                    let current = &directories[directories.len() - 1];
+                    let name = CString::new(component).map_err(|err| {
+                        std::io::Error::new(std::io::ErrorKind::InvalidInput, err)
+                    })?;
+
+                    let flags = if is_last {
+                        kind.flags()
+                    } else {
+                        OpenKind::Directory.flags()
+                    };
+
+                    let error = match open_at(current, &name, flags) {
+                        Ok(file) if is_last => return Ok(file),
+                        Ok(directory) => {
+                            directories.push(directory);
+                            continue;
+                        }
+                        Err(error) => error,
+                    };
+
+                    // `O_NOFOLLOW` fails with `ELOOP` on a symlink, some systems report `ENOTDIR`
+                    // for a symlink opened with `O_DIRECTORY`
+                    if !matches!(error.raw_os_error(), Some(libc::ELOOP | libc::ENOTDIR)) {
+                        return Err(error.into());
+                    }
+
+                    let Ok(target) = read_link_at(current, &name) else {
+                        return Err(error.into());
+                    };
+
+                    follows += 1;
+                    if follows > crate::fs::MAX_SYMLINK_FOLLOWS {
+                        return Err(SandboxError::SymlinkLoop(self.root.join(path)));
+                    }
+
+                    if target.starts_with('/') {
+                        return Err(self.out_of_sandbox(PathBuf::from(target)));
+                    }
+
+                    for part in target.split('/').rev() {
+                        pending.push_front(part.to_string());
+                    }
+
+                    continue;
+                }
+            }
+
+            // the path ends at a directory, e.g. the root or a symlink to `..`
+            if is_last {
+                let current = &directories[directories.len() - 1];
+
+                return Ok(open_at(current, &CString::from(c"."), kind.flags())?);
+            }
+        }
+
+        Err(std::io::Error::from(std::io::ErrorKind::NotFound).into())
+    }
+
+    /// Best effort for platforms without `openat`.
+    #[cfg(not(unix))]
+    fn open_beneath_checked(
+        &self,
+        path: &NeutralPath,
+        kind: OpenKind,
+    ) -> Result<File, SandboxError> {
+        let target = self.root.join(path);
+
+        let file = match kind {
+            OpenKind::Read | OpenKind::Directory => File::open(&target)?,
+            OpenKind::Create => File::create(&target)?,
+        };
+
+        let canonicalized = std::fs::canonicalize(&target)?;
+
+        if canonicalized.starts_with(&self.root) {
+            Ok(file)
         } else {
-            Err(SandboxError::TryAccessFileOutOfSandbox {
-                sandbox: self.root.clone(),
-                target: canonicalized_path,
+            Err(self.out_of_sandbox(canonicalized))
+        }
+    }
+}
+
+/// Read the target of the symlink in the directory.
+#[cfg(unix)]
+fn read_link_at(directory: &File, name: &std::ffi::CStr) -> std::io::Result<String> {
+    use std::os::fd::AsRawFd;
+
+    let mut buffer = vec![0u8; libc::PATH_MAX as usize];
+
+    let length = unsafe {
+        libc::readlinkat(
+            directory.as_raw_fd(),
+            name.as_ptr(),
+            buffer.as_mut_ptr().cast(),
+            buffer.len(),
+        )
+    };
+
+    if length < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    buffer.truncate(length as usize);
+
+    String::from_utf8(buffer)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
+
+/// Closes the directory stream, and the fd it owns, on drop.
+#[cfg(unix)]
+struct DirectoryStream(*mut libc::DIR);
+
+#[cfg(unix)]
+impl Drop for DirectoryStream {
+    fn drop(&mut self) {
+        unsafe { libc::closedir(self.0) };
+    }
+}
+
+/// Reset `errno`, `readdir` returns null both at the end and on failure.
+#[cfg(unix)]
+fn clear_errno() {
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_os = "linux", target_os = "android"))] {
+            unsafe { *libc::__errno_location() = 0 };
+        } else if #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))] {
+            unsafe { *libc::__error() = 0 };
+        }
+    }
+}
+
+/// List the entries of the opened directory, `.` and `..` are skipped.
+#[cfg(unix)]
+fn list_directory(directory: File) -> Result<Vec<DirectoryEntry>, SandboxError> {
+    use std::ffi::{CStr, CString};
+    use std::os::fd::IntoRawFd;
+
+    let fd = directory.into_raw_fd();
+
+    let stream = unsafe { libc::fdopendir(fd) };
+
+    if stream.is_null() {
+        let error = std::io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        return Err(error.into());
+    }
+
+    let stream = DirectoryStream(stream);
+
+    let mut entries = Vec::new();
+
+    loop {
+        clear_errno();
+
+        let entry = unsafe { libc::readdir(stream.0) };
+
+        if entry.is_null() {
+            let error = std::io::Error::last_os_error();
+
+            match error.raw_os_error() {
+                None | Some(0) => break,
+                _ => return Err(error.into()),
+            }
+        }
+
+        let entry = unsafe { &*entry };
+        let c_name = unsafe { CStr::from_ptr(entry.d_name.as_ptr()) };
+
+        let Ok(name) = c_name.to_str() else {
+            continue;
+        };
+
+        if name == "." || name == ".." {
+            continue;
+        }
+
+        let kind = match entry.d_type {
+            libc::DT_LNK => EntryKind::Symlink,
+            libc::DT_DIR => EntryKind::Directory,
+            libc::DT_REG => EntryKind::File,
+            // some file systems do not fill the type
+            libc::DT_UNKNOWN => {
+                let c_name = CString::from(c_name);
+                let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+
+                let result = unsafe {
+                    libc::fstatat(
+                        libc::dirfd(stream.0),
+                        c_name.as_ptr(),
+                        &mut stat,
+                        libc::AT_SYMLINK_NOFOLLOW,
+                    )
+                };
+
+                if result != 0 {
+                    return Err(std::io::Error::last_os_error().into());
+                }
+
+                EntryKind::from_mode(stat.st_mode)
+            }
+            _ => EntryKind::Other,
+        };
+
+        entries.push(DirectoryEntry {
+            name: name.to_string(),
+            kind,
+        });
+    }
+
+    Ok(entries)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    /// A sandbox whose root is a fresh temporary directory.
+    struct TestSandbox {
+        sandbox: Sandbox,
+        root: TempDir,
+    }
+
+    impl TestSandbox {
+        fn new() -> Result<Self, SandboxError> {
+            let root = TempDir::new()?;
+
+            Ok(Self {
+                sandbox: Sandbox::new(root.path())?,
+                root,
             })
         }
+
+        fn root(&self) -> &Path {
+            self.root.path()
+        }
+    }
+
+    #[test]
+    fn stat_does_not_follow_symlink() -> Result<(), SandboxError> {
+        let test = TestSandbox::new()?;
+        std::fs::write(test.root().join("file"), "data")?;
+        std::os::unix::fs::symlink("file", test.root().join("link"))?;
+
+        let file = test.sandbox.stat(&NeutralPath::new("file")?)?;
+        assert_eq!(file.kind, EntryKind::File);
+        assert_eq!(file.size, 4);
+
+        let link = test.sandbox.stat(&NeutralPath::new("link")?)?;
+        assert_eq!(link.kind, EntryKind::Symlink);
+
+        let root = test.sandbox.stat(&NeutralPath::current_dir())?;
+        assert_eq!(root.kind, EntryKind::Directory);
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_directory_lists_the_opened_directory() -> Result<(), SandboxError> {
+        let test = TestSandbox::new()?;
+        std::fs::create_dir(test.root().join("directory"))?;
+        std::fs::write(test.root().join("directory/file"), "")?;
+        std::fs::create_dir(test.root().join("directory/nested"))?;
+        std::os::unix::fs::symlink("/", test.root().join("directory/link"))?;
+
+        let entries = test
+            .sandbox
+            .read_directory(&NeutralPath::new("directory")?)?;
+
+        assert_eq!(
+            entries,
+            vec![
+                DirectoryEntry {
+                    name: "file".to_string(),
+                    kind: EntryKind::File,
+                },
+                DirectoryEntry {
+                    name: "link".to_string(),
+                    kind: EntryKind::Symlink,
+                },
+                DirectoryEntry {
+                    name: "nested".to_string(),
+                    kind: EntryKind::Directory,
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_directory_rejects_symlink_out_of_sandbox() -> Result<(), SandboxError> {
+        let test = TestSandbox::new()?;
+        std::os::unix::fs::symlink("/", test.root().join("link"))?;
+
+        assert!(
+            test.sandbox
+                .read_directory(&NeutralPath::new("link")?)
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    /// The name of the way and its result.
+    type Opened = (&'static str, Result<File, SandboxError>);
+
+    /// Open the file in every way, so the kernel dependent ways are tested on every kernel.
+    fn open_every_way(sandbox: &Sandbox, path: &str) -> Result<Vec<Opened>, SandboxError> {
+        let path = NeutralPath::new(path)?;
+
+        let mut results = vec![("walk", sandbox.open_beneath_walk(&path, OpenKind::Read))];
+
+        #[cfg(target_os = "linux")]
+        results.push((
+            "openat2",
+            sandbox.open_beneath_openat2(&path, OpenKind::Read),
+        ));
+
+        Ok(results)
+    }
+
+    /// The sandbox is `root/sandbox`, next to `root/outside/secret.txt`.
+    fn sandbox_with_outside() -> Result<(Sandbox, TempDir), SandboxError> {
+        let root = TempDir::new()?;
+        std::fs::create_dir_all(root.path().join("sandbox/directory"))?;
+        std::fs::create_dir_all(root.path().join("outside"))?;
+        std::fs::write(root.path().join("outside/secret.txt"), "secret")?;
+
+        Ok((Sandbox::new(root.path().join("sandbox"))?, root))
+    }
+
+    #[test]
+    fn symlinks_in_sandbox_are_followed() -> Result<(), SandboxError> {
+        let (sandbox, _root) = sandbox_with_outside()?;
+        let sandbox_root = sandbox.get_root().to_path_buf();
+
+        std::fs::write(sandbox_root.join("directory/file.txt"), "data")?;
+        std::os::unix::fs::symlink("directory/file.txt", sandbox_root.join("down"))?;
+        std::os::unix::fs::symlink("../down", sandbox_root.join("directory/up"))?;
+        std::os::unix::fs::symlink(".", sandbox_root.join("directory/self"))?;
+
+        for path in ["down", "directory/up", "directory/self/self/file.txt"] {
+            for (way, file) in open_every_way(&sandbox, path)? {
+                let mut data = String::new();
+                std::io::Read::read_to_string(&mut file?, &mut data)?;
+
+                assert_eq!(data, "data", "{} opens `{}` wrongly", way, path);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn symlinks_out_of_sandbox_are_refused() -> Result<(), SandboxError> {
+        let (sandbox, root) = sandbox_with_outside()?;
+        let sandbox_root = sandbox.get_root().to_path_buf();
+
+        std::os::unix::fs::symlink("..", sandbox_root.join("parent"))?;
+        std::os::unix::fs::symlink(
+            root.path().join("outside/secret.txt"),
+            sandbox_root.join("absolute"),
+        )?;
+        std::os::unix::fs::symlink(
+            "../../outside/secret.txt",
+            sandbox_root.join("directory/relative"),
+        )?;
+
+        for path in [
+            "parent/outside/secret.txt",
+            "absolute",
+            "directory/relative",
+        ] {
+            for (way, file) in open_every_way(&sandbox, path)? {
+                assert!(
+                    matches!(file, Err(SandboxError::TryAccessFileOutOfSandbox { .. })),
+                    "{} opens `{}`",
+                    way,
+                    path
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn symlink_loop_is_refused() -> Result<(), SandboxError> {
+        let (sandbox, _root) = sandbox_with_outside()?;
+        let sandbox_root = sandbox.get_root().to_path_buf();
+
+        std::os::unix::fs::symlink("b", sandbox_root.join("a"))?;
+        std::os::unix::fs::symlink("a", sandbox_root.join("b"))?;
+
+        for (way, file) in open_every_way(&sandbox, "a")? {
+            assert!(
+                matches!(file, Err(SandboxError::SymlinkLoop(_))),
+                "{} opens the loop",
+                way
+            );
+        }
+
+        Ok(())
     }
 }
@@ -0,0 +1,138 @@
+//! On-disk cache of the work to load build scripts, it is reused across runs.
+//!
+//! It holds the transpiled typescript and the V8 code cache of compiled modules.
+//!
+//! The cache is only an optimization, so failures are logged and treated as a miss.
+
+use crate::chunking::digest_of;
+use crate::transformer::{TRANSFORM_TARGET, Transpiled};
+use std::path::PathBuf;
+use tracing::{trace, warn};
+
+static TRANSPILED_DIRECTORY: &'static str = "transpiled";
+static CODE_CACHE_DIRECTORY: &'static str = "code";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ScriptCache {
+    directory: PathBuf,
+}
+
+impl ScriptCache {
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+
+    /// Every part that affects the output must be in the key.
+    fn key(parts: &[&str]) -> String {
+        let digest = digest_of(parts.join("\0").as_bytes());
+
+        digest.hex_fast_xxhash3_128()
+    }
+
+    fn entry_path(&self, kind: &str, key: &str) -> PathBuf {
+        self.directory.join(kind).join(&key[0..2]).join(&key[2..])
+    }
+
+    fn read(&self, kind: &str, key: &str) -> Option<Vec<u8>> {
+        let path = self.entry_path(kind, key);
+
+        match std::fs::read(&path) {
+            Ok(data) => {
+                trace!("script cache hit {}", path.display());
+                Some(data)
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => {
+                warn!("failed to read script cache {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    fn write(&self, kind: &str, key: &str, data: &[u8]) {
+        let path = self.entry_path(kind, key);
+
+        let result = (|| -> std::io::Result<()> {
+            let parent = path
+                .parent()
+                .ok_or_else(|| std::io::Error::other("invalid script cache path"))?;
+
+            std::fs::create_dir_all(parent)?;
+
+            // other zmake processes may read the entry at the same time
+            let temp_path = parent.join(format!("tmp_{}", uuid::Uuid::new_v4()));
+            std::fs::write(&temp_path, data)?;
+            std::fs::rename(&temp_path, &path)
+        })();
+
+        if let Err(err) = result {
+            warn!("failed to write script cache {}: {}", path.display(), err);
+        }
+    }
+
+    fn transpiled_key(source_code: &str, source_name: &str) -> String {
+        let source = digest_of(source_code.as_bytes());
+
+        Self::key(&[
+            &source.hex_fast_xxhash3_128(),
+            &source.size_bytes.to_string(),
+            // the source map refers to the source name
+            source_name,
+            TRANSFORM_TARGET,
+            env!("CARGO_PKG_VERSION"),
+        ])
+    }
+
+    pub fn get_transpiled(&self, source_code: &str, source_name: &str) -> Option<Transpiled> {
+        let data = self.read(
+            TRANSPILED_DIRECTORY,
+            &Self::transpiled_key(source_code, source_name),
+        )?;
+
+        match serde_json::from_slice(&data) {
+            Ok(transpiled) => Some(transpiled),
+            Err(err) => {
+                warn!(
+                    "ignore corrupted transpile cache of {}: {}",
+                    source_name, err
+                );
+                None
+            }
+        }
+    }
+
+    pub fn put_transpiled(&self, source_code: &str, source_name: &str, transpiled: &Transpiled) {
+        match serde_json::to_vec(transpiled) {
+            Ok(data) => self.write(
+                TRANSPILED_DIRECTORY,
+                &Self::transpiled_key(source_code, source_name),
+                &data,
+            ),
+            Err(err) => warn!("failed to serialize transpile cache: {}", err),
+        }
+    }
+
+    /// V8 checks the source itself, but the data is only valid for the same V8 version.
+    fn code_cache_key(source_code: &str) -> String {
+        let source = digest_of(source_code.as_bytes());
+
+        Self::key(&[
+            &source.hex_fast_xxhash3_128(),
+            &source.size_bytes.to_string(),
+            v8::V8::get_version(),
+            env!("CARGO_PKG_VERSION"),
+        ])
+    }
+
+    pub fn get_code_cache(&self, source_code: &str) -> Option<Vec<u8>> {
+        self.read(CODE_CACHE_DIRECTORY, &Self::code_cache_key(source_code))
+    }
+
+    pub fn put_code_cache(&self, source_code: &str, code_cache: &[u8]) {
+        self.write(
+            CODE_CACHE_DIRECTORY,
+            &Self::code_cache_key(source_code),
+            code_cache,
+        )
+    }
+}
@@ -0,0 +1,88 @@
+//! Errors thrown by build scripts.
+
+use std::fmt::Display;
+use v8::{Local, PinScope, Value};
+
+/// A JavaScript exception(or a rejected promise) converted for the host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    pub message: String,
+    /// The script resource name, e.g. the module specifier.
+    pub file: Option<String>,
+    /// 1-based.
+    pub line: Option<usize>,
+    /// 1-based.
+    pub column: Option<usize>,
+    /// The `stack` property of the thrown value, if it is an `Error`.
+    pub stack: Option<String>,
+}
+
+impl std::error::Error for ScriptError {}
+
+impl Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+
+        if let Some(file) = &self.file {
+            write!(f, "\n    at {}", file)?;
+
+            if let Some(line) = self.line {
+                write!(f, ":{}", line)?;
+
+                if let Some(column) = self.column {
+                    write!(f, ":{}", column)?;
+                }
+            }
+        }
+
+        if let Some(stack) = &self.stack {
+            // the first lines of the stack repeat the message
+            for frame in stack
+                .lines()
+                .skip_while(|line| !line.trim_start().starts_with("at "))
+            {
+                write!(f, "\n    {}", frame.trim_start())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ScriptError {
+    pub fn new<S: Into<String>>(message: S) -> Self {
+        Self {
+            message: message.into(),
+            file: None,
+            line: None,
+            column: None,
+            stack: None,
+        }
+    }
+
+    /// Convert the thrown value, the location is where it was thrown.
+    pub fn from_exception<'s, 'i>(scope: &PinScope<'s, 'i>, exception: Local<'s, Value>) -> Self {
+        let message = v8::Exception::create_message(scope, exception);
+
+        let stack = exception
+            .to_object(scope)
+            .filter(|_| exception.is_native_error())
+            .and_then(|object| {
+                let key = v8::String::new(scope, "stack")?;
+                object.get(scope, key.into())
+            })
+            .filter(|stack| stack.is_string())
+            .map(|stack| stack.to_rust_string_lossy(scope));
+
+        Self {
+            message: message.get(scope).to_rust_string_lossy(scope),
+            file: message
+                .get_script_resource_name(scope)
+                .filter(|name| !name.is_undefined())
+                .map(|name| name.to_rust_string_lossy(scope)),
+            line: message.get_line_number(scope),
+            column: Some(message.get_start_column() + 1),
+            stack,
+        }
+    }
+}
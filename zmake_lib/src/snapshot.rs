@@ -0,0 +1,137 @@
+//! V8 startup snapshot with the zmake runtime evaluated.
+//!
+//! The snapshot is created at build time(see the build script of zmake-cli), so every
+//! engine starts with the `rt` module and the builtin synthetic modules ready.
+
+use crate::builtin;
+use crate::module_loader::ModuleLoadError;
+use crate::module_specifier::ModuleSpecifier;
+use crate::platform::get_initialized_or_default;
+use std::borrow::Cow;
+use v8::script_compiler::Source;
+use v8::{Local, PinScope, ScriptOrigin};
+
+/// The modules in the snapshot, the index is the index of context data.
+pub fn snapshot_modules() -> Vec<&'static ModuleSpecifier> {
+    std::iter::once(&*builtin::js::RT)
+        .chain(
+            builtin::SYNTHETIC_MODULES
+                .iter()
+                .map(|module| module.specifier),
+        )
+        .collect()
+}
+
+/// The external references must be the same when creating and using the snapshot.
+pub fn external_references() -> Cow<'static, [v8::ExternalReference]> {
+    Cow::Owned(
+        builtin::SYNTHETIC_MODULES
+            .iter()
+            .flat_map(|module| (module.external_references)())
+            .collect(),
+    )
+}
+
+fn create_origin<'s, 'i>(
+    scope: &PinScope<'s, 'i>,
+    specifier: &ModuleSpecifier,
+) -> Result<ScriptOrigin<'s>, ModuleLoadError> {
+    Ok(ScriptOrigin::new(
+        scope,
+        v8::String::new(scope, specifier.to_string().as_str())
+            .ok_or(ModuleLoadError::V8ObjectAllocationError(
+                "v8::String::new(scope,specifier.to_string())",
+            ))?
+            .into(),
+        0,
+        0,
+        false,
+        0,
+        None,
+        false,
+        false,
+        true,
+        None,
+    ))
+}
+
+/// The builtin modules have no imports.
+fn no_imports<'s>(
+    _context: Local<'s, v8::Context>,
+    _specifier: Local<'s, v8::String>,
+    _import_attributes: Local<'s, v8::FixedArray>,
+    _referrer: Local<'s, v8::Module>,
+) -> Option<Local<'s, v8::Module>> {
+    None
+}
+
+fn create_module<'s, 'i>(
+    scope: &mut PinScope<'s, 'i>,
+    specifier: &ModuleSpecifier,
+) -> Result<Local<'s, v8::Module>, ModuleLoadError> {
+    let module = if specifier.eq(&builtin::js::RT) {
+        let origin = create_origin(scope, specifier)?;
+
+        let v8_source = v8::String::new(scope, builtin::js::RT_CODE).ok_or(
+            ModuleLoadError::V8ObjectAllocationError(
+                "v8::String::new(scope, &crate::builtin::js::RT_CODE)",
+            ),
+        )?;
+
+        v8::script_compiler::compile_module(scope, &mut Source::new(v8_source, Some(&origin)))
+            .ok_or_else(|| ModuleLoadError::V8CompileError(specifier.clone()))?
+    } else if let Some(synthetic) = builtin::find_synthetic_module(specifier) {
+        (synthetic.create)(scope, specifier)?
+    } else {
+        return Err(ModuleLoadError::UnknownBuiltinModuleSpecifier(
+            specifier.to_string(),
+        ));
+    };
+
+    if module.instantiate_module(scope, no_imports) != Some(true) {
+        return Err(ModuleLoadError::V8InstaniateAndEvaluateError(
+            specifier.clone(),
+        ));
+    }
+
+    if module.evaluate(scope).is_none() || module.get_status() != v8::ModuleStatus::Evaluated {
+        return Err(ModuleLoadError::V8InstaniateAndEvaluateError(
+            specifier.clone(),
+        ));
+    }
+
+    Ok(module)
+}
+
+/// Create the startup snapshot blob.
+pub fn create_snapshot() -> Result<Vec<u8>, ModuleLoadError> {
+    let _ = get_initialized_or_default();
+
+    let mut isolate = v8::Isolate::snapshot_creator(Some(external_references()), None);
+
+    {
+        let scope = std::pin::pin!(v8::HandleScope::new(&mut isolate));
+        let mut scope = scope.init();
+
+        let context = v8::Context::new(&scope, Default::default());
+        let scope = &mut v8::ContextScope::new(&mut scope, context);
+
+        for (index, specifier) in snapshot_modules().into_iter().enumerate() {
+            let module = create_module(scope, specifier)?;
+
+            if scope.add_context_data(context, module) != index {
+                return Err(ModuleLoadError::SnapshotError(
+                    "the index of snapshot context data is unexpected",
+                ));
+            }
+        }
+
+        scope.set_default_context(context);
+    }
+
+    let blob = isolate.create_blob(v8::FunctionCodeHandling::Keep).ok_or(
+        ModuleLoadError::SnapshotError("failed to create snapshot blob"),
+    )?;
+
+    Ok(blob.to_vec())
+}
@@ -0,0 +1,279 @@
+//! Map positions in transpiled scripts back to the original source.
+//!
+//! Only the parts of the source map v3 format that oxc emits are supported.
+
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SourceMapError {
+    #[error("invalid source map json: {0}")]
+    InvalidJson(#[from] serde_json::Error),
+    #[error("invalid base64 VLQ character `{0}` in mappings")]
+    InvalidVlqCharacter(char),
+    #[error("the VLQ value in mappings is truncated or too large")]
+    InvalidVlqValue,
+    #[error("the segment in mappings has {0} fields, expect 1, 4 or 5")]
+    InvalidSegment(usize),
+    #[error("the segment in mappings refers to unknown source {0}")]
+    UnknownSource(i64),
+}
+
+#[derive(Deserialize)]
+struct RawSourceMap {
+    sources: Vec<String>,
+    mappings: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Mapping {
+    generated_column: u32,
+    source: u32,
+    original_line: u32,
+    original_column: u32,
+}
+
+/// A position in the original source, 1-based like V8 reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OriginalPosition<'a> {
+    pub source: &'a str,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceMap {
+    sources: Vec<String>,
+    /// Mappings of every generated line, sorted by generated column.
+    lines: Vec<Vec<Mapping>>,
+}
+
+fn decode_base64(c: u8) -> Result<i64, SourceMapError> {
+    Ok(match c {
+        b'A'..=b'Z' => c - b'A',
+        b'a'..=b'z' => c - b'a' + 26,
+        b'0'..=b'9' => c - b'0' + 52,
+        b'+' => 62,
+        b'/' => 63,
+        _ => return Err(SourceMapError::InvalidVlqCharacter(c as char)),
+    } as i64)
+}
+
+/// Decode the VLQ values of one segment.
+fn decode_segment(segment: &str, fields: &mut Vec<i64>) -> Result<(), SourceMapError> {
+    fields.clear();
+
+    let mut value: i64 = 0;
+    let mut shift = 0;
+
+    for c in segment.bytes() {
+        let digit = decode_base64(c)?;
+
+        if shift > 60 {
+            return Err(SourceMapError::InvalidVlqValue);
+        }
+
+        value |= (digit & 0b11111) << shift;
+        shift += 5;
+
+        // continuation bit
+        if digit & 0b100000 == 0 {
+            let negative = value & 1 == 1;
+            value >>= 1;
+            fields.push(if negative { -value } else { value });
+
+            value = 0;
+            shift = 0;
+        }
+    }
+
+    if shift != 0 {
+        return Err(SourceMapError::InvalidVlqValue);
+    }
+
+    Ok(())
+}
+
+impl SourceMap {
+    pub fn from_json(json: &str) -> Result<Self, SourceMapError> {
+        let raw: RawSourceMap = serde_json::from_str(json)?;
+
+        let mut lines = Vec::new();
+        let mut fields = Vec::with_capacity(5);
+
+        // all fields but the generated column are relative to the previous segment in the whole map
+        let mut source: i64 = 0;
+        let mut original_line: i64 = 0;
+        let mut original_column: i64 = 0;
+
+        for line in raw.mappings.split(';') {
+            let mut mappings = Vec::new();
+            let mut generated_column: i64 = 0;
+
+            for segment in line.split(',').filter(|x| !x.is_empty()) {
+                decode_segment(segment, &mut fields)?;
+
+                match fields.len() {
+                    // the generated code has no original source
+                    1 => {
+                        generated_column += fields[0];
+                        continue;
+                    }
+                    4 | 5 => {}
+                    count => return Err(SourceMapError::InvalidSegment(count)),
+                }
+
+                generated_column += fields[0];
+                source += fields[1];
+                original_line += fields[2];
+                original_column += fields[3];
+
+                if source < 0 || source as usize >= raw.sources.len() {
+                    return Err(SourceMapError::UnknownSource(source));
+                }
+
+                if generated_column < 0 || original_line < 0 || original_column < 0 {
+                    return Err(SourceMapError::InvalidVlqValue);
+                }
+
+                mappings.push(Mapping {
+                    generated_column: generated_column as u32,
+                    source: source as u32,
+                    original_line: original_line as u32,
+                    original_column: original_column as u32,
+                });
+            }
+
+            mappings.sort_by_key(|x| x.generated_column);
+            lines.push(mappings);
+        }
+
+        Ok(Self {
+            sources: raw.sources,
+            lines,
+        })
+    }
+
+    /// Find the original position of the generated position, both are 1-based.
+    pub fn lookup(&self, line: usize, column: usize) -> Option<OriginalPosition<'_>> {
+        let mappings = self.lines.get(line.checked_sub(1)?)?;
+        let column = column.saturating_sub(1) as u32;
+
+        // the last mapping starts before the column
+        let index = match mappings.binary_search_by_key(&column, |x| x.generated_column) {
+            Ok(index) => index,
+            Err(0) => 0,
+            Err(index) => index - 1,
+        };
+
+        let mapping = mappings.get(index)?;
+
+        Some(OriginalPosition {
+            source: self.sources.get(mapping.source as usize)?,
+            line: mapping.original_line as usize + 1,
+            column: mapping.original_column as usize + 1,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transformer::transform_typescript;
+
+    fn decode(segment: &str) -> Result<Vec<i64>, SourceMapError> {
+        let mut fields = Vec::new();
+        decode_segment(segment, &mut fields)?;
+        Ok(fields)
+    }
+
+    fn map(mappings: &str) -> Result<SourceMap, SourceMapError> {
+        SourceMap::from_json(&format!(
+            r#"{{ "version": 3, "sources": ["main.ts"], "names": [], "mappings": "{}" }}"#,
+            mappings
+        ))
+    }
+
+    #[test]
+    fn vlq_values() -> Result<(), SourceMapError> {
+        assert_eq!(decode("A")?, [0]);
+        assert_eq!(decode("C")?, [1]);
+        assert_eq!(decode("D")?, [-1]);
+        assert_eq!(decode("f")?, [-15]);
+        assert_eq!(decode("gB")?, [16]);
+        assert_eq!(decode("hB")?, [-16]);
+        assert_eq!(decode("w+B")?, [1000]);
+        assert_eq!(decode("AAgBD")?, [0, 0, 16, -1]);
+        assert_eq!(decode("")?, Vec::<i64>::new());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_vlq_values() {
+        assert!(matches!(
+            decode("A!"),
+            Err(SourceMapError::InvalidVlqCharacter('!'))
+        ));
+        assert!(matches!(decode("g"), Err(SourceMapError::InvalidVlqValue)));
+        assert!(matches!(
+            decode("gggggggggggggB"),
+            Err(SourceMapError::InvalidVlqValue)
+        ));
+    }
+
+    #[test]
+    fn empty_lines_and_segments() -> Result<(), SourceMapError> {
+        let map = map(";;AAAA,,EAAE,C;")?;
+
+        assert_eq!(map.lookup(1, 1), None);
+        assert_eq!(map.lookup(2, 1), None);
+        assert_eq!(map.lookup(4, 1), None);
+        assert_eq!(map.lookup(5, 1), None);
+        assert_eq!(map.lookup(0, 1), None);
+
+        let position = |line, column| OriginalPosition {
+            source: "main.ts",
+            line,
+            column,
+        };
+
+        assert_eq!(map.lookup(3, 1), Some(position(1, 1)));
+        assert_eq!(map.lookup(3, 2), Some(position(1, 1)));
+        assert_eq!(map.lookup(3, 3), Some(position(1, 3)));
+        // the segment without original source only moves the generated column
+        assert_eq!(map.lookup(3, 80), Some(position(1, 3)));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_segments() {
+        assert!(matches!(map("AA"), Err(SourceMapError::InvalidSegment(2))));
+        assert!(matches!(map("ACAA"), Err(SourceMapError::UnknownSource(1))));
+        assert!(matches!(map("AAAD"), Err(SourceMapError::InvalidVlqValue)));
+        assert!(matches!(
+            SourceMap::from_json("{}"),
+            Err(SourceMapError::InvalidJson(_))
+        ));
+    }
+
+    #[test]
+    fn lookup_in_transpiled_typescript() -> Result<(), Box<dyn std::error::Error>> {
+        let source = "interface Options {\n  name: string;\n}\n\nconst options: Options = { name: \"zmake\" };\nthrow new Error(options.name);\n";
+
+        let transpiled = transform_typescript(source, "main.ts")?;
+        let map = SourceMap::from_json(transpiled.source_map.as_deref().ok_or("no source map")?)?;
+
+        let (line, column) = transpiled
+            .code
+            .lines()
+            .enumerate()
+            .find_map(|(index, line)| line.find("throw").map(|column| (index + 1, column + 1)))
+            .ok_or("no throw statement")?;
+
+        let position = map.lookup(line, column).ok_or("no mapping")?;
+
+        assert!(position.source.ends_with("main.ts"));
+        assert_eq!((position.line, position.column), (6, 1));
+        Ok(())
+    }
+}
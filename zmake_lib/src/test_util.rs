@@ -0,0 +1,26 @@
+//! Fixtures shared by the tests.
+
+use std::path::{Path, PathBuf};
+
+/// A fresh directory in the temporary directory, it is removed on drop.
+#[derive(Debug)]
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> std::io::Result<Self> {
+        let path = std::env::temp_dir().join(format!("zmake-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&path)?;
+
+        Ok(Self(path))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
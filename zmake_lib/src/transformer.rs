@@ -20,18 +20,29 @@
 use std::path::Path;
 
 use oxc::allocator::Allocator;
-use oxc::codegen::Codegen;
+use oxc::codegen::{Codegen, CodegenOptions};
 use oxc::parser::Parser;
 use oxc::semantic::SemanticBuilder;
 use oxc::span::SourceType;
 use oxc::transformer::{TransformOptions, Transformer};
+use serde::{Deserialize, Serialize};
 
-pub fn transform_typescript(source_code: &str, source_name: &str) -> Result<String, String> {
+/// The ECMAScript version that typescript is transpiled to.
+pub static TRANSFORM_TARGET: &'static str = "es2023";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transpiled {
+    pub code: String,
+    /// The source map v3 json, maps the code to the typescript source.
+    pub source_map: Option<String>,
+}
+
+pub fn transform_typescript(source_code: &str, source_name: &str) -> Result<Transpiled, String> {
     let allocator = Allocator::new();
 
     let path = Path::new(source_name);
 
-    let options = TransformOptions::from_target("es2023")?;
+    let options = TransformOptions::from_target(TRANSFORM_TARGET)?;
 
     let ret = Parser::new(&allocator, &source_code, SourceType::ts()).parse();
 
@@ -63,5 +74,15 @@ pub fn transform_typescript(source_code: &str, source_name: &str) -> Result<Stri
         return Err(err_str);
     }
 
-    return Ok(Codegen::new().build(&program).code);
+    let ret = Codegen::new()
+        .with_options(CodegenOptions {
+            source_map_path: Some(path.to_path_buf()),
+            ..CodegenOptions::default()
+        })
+        .build(&program);
+
+    return Ok(Transpiled {
+        code: ret.code,
+        source_map: ret.map.map(|map| map.to_json_string()),
+    });
 }
@@ -44,6 +44,9 @@ impl crate::proto::transport::transport_server::Transport for TransportServer {
             .map_err(|err| match err {
                 CasError::NotFound(digest) => Status::not_found(digest),
                 CasError::Io(err) => Status::internal(err.to_string()),
+                CasError::ManifestMismatch(err) | CasError::DigestMismatch(err) => {
+                    Status::data_loss(err)
+                }
                 CasError::Internal(err) => Status::internal(err),
             })?;
 
@@ -108,6 +111,7 @@ impl crate::proto::transport::transport_server::Transport for TransportServer {
             .map_err(|err| match err {
                 CasError::Io(err) => Status::internal(err.to_string()),
                 CasError::Internal(err) => Status::internal(err),
+                CasError::DigestMismatch(err) => Status::invalid_argument(err),
                 _ => Status::internal("Unexpected error during store initialization"),
             })?;
 
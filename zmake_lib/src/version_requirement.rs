@@ -0,0 +1,272 @@
+//! Requirements of projects and rule packages on the version of zmake.
+//!
+//! The requirement is a range in the syntax of Cargo(see [`semver::VersionReq`]), like
+//! `^0.1` or `>=0.1.2, <0.3`.
+//!
+//! The ranges written as string literals are checked when the module is loaded, see
+//! [`find_declared_requirements`], so that a project or rule package written for another zmake
+//! fails before any module of it is evaluated. Other ranges are checked when they are evaluated.
+
+use oxc::allocator::Allocator;
+use oxc::ast::ast::{
+    Argument, ExportDefaultDeclarationKind, Expression, ObjectExpression, ObjectPropertyKind,
+    Statement,
+};
+use oxc::parser::Parser;
+use oxc::span::SourceType;
+use semver::{Version, VersionReq};
+use thiserror::Error;
+
+/// The version of running zmake.
+#[::static_init::dynamic(lazy)]
+pub static ZMAKE_VERSION: Version =
+    // cargo has checked that the version of package is a semantic version
+    Version::parse(env!("CARGO_PKG_VERSION")).unwrap_or_else(|_| Version::new(0, 0, 0));
+
+#[derive(Error, Debug)]
+pub enum VersionRequirementError {
+    #[error("`{package}` requires zmake `{range}`, but the range is invalid: {source}")]
+    InvalidRange {
+        package: String,
+        range: String,
+        source: semver::Error,
+    },
+    #[error("`{package}` requires zmake `{range}`, but the running zmake is {running}")]
+    Unsatisfied {
+        package: String,
+        range: String,
+        running: Version,
+    },
+}
+
+/// Check that the running zmake satisfies the range required by the package.
+pub fn check_zmake_version(package: &str, range: &str) -> Result<(), VersionRequirementError> {
+    let requirement =
+        VersionReq::parse(range).map_err(|source| VersionRequirementError::InvalidRange {
+            package: package.to_string(),
+            range: range.to_string(),
+            source,
+        })?;
+
+    if requirement.matches(&ZMAKE_VERSION) {
+        Ok(())
+    } else {
+        Err(VersionRequirementError::Unsatisfied {
+            package: package.to_string(),
+            range: range.to_string(),
+            running: (*ZMAKE_VERSION).clone(),
+        })
+    }
+}
+
+/// A range required by a module, found in its source without evaluating it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeclaredRequirement {
+    /// `None` if the module does not name the package.
+    pub package: Option<String>,
+    pub range: String,
+}
+
+/// Find the ranges that the javascript module requires with string literals:
+///
+/// - `requireZMakeVersion(range, package?)` statements at the top level.
+/// - The `zmake` field of the exported project, `export default { zmake: range }`.
+///
+/// The source that can not be parsed requires nothing, its syntax errors are reported by v8.
+pub fn find_declared_requirements(source: &str) -> Vec<DeclaredRequirement> {
+    let allocator = Allocator::default();
+    let parsed = Parser::new(&allocator, source, SourceType::mjs()).parse();
+
+    if parsed.panicked || !parsed.errors.is_empty() {
+        return Vec::new();
+    }
+
+    parsed
+        .program
+        .body
+        .iter()
+        .filter_map(|statement| match statement {
+            Statement::ExpressionStatement(statement) => required_by_call(&statement.expression),
+            Statement::ExportDefaultDeclaration(export) => match &export.declaration {
+                ExportDefaultDeclarationKind::ObjectExpression(project) => {
+                    required_by_project(project)
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// Check the ranges that the module requires with string literals, `module` names the package
+/// that does not name itself.
+pub fn check_declared_requirements(
+    module: &str,
+    source: &str,
+) -> Result<(), VersionRequirementError> {
+    for requirement in find_declared_requirements(source) {
+        check_zmake_version(
+            requirement.package.as_deref().unwrap_or(module),
+            &requirement.range,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn string_literal(expression: &Expression) -> Option<String> {
+    match expression.without_parentheses() {
+        Expression::StringLiteral(literal) => Some(literal.value.to_string()),
+        _ => None,
+    }
+}
+
+fn required_by_call(expression: &Expression) -> Option<DeclaredRequirement> {
+    let Expression::CallExpression(call) = expression.without_parentheses() else {
+        return None;
+    };
+
+    if !call.callee.is_specific_id("requireZMakeVersion") {
+        return None;
+    }
+
+    let mut arguments = call
+        .arguments
+        .iter()
+        .map(|argument: &Argument| argument.as_expression().and_then(string_literal));
+
+    let range = arguments.next().flatten()?;
+    let package = arguments.next().flatten();
+
+    Some(DeclaredRequirement { package, range })
+}
+
+fn required_by_project(project: &ObjectExpression) -> Option<DeclaredRequirement> {
+    let field = |name: &str| {
+        project
+            .properties
+            .iter()
+            .find_map(|property| match property {
+                ObjectPropertyKind::ObjectProperty(property)
+                    if !property.computed && property.key.is_specific_static_name(name) =>
+                {
+                    string_literal(&property.value)
+                }
+                _ => None,
+            })
+    };
+
+    let range = field("zmake")?;
+    let package = match (field("group"), field("artifact")) {
+        (Some(group), Some(artifact)) => Some(format!("{}:{}", group, artifact)),
+        _ => None,
+    };
+
+    Some(DeclaredRequirement { package, range })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn satisfied_range() -> Result<(), VersionRequirementError> {
+        check_zmake_version("test", &format!("={}", *ZMAKE_VERSION))?;
+        check_zmake_version("test", ">=0.0.1")
+    }
+
+    #[test]
+    fn unsatisfied_range() {
+        assert!(matches!(
+            check_zmake_version("test", ">=99.0.0"),
+            Err(VersionRequirementError::Unsatisfied { ref package, ref range, ref running })
+                if package == "test" && range == ">=99.0.0" && *running == *ZMAKE_VERSION
+        ));
+    }
+
+    #[test]
+    fn invalid_range() {
+        assert!(matches!(
+            check_zmake_version("test", "not a range"),
+            Err(VersionRequirementError::InvalidRange { ref package, ref range, .. })
+                if package == "test" && range == "not a range"
+        ));
+    }
+
+    #[test]
+    fn pre_release_range() -> Result<(), VersionRequirementError> {
+        let running = &*ZMAKE_VERSION;
+
+        // a pre-release of the running version is older than it
+        check_zmake_version("test", &format!(">={}-alpha", running))?;
+
+        // the running version is older than the pre-releases of the next version
+        let next = Version::new(running.major, running.minor, running.patch + 1);
+        assert!(matches!(
+            check_zmake_version("test", &format!(">={}-alpha", next)),
+            Err(VersionRequirementError::Unsatisfied { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn requirements_of_calls() {
+        let requirements = find_declared_requirements(
+            r#"
+            import { requireZMakeVersion } from "zmake:core";
+            requireZMakeVersion(">=0.1");
+            requireZMakeVersion("^0.2", "group:rules");
+            requireZMakeVersion(computed());
+            function nested() { requireZMakeVersion("^9"); }
+            "#,
+        );
+
+        assert_eq!(
+            requirements,
+            vec![
+                DeclaredRequirement {
+                    package: None,
+                    range: ">=0.1".to_string(),
+                },
+                DeclaredRequirement {
+                    package: Some("group:rules".to_string()),
+                    range: "^0.2".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn requirements_of_projects() {
+        assert_eq!(
+            find_declared_requirements(
+                r#"export default { group: "group", artifact: "app", zmake: "^0.1" };"#
+            ),
+            vec![DeclaredRequirement {
+                package: Some("group:app".to_string()),
+                range: "^0.1".to_string(),
+            }]
+        );
+
+        assert_eq!(
+            find_declared_requirements(r#"export default { "zmake": "^0.1" };"#),
+            vec![DeclaredRequirement {
+                package: None,
+                range: "^0.1".to_string(),
+            }]
+        );
+
+        assert!(find_declared_requirements(r#"export default { zmake: range };"#).is_empty());
+        assert!(find_declared_requirements("export default {").is_empty());
+    }
+
+    #[test]
+    fn declared_requirements_name_the_module() -> Result<(), VersionRequirementError> {
+        assert!(matches!(
+            check_declared_requirements("/project.ts", r#"requireZMakeVersion(">=99");"#),
+            Err(VersionRequirementError::Unsatisfied { ref package, .. }) if package == "/project.ts"
+        ));
+
+        check_declared_requirements("/project.ts", "export default {};")
+    }
+}